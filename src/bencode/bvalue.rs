@@ -10,7 +10,7 @@ use crate::torrent::metainfo::TorrentInfo;
 /// - Integers
 /// - Lists
 /// - Dictionaries
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BValue {
     /// An integer value, can be positive or negative
     /// Example: `i42e` represents 42
@@ -24,9 +24,14 @@ pub enum BValue {
     /// Example: `l4:spami42ee` represents ["spam", 42]
     List(Vec<BValue>),
 
-    /// A dictionary mapping strings to BValues
+    /// A dictionary mapping byte-string keys to BValues, ordered by the raw key bytes
     /// Example: `d3:bar4:spam3:fooi42ee` represents {"bar": "spam", "foo": 42}
-    Dict(std::collections::BTreeMap<String, BValue>),
+    Dict(std::collections::BTreeMap<Vec<u8>, BValue>),
+
+    /// An already-encoded value, kept exactly as it appeared in the source and
+    /// written back out verbatim by the encoder
+    /// Example: the bytes of a torrent's `info` dictionary, unknown keys included
+    Raw(Vec<u8>),
 }
 
 impl From<serde_json::Value> for BValue {
@@ -38,7 +43,10 @@ impl From<serde_json::Value> for BValue {
                 BValue::List(arr.into_iter().map(BValue::from).collect())
             }
             serde_json::Value::Object(map) => {
-                let btree = map.into_iter().map(|(k, v)| (k, BValue::from(v))).collect();
+                let btree = map
+                    .into_iter()
+                    .map(|(k, v)| (k.into_bytes(), BValue::from(v)))
+                    .collect();
                 BValue::Dict(btree)
             }
             _ => BValue::String(Vec::new()),
@@ -51,7 +59,7 @@ impl From<BValue> for serde_json::Value {
         match value {
             BValue::Integer(n) => serde_json::Value::Number(n.into()),
             BValue::String(s) => {
-                if s.iter().any(|&b| b < 32 || b > 126) {
+                if s.iter().any(|&b| !(32..=126).contains(&b)) {
                     serde_json::Value::String(hex::encode(&s))
                 } else {
                     let string = String::from_utf8_lossy(&s).into_owned();
@@ -62,23 +70,69 @@ impl From<BValue> for serde_json::Value {
                 serde_json::Value::Array(arr.into_iter().map(|v| v.into()).collect())
             }
             BValue::Dict(map) => {
-                let obj = map.into_iter().map(|(k, v)| (k, v.into())).collect();
+                let obj = map
+                    .into_iter()
+                    .map(|(k, v)| (String::from_utf8_lossy(&k).into_owned(), v.into()))
+                    .collect();
                 serde_json::Value::Object(obj)
             }
+            BValue::Raw(bytes) => raw_to_json(&bytes),
         }
     }
 }
 
 impl From<&TorrentInfo> for BValue {
     fn from(info: &TorrentInfo) -> Self {
+        if let Some(raw) = &info.raw {
+            return BValue::Raw(raw.clone());
+        }
         let mut dict = std::collections::BTreeMap::new();
         dict.insert("name".into(), BValue::String(info.name.as_bytes().to_vec()));
-        dict.insert("length".into(), BValue::Integer(info.length as i64));
+        match &info.files {
+            Some(files) => {
+                let files = files
+                    .iter()
+                    .map(|file| {
+                        let mut entry = std::collections::BTreeMap::new();
+                        entry.insert("length".into(), BValue::Integer(file.length as i64));
+                        entry.insert(
+                            "path".into(),
+                            BValue::List(
+                                file.path
+                                    .iter()
+                                    .map(|p| BValue::String(p.as_bytes().to_vec()))
+                                    .collect(),
+                            ),
+                        );
+                        if let Some(md5sum) = &file.md5sum {
+                            entry.insert(
+                                "md5sum".into(),
+                                BValue::String(md5sum.as_bytes().to_vec()),
+                            );
+                        }
+                        BValue::Dict(entry)
+                    })
+                    .collect();
+                dict.insert("files".into(), BValue::List(files));
+            }
+            None => {
+                dict.insert("length".into(), BValue::Integer(info.length as i64));
+            }
+        }
         dict.insert(
             "piece length".into(),
             BValue::Integer(info.piece_length as i64),
         );
-        dict.insert("pieces".into(), BValue::String(info.pieces.clone()));
+        dict.insert(
+            "pieces".into(),
+            BValue::String(info.pieces.as_bytes().to_vec()),
+        );
+        if info.private {
+            dict.insert("private".into(), BValue::Integer(1));
+        }
+        if let Some(source) = &info.source {
+            dict.insert("source".into(), BValue::String(source.as_bytes().to_vec()));
+        }
         BValue::Dict(dict)
     }
 }
@@ -88,7 +142,7 @@ impl Display for BValue {
         match self {
             BValue::Integer(n) => write!(f, "{}", n),
             BValue::String(s) => {
-                if s.iter().any(|&b| b < 32 || b > 126) {
+                if s.iter().any(|&b| !(32..=126).contains(&b)) {
                     write!(f, "\"{}\"", hex::encode(s))
                 } else {
                     let string = String::from_utf8_lossy(s);
@@ -111,47 +165,21 @@ impl Display for BValue {
                     if i > 0 {
                         write!(f, ",")?;
                     }
-                    write!(f, "\"{}\":{}", key, value)?;
+                    write!(f, "\"{}\":{}", String::from_utf8_lossy(key), value)?;
                 }
                 write!(f, "}}")
             }
+            BValue::Raw(bytes) => match BValue::from_bytes(bytes) {
+                Ok(value) => write!(f, "{}", value),
+                Err(_) => write!(f, "\"{}\"", hex::encode(bytes)),
+            },
         }
     }
 }
 
 impl BValue {
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
-        let mut output = Vec::new();
-        match self {
-            BValue::Integer(n) => {
-                output.extend(b"i");
-                output.extend(n.to_string().as_bytes());
-                output.extend(b"e");
-            }
-            BValue::String(s) => {
-                output.extend(s.len().to_string().as_bytes());
-                output.extend(b":");
-                output.extend(s); // Direct binary extension, no UTF-8 conversion
-            }
-            BValue::List(list) => {
-                output.extend(b"l");
-                for item in list {
-                    output.extend(item.to_bytes()?);
-                }
-                output.extend(b"e");
-            }
-            BValue::Dict(dict) => {
-                output.extend(b"d");
-                for (key, value) in dict {
-                    output.extend(key.len().to_string().as_bytes());
-                    output.extend(b":");
-                    output.extend(key.as_bytes());
-                    output.extend(value.to_bytes()?);
-                }
-                output.extend(b"e");
-            }
-        }
-        Ok(output)
+        super::encoder::Encoder::new().encode_bytes(self)
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
@@ -159,16 +187,11 @@ impl BValue {
         decoder.parse()
     }
 
-    pub fn from_str(s: &str) -> Result<Self> {
-        let mut decoder = crate::bencode::decoder::Decoder::new(s);
-        decoder.parse()
-    }
-
     pub fn to_json(&self) -> serde_json::Value {
         serde_json::Value::from(self)
     }
 
-    pub fn get_dict(&self) -> Result<&std::collections::BTreeMap<String, BValue>> {
+    pub fn get_dict(&self) -> Result<&std::collections::BTreeMap<Vec<u8>, BValue>> {
         match self {
             BValue::Dict(dict) => Ok(dict),
             _ => Err(anyhow::anyhow!("Value is not a dictionary")),
@@ -183,6 +206,14 @@ impl BValue {
     }
 }
 
+/// Converts a raw value to JSON by decoding it, falling back to hex if it is malformed.
+fn raw_to_json(bytes: &[u8]) -> serde_json::Value {
+    match BValue::from_bytes(bytes) {
+        Ok(value) => serde_json::Value::from(value),
+        Err(_) => serde_json::Value::String(hex::encode(bytes)),
+    }
+}
+
 impl TryFrom<&[u8]> for BValue {
     type Error = anyhow::Error;
 
@@ -191,11 +222,20 @@ impl TryFrom<&[u8]> for BValue {
     }
 }
 
+impl std::str::FromStr for BValue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut decoder = crate::bencode::decoder::Decoder::new(s);
+        decoder.parse()
+    }
+}
+
 impl TryFrom<&str> for BValue {
     type Error = anyhow::Error;
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        Self::from_str(s)
+        s.parse()
     }
 }
 
@@ -204,7 +244,7 @@ impl From<&BValue> for serde_json::Value {
         match value {
             BValue::Integer(n) => serde_json::Value::Number((*n).into()),
             BValue::String(s) => {
-                if s.iter().any(|&b| b < 32 || b > 126) {
+                if s.iter().any(|&b| !(32..=126).contains(&b)) {
                     serde_json::Value::String(hex::encode(s))
                 } else {
                     let string = String::from_utf8_lossy(s).into_owned();
@@ -213,9 +253,13 @@ impl From<&BValue> for serde_json::Value {
             }
             BValue::List(arr) => serde_json::Value::Array(arr.iter().map(|v| v.into()).collect()),
             BValue::Dict(map) => {
-                let obj = map.iter().map(|(k, v)| (k.clone(), v.into())).collect();
+                let obj = map
+                    .iter()
+                    .map(|(k, v)| (String::from_utf8_lossy(k).into_owned(), v.into()))
+                    .collect();
                 serde_json::Value::Object(obj)
             }
+            BValue::Raw(bytes) => raw_to_json(bytes),
         }
     }
 }
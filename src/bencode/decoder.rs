@@ -9,6 +9,8 @@
 //! - Lists: `l<bencoded values>e` (e.g. `l4:spami42ee`)
 //! - Dictionaries: `d<bencoded string><bencoded value>e` (e.g. `d3:bar4:spam3:fooi42ee`)
 
+use std::num::IntErrorKind;
+
 use anyhow::Result;
 use tracing::error;
 
@@ -96,12 +98,21 @@ impl<'a> Decoder<'a> {
     }
 
     /// Parses a bencoded integer of the form `i<number>e`.
+    ///
+    /// Integers are held as `i64`, which covers every length and size a torrent
+    /// can realistically carry; anything larger is rejected rather than truncated.
     fn parse_integer(&mut self) -> Result<i64> {
+        let start = self.position;
         self.consume(); // consume 'i'
         let num_bytes = self.consume_until(b'e')?;
-        std::str::from_utf8(num_bytes)
-            .map(|num_str| num_str.parse::<i64>().map_err(Into::into))
-            .map_err(|e| anyhow::anyhow!("Failed to parse integer: {}", e))?
+        let num_str = std::str::from_utf8(num_bytes)
+            .map_err(|e| anyhow::anyhow!("Failed to parse integer: {}", e))?;
+        num_str.parse::<i64>().map_err(|e| match e.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+                anyhow::anyhow!("Integer out of range at byte {}", start)
+            }
+            _ => anyhow::anyhow!("Invalid integer {:?} at byte {}", num_str, start),
+        })
     }
 
     /// Parses a bencoded string of the form `<length>:<contents>`.
@@ -130,7 +141,7 @@ impl<'a> Decoder<'a> {
                 self.consume();
                 return Ok(BValue::List(values));
             }
-            let value: BValue = self.parse_value()?.into();
+            let value = self.parse_value()?;
             values.push(value);
         }
         Err(anyhow::anyhow!("Unterminated list"))
@@ -149,15 +160,16 @@ impl<'a> Decoder<'a> {
             }
 
             let key = match self.parse_value() {
-                Ok(val) => match val.into() {
-                    BValue::String(s) => String::from_utf8(s)?,
+                Ok(val) => match val {
+                    // Keys stay raw bytes, so non-UTF-8 keys decode and sort as encoded
+                    BValue::String(s) => s,
                     _ => return Err(anyhow::anyhow!("Dictionary key must be a string")),
                 },
                 Err(_) => return Err(anyhow::anyhow!("Unterminated dictionary")),
             };
 
-            let value: BValue = match self.parse_value() {
-                Ok(val) => val.into(),
+            let value = match self.parse_value() {
+                Ok(val) => val,
                 Err(_) => return Err(anyhow::anyhow!("Unterminated dictionary")),
             };
 
@@ -253,11 +265,8 @@ mod tests {
         assert_eq!(
             decoder.parse().unwrap(),
             BValue::Dict(std::collections::BTreeMap::from([
-                (
-                    "bar".to_string(),
-                    BValue::String("spam".as_bytes().to_vec())
-                ),
-                ("foo".to_string(), BValue::Integer(42))
+                (b"bar".to_vec(), BValue::String("spam".as_bytes().to_vec())),
+                (b"foo".to_vec(), BValue::Integer(42))
             ]))
         );
 
@@ -275,7 +284,7 @@ mod tests {
             decoder.parse().unwrap(),
             BValue::Dict(std::collections::BTreeMap::from([
                 (
-                    "list".to_string(),
+                    b"list".to_vec(),
                     BValue::List(vec![
                         BValue::String("a".as_bytes().to_vec()),
                         BValue::String("b".as_bytes().to_vec()),
@@ -283,10 +292,10 @@ mod tests {
                     ])
                 ),
                 (
-                    "dict".to_string(),
+                    b"dict".to_vec(),
                     BValue::Dict(std::collections::BTreeMap::from([
-                        ("x".to_string(), BValue::String("y".as_bytes().to_vec())),
-                        ("z".to_string(), BValue::Integer(42))
+                        (b"x".to_vec(), BValue::String("y".as_bytes().to_vec())),
+                        (b"z".to_vec(), BValue::Integer(42))
                     ]))
                 )
             ]))
@@ -302,6 +311,7 @@ mod tests {
             ("d1:a", "Unterminated dictionary"),
             ("d1:ai1e1:b", "Unterminated dictionary"),
             ("di1ei2ee", "Dictionary key must be a string"),
+            ("i4x2e", "Invalid integer \"4x2\" at byte 0"),
         ];
 
         for (input, expected_err) in cases {
@@ -316,4 +326,42 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_integer_out_of_range() {
+        let cases = vec![
+            ("i9999999999999999999999e", 0),
+            ("i-9999999999999999999999e", 0),
+            ("li1ei9223372036854775808ee", 4),
+        ];
+
+        for (input, byte) in cases {
+            let err = Decoder::new(input).parse().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Integer out of range at byte {}", byte),
+                "for input '{}'",
+                input
+            );
+        }
+
+        let mut decoder = Decoder::new("i9223372036854775807e");
+        assert_eq!(decoder.parse().unwrap(), BValue::Integer(i64::MAX));
+    }
+
+    #[test]
+    fn test_parse_dict_with_non_utf8_key() {
+        let input = b"d1:ai1e2:\xff\xfei2e1:zi3ee";
+        let value = Decoder::new_from_bytes(input).parse().unwrap();
+        let dict = value.get_dict().unwrap();
+
+        assert_eq!(dict.get(b"\xff\xfe".as_slice()), Some(&BValue::Integer(2)));
+        // Keys sort by raw bytes, so the non-UTF-8 key comes after "z"
+        let keys: Vec<&[u8]> = dict.keys().map(Vec::as_slice).collect();
+        assert_eq!(keys, vec![b"a".as_slice(), b"z", b"\xff\xfe"]);
+        assert_eq!(
+            crate::bencode::Bencode::encode_value(&value).unwrap(),
+            b"d1:ai1e1:zi3e2:\xff\xfei2ee"
+        );
+    }
 }
@@ -4,6 +4,7 @@ use bvalue::BValue;
 pub mod bvalue;
 mod decoder;
 mod encoder;
+pub mod tokenizer;
 
 /// Bencode decoder
 #[derive(Debug, Clone, Copy)]
@@ -22,10 +23,38 @@ impl Bencode {
             .map_err(|e| anyhow::anyhow!("Failed to decode bencode bytes: {}", e))
     }
 
+    /// Decode a bencoded dictionary, keeping the values of `raw_keys` as
+    /// `BValue::Raw` with their exact source bytes.
+    ///
+    /// Encoding the result reproduces those values byte for byte, including keys
+    /// and orderings a decode/encode round-trip would otherwise normalise away.
+    pub fn decode_with_raw(input: &[u8], raw_keys: &[&str]) -> Result<BValue> {
+        let mut dict = match Self::decode_bytes(input)? {
+            BValue::Dict(dict) => dict,
+            _ => return Err(anyhow::anyhow!("Expected a dictionary")),
+        };
+        for key in raw_keys {
+            if let Some(raw) = tokenizer::dict_value(input, key.as_bytes())? {
+                dict.insert(key.as_bytes().to_vec(), BValue::Raw(raw.to_vec()));
+            }
+        }
+        Ok(BValue::Dict(dict))
+    }
+
+    /// Tokenize bencode bytes without copying byte strings
+    pub fn tokenize(input: &[u8]) -> tokenizer::Tokenizer<'_> {
+        tokenizer::Tokenizer::new(input)
+    }
+
     /// Encode plaintext to bencode string
     pub fn encode(value: &serde_json::Value) -> Result<String> {
         encoder::Encoder::new().encode(value)
     }
+
+    /// Encode a bvalue to raw bencode bytes
+    pub fn encode_value(value: &BValue) -> Result<Vec<u8>> {
+        encoder::Encoder::new().encode_bytes(value)
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -62,4 +91,35 @@ mod tests {
             assert_eq!(input, encoded);
         }
     }
+
+    #[test]
+    fn test_encode_value_roundtrip() {
+        let torrent = std::fs::read("sample.torrent").unwrap();
+        let decoded = Bencode::decode_bytes(&torrent).unwrap();
+
+        assert_eq!(Bencode::encode_value(&decoded).unwrap(), torrent);
+        assert_eq!(
+            Bencode::decode_bytes(&Bencode::encode_value(&decoded).unwrap()).unwrap(),
+            decoded
+        );
+    }
+
+    #[test]
+    fn test_decode_with_raw_preserves_unknown_keys() {
+        // The info dictionary has an unknown key and its keys out of order, so a
+        // plain decode/encode would change its bytes
+        let torrent =
+            b"d8:announce3:url4:infod4:name1:x6:lengthi1e7:x-extrali1e1:ae12:piece lengthi1eee";
+
+        let decoded = Bencode::decode_with_raw(torrent, &["info"]).unwrap();
+        assert!(matches!(
+            decoded.get_dict().unwrap().get(b"info".as_slice()),
+            Some(BValue::Raw(_))
+        ));
+        assert_eq!(Bencode::encode_value(&decoded).unwrap(), torrent);
+
+        let sample = std::fs::read("sample.torrent").unwrap();
+        let decoded = Bencode::decode_with_raw(&sample, &["info"]).unwrap();
+        assert_eq!(Bencode::encode_value(&decoded).unwrap(), sample);
+    }
 }
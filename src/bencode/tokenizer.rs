@@ -0,0 +1,228 @@
+//! Zero-copy bencode tokenizer.
+//!
+//! Where the decoder builds an owned `BValue` tree, the tokenizer walks the input and
+//! yields one token at a time, with byte strings borrowed straight from the input.
+//! This avoids copying large values such as the `pieces` blob of a torrent with
+//! hundreds of thousands of pieces, and lets callers take the raw encoded bytes of a
+//! value (e.g. the `info` dictionary for hashing) without re-encoding it.
+
+use anyhow::Result;
+
+/// A single lexical element of bencoded input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token<'a> {
+    /// An integer, `i<number>e`
+    Integer(i64),
+    /// A byte string, borrowed from the input
+    Bytes(&'a [u8]),
+    /// The start of a list, `l`
+    ListStart,
+    /// The start of a dictionary, `d`
+    DictStart,
+    /// The end of the innermost open list or dictionary, `e`
+    End,
+}
+
+/// Returned when the input ends part-way through a value, so callers can tell
+/// truncated input from malformed input.
+#[derive(Debug, thiserror::Error)]
+#[error("Unexpected end of input")]
+pub struct UnexpectedEof;
+
+/// Iterates over the tokens of bencoded input without copying.
+#[derive(Debug)]
+pub struct Tokenizer<'a> {
+    input: &'a [u8],
+    position: usize,
+    depth: usize,
+    failed: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Creates a tokenizer positioned at the start of `input`.
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            position: 0,
+            depth: 0,
+            failed: false,
+        }
+    }
+
+    /// Returns the offset of the next unread byte.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Reads the next token, or `None` at the end of the input.
+    pub fn next_token(&mut self) -> Result<Option<Token<'a>>> {
+        let Some(&b) = self.input.get(self.position) else {
+            if self.depth > 0 {
+                return Err(UnexpectedEof.into());
+            }
+            return Ok(None);
+        };
+
+        let token = match b {
+            b'i' => {
+                let end = self.find_byte(b'e', self.position + 1)?;
+                let number = std::str::from_utf8(&self.input[self.position + 1..end])?
+                    .parse::<i64>()
+                    .map_err(|e| anyhow::anyhow!("Failed to parse integer: {}", e))?;
+                self.position = end + 1;
+                Token::Integer(number)
+            }
+            b'l' | b'd' => {
+                self.position += 1;
+                self.depth += 1;
+                if b == b'l' {
+                    Token::ListStart
+                } else {
+                    Token::DictStart
+                }
+            }
+            b'e' => {
+                if self.depth == 0 {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected end marker at position {}",
+                        self.position
+                    ));
+                }
+                self.position += 1;
+                self.depth -= 1;
+                Token::End
+            }
+            b'0'..=b'9' => {
+                let colon = self.find_byte(b':', self.position)?;
+                let len =
+                    std::str::from_utf8(&self.input[self.position..colon])?.parse::<usize>()?;
+                let start = colon + 1;
+                let bytes = start
+                    .checked_add(len)
+                    .and_then(|end| self.input.get(start..end))
+                    .ok_or(UnexpectedEof)?;
+                self.position = start + len;
+                Token::Bytes(bytes)
+            }
+            c => return Err(anyhow::anyhow!("Unhandled encoded value: {}", c)),
+        };
+        Ok(Some(token))
+    }
+
+    /// Skips over the next complete value and returns its raw encoded bytes.
+    pub fn next_raw_value(&mut self) -> Result<&'a [u8]> {
+        let start = self.position;
+        let mut open = 0usize;
+        loop {
+            match self.next_token()? {
+                Some(Token::ListStart | Token::DictStart) => open += 1,
+                Some(Token::End) if open > 0 => open -= 1,
+                Some(Token::End) => return Err(anyhow::anyhow!("Expected a value")),
+                Some(_) => {}
+                None => return Err(UnexpectedEof.into()),
+            }
+            if open == 0 {
+                return Ok(&self.input[start..self.position]);
+            }
+        }
+    }
+
+    /// Returns the index of the first `delimiter` at or after `from`.
+    fn find_byte(&self, delimiter: u8, from: usize) -> Result<usize> {
+        self.input
+            .get(from..)
+            .and_then(|rest| rest.iter().position(|&b| b == delimiter))
+            .map(|offset| from + offset)
+            .ok_or_else(|| UnexpectedEof.into())
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let token = self.next_token().transpose();
+        self.failed = matches!(token, Some(Err(_)));
+        token
+    }
+}
+
+/// Returns the raw encoded bytes of `key`'s value in the top-level dictionary of
+/// `input`, or `None` if the key is absent.
+pub fn dict_value<'a>(input: &'a [u8], key: &[u8]) -> Result<Option<&'a [u8]>> {
+    let mut tokenizer = Tokenizer::new(input);
+    if tokenizer.next_token()? != Some(Token::DictStart) {
+        return Err(anyhow::anyhow!("Expected a dictionary"));
+    }
+    loop {
+        match tokenizer.next_token()? {
+            Some(Token::End) => return Ok(None),
+            Some(Token::Bytes(k)) => {
+                let value = tokenizer.next_raw_value()?;
+                if k == key {
+                    return Ok(Some(value));
+                }
+            }
+            _ => return Err(anyhow::anyhow!("Dictionary key must be a string")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_nested_values() {
+        let tokens: Vec<Token> = Tokenizer::new(b"d3:bar4:spam3:fooli42ei-7eee")
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::DictStart,
+                Token::Bytes(b"bar"),
+                Token::Bytes(b"spam"),
+                Token::Bytes(b"foo"),
+                Token::ListStart,
+                Token::Integer(42),
+                Token::Integer(-7),
+                Token::End,
+                Token::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_byte_strings_borrow_from_input() {
+        let input = b"5:hello";
+        let Some(Ok(Token::Bytes(bytes))) = Tokenizer::new(input).next() else {
+            panic!("expected a byte string");
+        };
+        assert!(std::ptr::eq(bytes.as_ptr(), input[2..].as_ptr()));
+    }
+
+    #[test]
+    fn test_dict_value_returns_raw_bytes() {
+        let input = b"d1:ai1e4:infod4:name1:xe1:zl1:yee";
+
+        assert_eq!(
+            dict_value(input, b"info").unwrap(),
+            Some(&b"d4:name1:xe"[..])
+        );
+        assert_eq!(dict_value(input, b"z").unwrap(), Some(&b"l1:ye"[..]));
+        assert_eq!(dict_value(input, b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_error_cases() {
+        for input in [&b"d1:a"[..], b"5:abc", b"i42", b"e", b"x"] {
+            let result: Result<Vec<Token>> = Tokenizer::new(input).collect();
+            assert!(result.is_err(), "expected {:?} to fail", input);
+        }
+    }
+}
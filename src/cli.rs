@@ -1,13 +1,53 @@
-use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+
+use clap::{Parser, Subcommand, ValueEnum};
 
 /// Command line arguments for the bittorrent client implementation
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct Args {
+    /// How log lines are formatted
+    #[arg(long, value_enum, default_value_t = LogFormat::Full, global = true)]
+    pub log_format: LogFormat,
     #[command(subcommand)]
     pub command: Command,
 }
 
+/// Output formats for log lines
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Single-line output with span context
+    Full,
+    /// Multi-line, human-readable output
+    Pretty,
+    /// Abbreviated single-line output
+    Compact,
+    /// Newline-delimited JSON, one object per event
+    Json,
+}
+
+/// Encodings the `decode` command's argument may be given in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum InputEncoding {
+    /// The bencoded text itself
+    Raw,
+    /// Hex digits of the bencoded bytes
+    Hex,
+    /// Standard base64 of the bencoded bytes, padded or not
+    Base64,
+}
+
+/// Encodings the `info` command prints the info hash in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum InfoHashFormat {
+    /// 40 hex characters
+    Hex,
+    /// 32 base32 characters, as used by older magnet links
+    Base32,
+    /// Both forms
+    Both,
+}
+
 /// Available commands for the bittorrent client implementation
 #[derive(Subcommand, Debug)]
 pub enum Command {
@@ -15,6 +55,10 @@ pub enum Command {
     Decode {
         /// The bencoded string to decode
         input: String,
+        /// How the input is encoded; binary data such as a captured tracker
+        /// response can be passed as hex or base64
+        #[arg(long, value_enum, default_value_t = InputEncoding::Raw)]
+        input_encoding: InputEncoding,
     },
     /// Encode a string to a bencoded string
     Encode {
@@ -23,17 +67,35 @@ pub enum Command {
     },
     /// Info about a torrent file
     Info {
-        /// The path to the torrent file
-        path: String,
+        /// Paths to one or more torrent files, or `-` to read one from stdin
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Print the torrent details as JSON
+        #[arg(long)]
+        json: bool,
+        /// How the info hash is printed
+        #[arg(long, value_enum, default_value_t = InfoHashFormat::Hex)]
+        infohash_format: InfoHashFormat,
     },
     /// Peers for the torrent
     Peers {
-        /// The path to the torrent file
-        path: String,
+        /// Paths to one or more torrent files, or `-` to read one from stdin; their
+        /// trackers are asked concurrently
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Print only the first N peers the tracker returns
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Also handshake with each peer and print the peer IDs of those that answer
+        #[arg(long)]
+        connect: bool,
+        /// Seconds to wait for each handshake with `--connect`
+        #[arg(long, default_value_t = 5)]
+        connect_timeout: u64,
     },
     /// Handshake with a peer
     Handshake {
-        /// The path to the torrent file
+        /// The path to the torrent file, or `-` to read it from stdin
         path: String,
         /// The peer address in format IP:PORT
         peer: String,
@@ -41,22 +103,57 @@ pub enum Command {
     /// Download a piece from the torrent
     #[command(name = "download_piece")]
     DownloadPiece {
-        /// The path to the torrent file
+        /// The path to the torrent file, or `-` to read it from stdin
         path: String,
         /// The index of the piece to download
         piece_index: usize,
         /// The output file path
         #[arg(short)]
         output: String,
+        /// Download from these peers, e.g. `10.0.0.2:6881,10.0.0.3:6881`, instead of
+        /// asking the tracker or DHT
+        #[arg(long, value_delimiter = ',')]
+        peers: Vec<SocketAddr>,
     },
     /// Download the complete torrent
     Download {
-        /// The output file path
+        /// The output file path, or an existing directory to save the torrent into
         #[arg(short)]
         output: String,
 
-        /// The path to the torrent file
+        /// Overwrite the output if it already exists
+        #[arg(long)]
+        force: bool,
+
+        /// After downloading, check each file against its `md5sum` in the torrent
+        #[arg(long)]
+        verify_md5: bool,
+
+        /// The path to the torrent file, or `-` to read it from stdin
+        path: String,
+
+        /// Only download these pieces, e.g. `0,1,5-10`; the rest of the file is left
+        /// zeroed. Single-file torrents only
+        #[arg(long, conflicts_with = "verify_md5")]
+        pieces: Option<String>,
+        /// Download from these peers, e.g. `10.0.0.2:6881,10.0.0.3:6881`, instead of
+        /// asking the tracker or DHT
+        #[arg(long, value_delimiter = ',')]
+        peers: Vec<SocketAddr>,
+    },
+    /// Verify an existing file against the torrent's piece hashes
+    Verify {
+        /// The path to the torrent file, or `-` to read it from stdin
+        path: String,
+        /// The path to the downloaded data to check
+        data: String,
+    },
+    /// Re-download only the pieces of an existing file that fail verification
+    Repair {
+        /// The path to the torrent file, or `-` to read it from stdin
         path: String,
+        /// The path to the downloaded data to repair in place
+        data: String,
     },
     /// Parse a magnet link
     #[command(name = "magnet_parse")]
@@ -64,12 +161,46 @@ pub enum Command {
         /// The magnet link
         magnet_link: String,
     },
+    /// Build a magnet link for a torrent file
+    #[command(name = "magnet_create")]
+    MagnetCreate {
+        /// The path to the torrent file, or `-` to read it from stdin
+        path: String,
+    },
     /// Handshake with a peer from magnet link
     #[command(name = "magnet_handshake")]
     MagnetHandshake {
         /// The magnet link
         magnet_link: String,
     },
+    /// Fetch the metadata for a magnet link and save it as a torrent file
+    #[command(name = "magnet_save")]
+    MagnetSave {
+        /// The path to write the torrent file to
+        #[arg(short)]
+        output: String,
+
+        /// The magnet link
+        magnet_link: String,
+    },
+    /// Fetch the metadata for a magnet link and download the complete torrent
+    #[command(name = "magnet_download")]
+    MagnetDownload {
+        /// The output file path, or an existing directory to save the torrent into
+        #[arg(short)]
+        output: String,
+
+        /// Overwrite the output if it already exists
+        #[arg(long)]
+        force: bool,
+
+        /// After downloading, check each file against its `md5sum` in the torrent
+        #[arg(long)]
+        verify_md5: bool,
+
+        /// The magnet link
+        magnet_link: String,
+    },
 }
 
 impl Args {
@@ -0,0 +1,67 @@
+//! A BitTorrent client library.
+//!
+//! The `bittorrent-starter-rust` binary is a thin CLI over this crate; everything it
+//! does is available to other programs too:
+//! - `bencode`: Encoding and decoding bencoded values
+//! - `torrent`: Torrent files and magnet links, trackers, the DHT, the peer wire
+//!   protocol, downloading and seeding
+//! - `utils`: Peer ID helpers and piece range parsing
+//!
+//! The types most programs need are re-exported at the crate root. `Downloader`
+//! is the entry point for fetching a torrent: it finds peers for a
+//! `TorrentMetainfo` or `MagnetLink` and downloads the data with a
+//! `DownloadManager`, which can also be used directly for finer control.
+//!
+//! ```
+//! use bittorrent_starter_rust::{Downloader, PeerConfig, Seeder, TorrentInfo, TorrentMetainfo};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let data = vec![7u8; 40_000];
+//! let info = TorrentInfo::from_data("example.bin", 16 * 1024, &data);
+//! let torrent = TorrentMetainfo::new(None, info.clone());
+//!
+//! // A local seeder stands in for the swarm
+//! let peer_config = PeerConfig {
+//!     info_hash: torrent.info_hash()?,
+//!     ..Default::default()
+//! };
+//! let seeder = Seeder::bind("127.0.0.1:0".parse()?, info, data.clone(), peer_config).await?;
+//! let addr = seeder.local_addr()?;
+//! tokio::spawn(seeder.run());
+//!
+//! let downloader = Downloader::with_peers(torrent, vec![addr])?;
+//! let dir = tempfile::tempdir()?;
+//! let output = dir.path().join("example.bin");
+//! let summary = downloader.download_all(output.to_str().unwrap()).await?;
+//!
+//! assert_eq!(std::fs::read(&output)?, data);
+//! assert_eq!(summary.total_pieces, 3);
+//! # Ok(())
+//! # }
+//! ```
+
+use once_cell::sync::Lazy;
+
+pub mod bencode;
+pub mod torrent;
+pub mod utils;
+
+pub use bencode::{bvalue::BValue, Bencode};
+pub use torrent::{
+    download::{
+        DownloadCancelled, DownloadConfig, DownloadHandle, DownloadManager, DownloadSummary,
+        Downloader, PiecePriority, ProgressEvent,
+    },
+    magnet_link::MagnetLink,
+    metainfo::{FileEntry, TorrentInfo, TorrentMetainfo},
+    peer::{InfoHash, Peer, PeerConfig, PeerError, PeerId},
+    seeder::Seeder,
+    storage::{FileStore, MemoryStore, PieceStore},
+    tracker::{Tracker, TrackerConfig, TrackerResponse},
+};
+
+/// Protocol string sent at the start of every peer handshake
+pub const PROTOCOL: &str = "BitTorrent protocol";
+/// Peer ID this client identifies itself with, generated once per process
+pub static PEER_ID: Lazy<PeerId> = Lazy::new(utils::generate_peer_id);
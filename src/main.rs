@@ -1,74 +1,51 @@
 use anyhow::Result;
-use bencode::Bencode;
-use once_cell::sync::Lazy;
-use torrent::{
-    download::Downloader,
-    metainfo::TorrentMetainfo,
-    peer::{PeerConfig, PeerId},
+use bittorrent_starter_rust::{
+    torrent::{
+        self,
+        download::{self, output_destination},
+    },
+    utils::{self, peer_id_to_string, serialize_peer_id},
+    BValue, Bencode, Downloader, PeerConfig, TorrentMetainfo,
 };
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
 use tracing::info;
-use utils::{peer_id_to_string, serialize_peer_id};
 
-pub mod bencode;
-pub mod cli;
-pub mod torrent;
-pub mod utils;
-
-pub const PROTOCOL: &str = "BitTorrent protocol";
-pub static PEER_ID: Lazy<PeerId> = Lazy::new(utils::generate_peer_id);
+mod cli;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
-
     let args = cli::Args::parse();
+    init_logging(args.log_format);
 
     match args.command {
-        cli::Command::Decode { input } => {
+        cli::Command::Decode {
+            input,
+            input_encoding,
+        } => {
             info!("Decoding input: {}", input);
-            let decoded_value = Bencode::decode(&input)?;
-            println!("{}", decoded_value);
+            println!("{}", decode_input(&input, input_encoding)?);
         }
         cli::Command::Encode { input } => {
             info!("Encoding input: {}", input);
             let encoded_value = Bencode::encode(&serde_json::Value::from(input))?;
             println!("{}", encoded_value);
         }
-        cli::Command::Info { path } => {
-            info!("Getting info about torrent file: {}", path);
-            let bytes = std::fs::read(path)?;
-            let torrent_info = TorrentMetainfo::from_bytes(&bytes)?;
-            println!("{}", torrent_info);
-        }
-        cli::Command::Peers { path } => {
-            info!("Getting peers for torrent file: {}", path);
-            let bytes = std::fs::read(path)?;
-            let torrent = TorrentMetainfo::from_bytes(&bytes)?;
-            let announce = torrent
-                .announce
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("No tracker URL"))?;
-            info!("Tracker URL: {}", announce);
-            let info_hash = torrent.info_hash()?;
-            info!("Info Hash: {}", hex::encode(info_hash));
-
-            let peers = torrent::tracker::get_peers(
-                announce,
-                info_hash,
-                torrent.info.as_ref().map(|i| i.length as u64),
-                Some(torrent::tracker::TrackerConfig::default()),
-            )
-            .await?;
-
-            for peer in peers {
-                println!("{}", peer);
-            }
-        }
+        cli::Command::Info {
+            paths,
+            json,
+            infohash_format,
+        } => println!("{}", info_report(&paths, json, infohash_format)?),
+        cli::Command::Peers {
+            paths,
+            limit,
+            connect,
+            connect_timeout,
+        } => handle_peers(&paths, limit, connect, connect_timeout).await?,
         cli::Command::Handshake { path, peer } => {
             info!("Performing handshake with peer: {}", peer);
-            let bytes = std::fs::read(path)?;
+            let bytes = utils::read_torrent_source(&path)?;
             let torrent = TorrentMetainfo::from_bytes(&bytes)?;
             let info_hash = torrent.info_hash()?;
             let peer_config = PeerConfig {
@@ -87,36 +64,422 @@ async fn main() -> Result<()> {
             output,
             path,
             piece_index,
-        } => handle_download_piece(output, path, piece_index).await?,
-        cli::Command::Download { output, path } => handle_download(output, path).await?,
+            peers,
+        } => handle_download_piece(output, path, piece_index, peers).await?,
+        cli::Command::Download {
+            output,
+            force,
+            verify_md5,
+            path,
+            pieces,
+            peers,
+        } => handle_download(output, force, verify_md5, path, pieces, peers).await?,
+        cli::Command::Verify { path, data } => handle_verify(path, data).await?,
+        cli::Command::Repair { path, data } => handle_repair(path, data).await?,
         cli::Command::MagnetParse { magnet_link } => handle_magnet_parse(magnet_link).await?,
+        cli::Command::MagnetCreate { path } => {
+            let bytes = utils::read_torrent_source(&path)?;
+            let torrent = TorrentMetainfo::from_bytes(&bytes)?;
+            let magnet = torrent::magnet_link::MagnetLink::from_torrent(&torrent)?;
+            println!("{}", magnet.to_uri());
+        }
         cli::Command::MagnetHandshake { magnet_link } => {
             handle_magnet_handshake(magnet_link).await?
         }
+        cli::Command::MagnetSave {
+            output,
+            magnet_link,
+        } => handle_magnet_save(output, magnet_link).await?,
+        cli::Command::MagnetDownload {
+            output,
+            force,
+            verify_md5,
+            magnet_link,
+        } => handle_magnet_download(output, force, verify_md5, magnet_link).await?,
+    }
+
+    Ok(())
+}
+
+/// Installs the global tracing subscriber, filtered by `RUST_LOG`.
+fn init_logging(format: cli::LogFormat) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+    match format {
+        cli::LogFormat::Full => builder.init(),
+        cli::LogFormat::Pretty => builder.pretty().init(),
+        cli::LogFormat::Compact => builder.compact().init(),
+        cli::LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Bencode-decodes `input`, first turning it into bytes as `encoding` says.
+fn decode_input(input: &str, encoding: cli::InputEncoding) -> Result<BValue> {
+    match encoding {
+        cli::InputEncoding::Raw => Bencode::decode(input),
+        cli::InputEncoding::Hex => Bencode::decode_bytes(
+            &hex::decode(input.trim()).map_err(|e| anyhow::anyhow!("Invalid hex input: {}", e))?,
+        ),
+        cli::InputEncoding::Base64 => Bencode::decode_bytes(&utils::decode_base64(input)?),
+    }
+}
+
+/// Describes the torrents at `paths`, as text or as JSON.
+///
+/// With several paths each torrent's text gets a `==> path <==` header, and the JSON
+/// is an array with each torrent's `path` added.
+fn info_report(
+    paths: &[String],
+    json: bool,
+    infohash_format: cli::InfoHashFormat,
+) -> Result<String> {
+    if json {
+        let mut reports = Vec::new();
+        for path in paths {
+            let mut report = info_json(path, infohash_format)
+                .map_err(|e| e.context(format!("Failed to read {}", path)))?;
+            if paths.len() > 1 {
+                report["path"] = path.as_str().into();
+            }
+            reports.push(report);
+        }
+        return Ok(match reports.len() {
+            1 => serde_json::to_string_pretty(&reports[0])?,
+            _ => serde_json::to_string_pretty(&reports)?,
+        });
+    }
+
+    let mut reports = Vec::new();
+    for path in paths {
+        let report = info_text(path, infohash_format)
+            .map_err(|e| e.context(format!("Failed to read {}", path)))?;
+        reports.push(match paths.len() {
+            1 => report,
+            _ => format!("==> {} <==\n{}", path, report),
+        });
+    }
+    Ok(reports.join("\n"))
+}
+
+/// Describes the torrent at `path` as JSON.
+fn info_json(path: &str, infohash_format: cli::InfoHashFormat) -> Result<serde_json::Value> {
+    info!("Getting info about torrent file: {}", path);
+    let torrent = TorrentMetainfo::from_bytes(&utils::read_torrent_source(path)?)?;
+    let base32 = torrent::magnet_link::info_hash_to_base32(&torrent.info_hash()?);
+    let mut json = torrent.to_json();
+    match infohash_format {
+        cli::InfoHashFormat::Hex => {}
+        cli::InfoHashFormat::Base32 => {
+            json["info_hash_base32"] = base32.into();
+            if let Some(json) = json.as_object_mut() {
+                json.remove("info_hash");
+            }
+        }
+        cli::InfoHashFormat::Both => json["info_hash_base32"] = base32.into(),
     }
+    Ok(json)
+}
 
+/// Describes the torrent at `path` as text.
+fn info_text(path: &str, infohash_format: cli::InfoHashFormat) -> Result<String> {
+    info!("Getting info about torrent file: {}", path);
+    let torrent = TorrentMetainfo::from_bytes(&utils::read_torrent_source(path)?)?;
+    let info_hash = torrent.info_hash()?;
+    let base32 = torrent::magnet_link::info_hash_to_base32(&info_hash);
+    let hex = hex::encode(info_hash);
+    let hash_lines = match infohash_format {
+        cli::InfoHashFormat::Hex => format!("Info Hash: {}", hex),
+        cli::InfoHashFormat::Base32 => format!("Info Hash: {}", base32),
+        cli::InfoHashFormat::Both => format!("Info Hash: {}\nInfo Hash (base32): {}", hex, base32),
+    };
+    let text = torrent.display_with_info_hash(&hash_lines).to_string();
+    Ok(text)
+}
+
+/// Prints the peers of each torrent at `paths`, announcing to their trackers
+/// concurrently.
+///
+/// With several paths each torrent's peers get a `==> path <==` header, and a
+/// torrent that fails doesn't stop the others from being printed.
+async fn handle_peers(
+    paths: &[String],
+    limit: Option<usize>,
+    connect: bool,
+    connect_timeout: u64,
+) -> Result<()> {
+    let reports = futures::future::join_all(
+        paths
+            .iter()
+            .map(|path| peers_report(path, limit, connect, connect_timeout)),
+    )
+    .await;
+    if paths.len() == 1 {
+        for report in reports {
+            print!("{}", report?);
+        }
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for (i, (path, report)) in paths.iter().zip(reports).enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("==> {} <==", path);
+        match report {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                println!("Error: {:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} torrents failed",
+            failed,
+            paths.len()
+        ));
+    }
     Ok(())
 }
 
-async fn handle_download_piece(output: String, path: String, piece_index: usize) -> Result<()> {
-    let bytes = std::fs::read(path)?;
+/// Announces the torrent at `path` and lists the peers its tracker returns, one
+/// per line, optionally probing each with a handshake.
+async fn peers_report(
+    path: &str,
+    limit: Option<usize>,
+    connect: bool,
+    connect_timeout: u64,
+) -> Result<String> {
+    use std::fmt::Write;
+
+    info!("Getting peers for torrent file: {}", path);
+    let bytes = utils::read_torrent_source(path)?;
     let torrent = TorrentMetainfo::from_bytes(&bytes)?;
+    let announce = torrent
+        .announce
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No tracker URL"))?;
+    info!("Tracker URL: {}", announce);
+    let info_hash = torrent.info_hash()?;
+    info!("Info Hash: {}", hex::encode(info_hash));
 
-    let downloader = Downloader::new(torrent).await?;
-    let piece_data = downloader.download_piece(piece_index).await?;
+    let response = torrent::tracker::announce(
+        announce,
+        info_hash,
+        torrent.info.as_ref().map(|i| i.length as u64),
+        Some(torrent::tracker::TrackerConfig::default()),
+    )
+    .await?;
+
+    let mut report = String::new();
+    if let (Some(seeders), Some(leechers)) = (response.complete, response.incomplete) {
+        writeln!(report, "Seeders: {}, Leechers: {}", seeders, leechers)?;
+    }
+    let mut peers = response.peers;
+    if let Some(limit) = limit {
+        peers.truncate(limit);
+    }
+    if !connect {
+        for peer in peers {
+            writeln!(report, "{}", peer)?;
+        }
+        return Ok(report);
+    }
+
+    let addrs: Vec<SocketAddr> = peers
+        .iter()
+        .map(|peer| SocketAddr::new(peer.ip.into(), peer.port))
+        .collect();
+    let peer_config = PeerConfig {
+        info_hash,
+        ..Default::default()
+    };
+    let results =
+        torrent::peer::probe_peers(&addrs, peer_config, Duration::from_secs(connect_timeout)).await;
+    let reachable = results.iter().filter(|(_, r)| r.is_ok()).count();
+    for (addr, result) in results {
+        match result {
+            Ok(peer_id) => writeln!(report, "{} {}", addr, serialize_peer_id(&peer_id))?,
+            Err(e) => writeln!(report, "{} failed: {}", addr, e)?,
+        }
+    }
+    writeln!(report, "{} of {} peers reachable", reachable, addrs.len())?;
+    Ok(report)
+}
+
+/// Creates a downloader for `torrent` that uses `peers` if any were given, and
+/// otherwise finds peers through the tracker or DHT.
+async fn downloader_for(torrent: TorrentMetainfo, peers: Vec<SocketAddr>) -> Result<Downloader> {
+    if peers.is_empty() {
+        Downloader::new(torrent).await
+    } else {
+        Downloader::with_peers(torrent, peers)
+    }
+}
+
+async fn handle_download_piece(
+    output: String,
+    path: String,
+    piece_index: usize,
+    peers: Vec<SocketAddr>,
+) -> Result<()> {
+    let bytes = utils::read_torrent_source(&path)?;
+    let torrent = TorrentMetainfo::from_bytes(&bytes)?;
+
+    let downloader = downloader_for(torrent, peers).await?;
+    let piece_data = downloader.download_piece_from_any(piece_index).await?;
 
     tokio::fs::write(output, piece_data).await?;
     info!("Successfully downloaded and verified piece {}", piece_index);
     Ok(())
 }
 
-async fn handle_download(output: String, path: String) -> Result<()> {
-    let bytes = std::fs::read(path)?;
+async fn handle_download(
+    output: String,
+    force: bool,
+    verify_md5: bool,
+    path: String,
+    pieces: Option<String>,
+    peers: Vec<SocketAddr>,
+) -> Result<()> {
+    let bytes = utils::read_torrent_source(&path)?;
     let torrent = TorrentMetainfo::from_bytes(&bytes)?;
+    let info = torrent
+        .info
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No info dictionary"))?;
+    let pieces = pieces
+        .as_deref()
+        .map(|spec| utils::parse_piece_ranges(spec, info.total_pieces()))
+        .transpose()?;
+    let output = output_destination(Path::new(&output), info, force)?;
+    let output = output.to_string_lossy();
 
-    let downloader = Downloader::new(torrent).await?;
-    downloader.download_all(&output).await?;
+    let downloader = downloader_for(torrent, peers).await?;
+    match pieces {
+        Some(pieces) => {
+            cancel_on_ctrl_c(&downloader);
+            downloader.download_selected(&output, &pieces).await
+        }
+        None => run_download(downloader, &output, verify_md5).await,
+    }
+}
+
+async fn handle_magnet_save(output: String, magnet_link: String) -> Result<()> {
+    let magnet = torrent::magnet_link::MagnetLink::parse(&magnet_link)?;
+    // Torrent files without an announce URL aren't accepted when loaded again
+    if magnet.tracker.is_none() {
+        return Err(anyhow::anyhow!(
+            "Magnet link has no tracker (tr) to use as the torrent's announce URL"
+        ));
+    }
+
+    let downloader = Downloader::from_magnet(&magnet).await?;
+    downloader.save_torrent(Path::new(&output)).await?;
+    println!("Saved torrent to {}", output);
+    Ok(())
+}
+
+async fn handle_magnet_download(
+    output: String,
+    force: bool,
+    verify_md5: bool,
+    magnet_link: String,
+) -> Result<()> {
+    let magnet = torrent::magnet_link::MagnetLink::parse(&magnet_link)?;
+
+    let downloader = Downloader::from_magnet(&magnet).await?;
+    let info = downloader
+        .torrent()
+        .info
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No info dictionary"))?;
+    let output = output_destination(Path::new(&output), info, force)?;
+    run_download(downloader, &output.to_string_lossy(), verify_md5).await
+}
+
+/// Cancels `downloader`'s download when ctrl-c is pressed.
+fn cancel_on_ctrl_c(downloader: &Downloader) {
+    let cancel = downloader.cancellation_token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Interrupted, stopping download");
+            cancel.cancel();
+        }
+    });
+}
 
+/// Downloads every piece to `output`, stopping cleanly on ctrl-c, then optionally
+/// checks the files' MD5 sums.
+async fn run_download(downloader: Downloader, output: &str, verify_md5: bool) -> Result<()> {
+    cancel_on_ctrl_c(&downloader);
+    let summary = downloader.download_all(output).await?;
+
+    if verify_md5 {
+        if let Some(info) = &downloader.torrent().info {
+            download::verify_md5(Path::new(output), info).await?;
+        }
+    }
+    print_summary(&summary);
+    Ok(())
+}
+
+/// Prints how many pieces each peer contributed, busiest peer first.
+fn print_summary(summary: &download::DownloadSummary) {
+    println!(
+        "Downloaded {} pieces in {:.1?}",
+        summary.total_pieces, summary.elapsed
+    );
+    let mut peers: Vec<_> = summary.per_peer.iter().collect();
+    peers.sort_by_key(|(addr, state)| (std::cmp::Reverse(state.successful_pieces), **addr));
+    for (addr, state) in peers {
+        println!(
+            "  {}: {} pieces, {} failed",
+            addr, state.successful_pieces, state.failed_pieces
+        );
+    }
+}
+
+async fn handle_verify(path: String, data: String) -> Result<()> {
+    let bytes = utils::read_torrent_source(&path)?;
+    let torrent = TorrentMetainfo::from_bytes(&bytes)?;
+    let info = torrent
+        .info
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No info dictionary"))?;
+
+    let data = tokio::fs::read(data).await?;
+    let results = info.verify_data(&data);
+    for (piece_index, valid) in results.iter().enumerate() {
+        if !valid {
+            println!("Piece {} failed verification", piece_index);
+        }
+    }
+
+    let valid = results.iter().filter(|&&v| v).count();
+    println!("{}/{} pieces valid", valid, results.len());
+    if valid != results.len() {
+        return Err(anyhow::anyhow!(
+            "{} piece(s) failed verification",
+            results.len() - valid
+        ));
+    }
+    Ok(())
+}
+
+async fn handle_repair(path: String, data: String) -> Result<()> {
+    let bytes = utils::read_torrent_source(&path)?;
+    let torrent = TorrentMetainfo::from_bytes(&bytes)?;
+
+    let downloader = Downloader::new(torrent).await?;
+    let repaired = downloader.repair(&data).await?;
+    if repaired.is_empty() {
+        println!("All pieces valid, nothing to repair");
+    } else {
+        println!("Repaired {} piece(s)", repaired.len());
+    }
     Ok(())
 }
 
@@ -132,3 +495,100 @@ async fn handle_magnet_handshake(magnet_link: String) -> Result<()> {
     println!("Peer ID: {}", peer_id_to_string(&peer_id));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bittorrent_starter_rust::{Seeder, TorrentInfo};
+
+    #[test]
+    fn test_info_prints_every_torrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = TorrentMetainfo::new(
+            Some("http://tracker.example/announce".to_string()),
+            TorrentInfo::from_data("other.txt", 4, b"some other data"),
+        );
+        let other_path = dir.path().join("other.torrent");
+        std::fs::write(&other_path, other.to_bytes().unwrap()).unwrap();
+        let other_path = other_path.to_string_lossy().into_owned();
+        let paths = vec!["sample.torrent".to_string(), other_path.clone()];
+
+        let report = info_report(&paths, false, cli::InfoHashFormat::Hex).unwrap();
+        assert!(report.contains("==> sample.torrent <=="));
+        assert!(report.contains(&format!("==> {} <==", other_path)));
+        assert!(report.contains("d69f91e6b2ae4c542468d1073a71d4ea13879a7f"));
+        let other_hash = hex::encode(other.info_hash().unwrap());
+        assert!(report.contains(&format!("Info Hash: {}", other_hash)));
+
+        let report = info_report(&paths, true, cli::InfoHashFormat::Hex).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(json[1]["path"], other_path.as_str());
+
+        // A single torrent prints as before, without a header
+        let report = info_report(&paths[..1], false, cli::InfoHashFormat::Hex).unwrap();
+        assert!(!report.contains("==>"));
+    }
+
+    #[test]
+    fn test_decode_input_from_hex_and_base64() {
+        let value = decode_input("69343265", cli::InputEncoding::Hex).unwrap();
+        assert_eq!(value.to_string(), "42");
+
+        let value = decode_input("ZDM6Zm9vMzpiYXJl", cli::InputEncoding::Base64).unwrap();
+        assert_eq!(value.to_string(), r#"{"foo":"bar"}"#);
+
+        assert!(decode_input("6934zz", cli::InputEncoding::Hex).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_from_explicit_peers() {
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        let info = TorrentInfo::from_data("explicit.bin", 16384, &data);
+        // Nothing listens at the announce URL, so the peers must come from --peers
+        let torrent = TorrentMetainfo::new(
+            Some("http://127.0.0.1:1/announce".to_string()),
+            info.clone(),
+        );
+        let peer_config = PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        };
+        let seeder = Seeder::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            info,
+            data.clone(),
+            peer_config,
+        )
+        .await
+        .unwrap();
+        let peers = vec![seeder.local_addr().unwrap()];
+        tokio::spawn(seeder.run());
+
+        let dir = tempfile::tempdir().unwrap();
+        let torrent_path = dir.path().join("explicit.torrent");
+        std::fs::write(&torrent_path, torrent.to_bytes().unwrap()).unwrap();
+        let torrent_path = torrent_path.to_string_lossy().into_owned();
+
+        let output = dir.path().join("explicit.bin");
+        let output_arg = output.to_string_lossy().into_owned();
+        handle_download(
+            output_arg,
+            false,
+            false,
+            torrent_path.clone(),
+            None,
+            peers.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+
+        let piece = dir.path().join("piece");
+        let piece_arg = piece.to_string_lossy().into_owned();
+        handle_download_piece(piece_arg, torrent_path, 1, peers)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&piece).unwrap(), &data[16384..32768]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Framing of peer wire messages for `tokio_util::codec`.
+//!
+//! After the handshake every peer message is a 4-byte big-endian length followed by
+//! that many bytes of body. `MessageCodec` finds those boundaries in a byte stream
+//! and converts each frame to and from a `Message`, so `Peer` reads whole messages
+//! from a `Framed` connection instead of handling length prefixes itself.
+//!
+//! The 68-byte handshake isn't length-prefixed and is exchanged on the underlying
+//! stream before the codec reads anything.
+
+use anyhow::Result;
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::message::Message;
+use super::peer::{PeerError, DEFAULT_MAX_MESSAGE_SIZE};
+
+/// Size of the length prefix in front of every message
+const LENGTH_PREFIX: usize = 4;
+
+/// Splits a byte stream into peer wire messages and writes messages with their
+/// length prefix.
+#[derive(Debug, Clone)]
+pub struct MessageCodec {
+    /// Largest message body accepted, checked before any buffer is reserved for it
+    max_message_size: usize,
+}
+
+impl MessageCodec {
+    /// Creates a codec that rejects messages longer than `max_message_size` bytes.
+    pub fn new(max_message_size: usize) -> Self {
+        Self { max_message_size }
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
+        let Some(prefix) = src.get(..LENGTH_PREFIX) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(prefix.try_into()?) as usize;
+
+        // Reject oversized messages before reserving a buffer for them
+        if len > self.max_message_size {
+            return Err(anyhow::anyhow!(
+                "Message length {} exceeds maximum of {} bytes",
+                len,
+                self.max_message_size
+            ));
+        }
+        if src.len() < LENGTH_PREFIX + len {
+            src.reserve(LENGTH_PREFIX + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(LENGTH_PREFIX);
+        let body = src.split_to(len);
+        Message::from_bytes(&body).map(Some)
+    }
+
+    /// Fails with `PeerError::Disconnected` if the connection closed partway
+    /// through a message.
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if src.is_empty() => Ok(None),
+            None => Err(PeerError::Disconnected.into()),
+        }
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<()> {
+        dst.extend_from_slice(&message.to_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_concatenated_messages_and_waits_for_partial_one() {
+        let piece = Message::Piece {
+            index: 1,
+            begin: 0,
+            block: vec![7; 10],
+        };
+        let mut src = BytesMut::new();
+        src.extend_from_slice(&Message::Unchoke.to_bytes());
+        src.extend_from_slice(&Message::KeepAlive.to_bytes());
+        src.extend_from_slice(&Message::Have(3).to_bytes());
+        let piece_bytes = piece.to_bytes();
+        src.extend_from_slice(&piece_bytes[..9]);
+
+        let mut codec = MessageCodec::default();
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(Message::Unchoke));
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(Message::KeepAlive));
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(Message::Have(3)));
+        // The piece is still missing most of its block
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        assert_eq!(src.len(), 9);
+
+        src.extend_from_slice(&piece_bytes[9..]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(piece));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_oversized_length_before_buffering_body() {
+        let mut codec = MessageCodec::new(16);
+        let mut src = BytesMut::from(&17u32.to_be_bytes()[..]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert!(err.to_string().contains("exceeds maximum of 16 bytes"));
+    }
+
+    #[test]
+    fn test_connection_closed_mid_message_is_a_disconnect() {
+        let mut codec = MessageCodec::default();
+        let mut src = BytesMut::from(&Message::Have(3).to_bytes()[..6]);
+        let err = codec.decode_eof(&mut src).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(PeerError::Disconnected)));
+
+        assert_eq!(codec.decode_eof(&mut BytesMut::new()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_encodes_length_prefixed_message() {
+        let mut dst = BytesMut::new();
+        MessageCodec::default()
+            .encode(Message::Have(42), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], &[0, 0, 0, 5, 4, 0, 0, 0, 42]);
+    }
+}
@@ -0,0 +1,378 @@
+//! Trackerless peer discovery over the Mainline DHT (BEP 5).
+//!
+//! This is a minimal, query-only DHT client. To find peers for an info hash it:
+//! - Bootstraps from well-known nodes
+//! - Sends `get_peers` queries to the closest nodes it knows about
+//! - Follows the `nodes` returned in responses, getting closer to the info hash each round
+//! - Collects the compact peer addresses returned in `values`
+//!
+//! The client never answers queries from other nodes and keeps no routing table
+//! between lookups.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use crate::bencode::{bvalue::BValue, Bencode};
+
+/// Nodes used to join the DHT when no other nodes are known.
+pub const DEFAULT_BOOTSTRAP_NODES: &[&str] = &[
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+/// A 160-bit DHT node ID.
+pub type NodeId = [u8; 20];
+
+/// Configuration options for DHT lookups.
+#[derive(Debug, Clone)]
+pub struct DhtConfig {
+    /// Our node ID, sent with every query
+    pub node_id: NodeId,
+    /// `host:port` addresses of the nodes used to join the DHT
+    pub bootstrap_nodes: Vec<String>,
+    /// Number of queries sent in parallel each round
+    pub alpha: usize,
+    /// Maximum time to wait for the responses to a round of queries
+    pub query_timeout: Duration,
+    /// Upper bound on the total number of queries sent during one lookup
+    pub max_queries: usize,
+    /// Stop the lookup once this many peers have been found
+    pub max_peers: usize,
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self {
+            node_id: rand::random(),
+            bootstrap_nodes: DEFAULT_BOOTSTRAP_NODES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            alpha: 8,
+            query_timeout: Duration::from_secs(2),
+            max_queries: 128,
+            max_peers: 50,
+        }
+    }
+}
+
+/// The useful parts of a `get_peers` response.
+#[derive(Debug, Default)]
+struct GetPeersResponse {
+    /// Peers known to be sharing the torrent
+    peers: Vec<SocketAddr>,
+    /// Nodes closer to the info hash, with their IDs
+    nodes: Vec<(NodeId, SocketAddr)>,
+}
+
+/// A query-only DHT client bound to a UDP socket.
+pub struct Dht {
+    socket: UdpSocket,
+    config: DhtConfig,
+    next_transaction: u16,
+}
+
+impl Dht {
+    /// Binds the UDP socket used for DHT queries.
+    ///
+    /// # Arguments
+    /// * `addr` - Local address to bind; use port 0 to let the OS choose
+    /// * `config` - Lookup settings and bootstrap nodes
+    pub async fn bind(addr: SocketAddr, config: DhtConfig) -> Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        Ok(Self {
+            socket,
+            config,
+            next_transaction: 0,
+        })
+    }
+
+    /// Performs an iterative `get_peers` lookup for `info_hash`.
+    ///
+    /// # Returns
+    /// The distinct peers found, or an error if no peers could be found.
+    pub async fn get_peers(&mut self, info_hash: [u8; 20]) -> Result<Vec<SocketAddr>> {
+        // Candidates keyed by XOR distance to the info hash; bootstrap nodes have no
+        // known ID so they are queried first.
+        let mut candidates: BTreeMap<NodeId, SocketAddr> = BTreeMap::new();
+        let mut bootstrap = Vec::new();
+        for node in &self.config.bootstrap_nodes {
+            match tokio::net::lookup_host(node.as_str()).await {
+                Ok(addrs) => bootstrap.extend(addrs.filter(SocketAddr::is_ipv4)),
+                Err(e) => debug!("Failed to resolve DHT bootstrap node {}: {}", node, e),
+            }
+        }
+
+        let mut queried = HashSet::new();
+        let mut peers = Vec::new();
+        let mut queries_sent = 0;
+
+        while queries_sent < self.config.max_queries && peers.len() < self.config.max_peers {
+            let budget = self
+                .config
+                .alpha
+                .min(self.config.max_queries - queries_sent);
+            let round: Vec<SocketAddr> = bootstrap
+                .iter()
+                .chain(candidates.values())
+                .copied()
+                .filter(|addr| !queried.contains(addr))
+                .take(budget)
+                .collect();
+            if round.is_empty() {
+                break;
+            }
+
+            queried.extend(round.iter().copied());
+            queries_sent += round.len();
+            for response in self.query_round(&round, info_hash).await? {
+                for peer in response.peers {
+                    if !peers.contains(&peer) {
+                        peers.push(peer);
+                    }
+                }
+                for (id, addr) in response.nodes {
+                    if !queried.contains(&addr) {
+                        candidates.insert(distance(&id, &info_hash), addr);
+                    }
+                }
+            }
+        }
+
+        info!(
+            "DHT lookup sent {} queries and found {} peers",
+            queries_sent,
+            peers.len()
+        );
+        if peers.is_empty() {
+            return Err(anyhow::anyhow!("No peers found in the DHT"));
+        }
+        peers.truncate(self.config.max_peers);
+        Ok(peers)
+    }
+
+    /// Sends a `get_peers` query to each node and collects the responses that
+    /// arrive before the query timeout.
+    async fn query_round(
+        &mut self,
+        nodes: &[SocketAddr],
+        info_hash: [u8; 20],
+    ) -> Result<Vec<GetPeersResponse>> {
+        let mut pending = HashMap::new();
+        for &addr in nodes {
+            let transaction = self.next_transaction.to_be_bytes();
+            self.next_transaction = self.next_transaction.wrapping_add(1);
+            let query = get_peers_query(&transaction, &self.config.node_id, &info_hash)?;
+            if let Err(e) = self.socket.send_to(&query, addr).await {
+                debug!("Failed to send DHT query to {}: {}", addr, e);
+                continue;
+            }
+            pending.insert(transaction, addr);
+        }
+
+        let deadline = Instant::now() + self.config.query_timeout;
+        let mut responses = Vec::new();
+        let mut buf = [0u8; 2048];
+        while !pending.is_empty() {
+            let (len, from) =
+                match tokio::time::timeout_at(deadline, self.socket.recv_from(&mut buf)).await {
+                    Ok(Ok(received)) => received,
+                    // ICMP errors from unreachable nodes surface here; keep waiting for the rest
+                    Ok(Err(e)) => {
+                        debug!("DHT receive failed: {}", e);
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+
+            match parse_response(&buf[..len]) {
+                Ok((transaction, response)) => {
+                    if pending.get(&transaction) == Some(&from) {
+                        pending.remove(&transaction);
+                        responses.push(response);
+                    }
+                }
+                Err(e) => debug!("Ignoring DHT message from {}: {}", from, e),
+            }
+        }
+        Ok(responses)
+    }
+}
+
+/// XOR distance between a node ID and a target, comparable as a big-endian number.
+fn distance(id: &NodeId, target: &[u8; 20]) -> NodeId {
+    std::array::from_fn(|i| id[i] ^ target[i])
+}
+
+/// Encodes a KRPC `get_peers` query.
+fn get_peers_query(transaction: &[u8], node_id: &NodeId, info_hash: &[u8; 20]) -> Result<Vec<u8>> {
+    let arguments = BTreeMap::from([
+        (b"id".to_vec(), BValue::String(node_id.to_vec())),
+        (b"info_hash".to_vec(), BValue::String(info_hash.to_vec())),
+    ]);
+    BValue::Dict(BTreeMap::from([
+        (b"a".to_vec(), BValue::Dict(arguments)),
+        (b"q".to_vec(), BValue::String(b"get_peers".to_vec())),
+        (b"t".to_vec(), BValue::String(transaction.to_vec())),
+        (b"y".to_vec(), BValue::String(b"q".to_vec())),
+    ]))
+    .to_bytes()
+}
+
+/// Decodes a KRPC response, returning its transaction ID and the peers and nodes
+/// it contains.
+fn parse_response(bytes: &[u8]) -> Result<([u8; 2], GetPeersResponse)> {
+    let message = Bencode::decode_bytes(bytes)?;
+    let dict = message.get_dict()?;
+
+    let transaction: [u8; 2] = dict
+        .get(b"t".as_slice())
+        .ok_or_else(|| anyhow::anyhow!("Missing transaction ID"))?
+        .get_bytes()?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Unexpected transaction ID length"))?;
+
+    match dict
+        .get(b"y".as_slice())
+        .map(BValue::get_bytes)
+        .transpose()?
+    {
+        Some(b"r") => {}
+        Some(b"e") => return Err(anyhow::anyhow!("Node returned an error: {}", message)),
+        _ => return Err(anyhow::anyhow!("Not a response")),
+    }
+    let body = dict
+        .get(b"r".as_slice())
+        .ok_or_else(|| anyhow::anyhow!("Missing response body"))?
+        .get_dict()?;
+
+    let mut response = GetPeersResponse::default();
+    if let Some(BValue::List(values)) = body.get(b"values".as_slice()) {
+        for value in values {
+            if let Ok(addr) = value.get_bytes().and_then(parse_compact_addr) {
+                response.peers.push(addr);
+            }
+        }
+    }
+    if let Some(nodes) = body.get(b"nodes".as_slice()) {
+        for chunk in nodes.get_bytes()?.chunks_exact(26) {
+            let id = chunk[..20].try_into()?;
+            response.nodes.push((id, parse_compact_addr(&chunk[20..])?));
+        }
+    }
+    Ok((transaction, response))
+}
+
+/// Parses a 6-byte compact IPv4 address and port.
+fn parse_compact_addr(bytes: &[u8]) -> Result<SocketAddr> {
+    let [a, b, c, d, p1, p2] = bytes else {
+        return Err(anyhow::anyhow!("Compact address must be 6 bytes"));
+    };
+    let ip = Ipv4Addr::new(*a, *b, *c, *d);
+    Ok(SocketAddrV4::new(ip, u16::from_be_bytes([*p1, *p2])).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes an IPv4 socket address in compact form.
+    fn compact(addr: SocketAddr) -> Vec<u8> {
+        let SocketAddr::V4(addr) = addr else {
+            panic!("expected an IPv4 address");
+        };
+        let mut bytes = addr.ip().octets().to_vec();
+        bytes.extend_from_slice(&addr.port().to_be_bytes());
+        bytes
+    }
+
+    /// Spawns a fake DHT node that answers every `get_peers` query with `body`.
+    async fn mock_node(body: BTreeMap<Vec<u8>, BValue>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                let query = Bencode::decode_bytes(&buf[..len]).unwrap();
+                let query = query.get_dict().unwrap();
+                assert_eq!(query[b"q".as_slice()].get_bytes().unwrap(), b"get_peers");
+
+                let response = BValue::Dict(BTreeMap::from([
+                    (b"r".to_vec(), BValue::Dict(body.clone())),
+                    (b"t".to_vec(), query[b"t".as_slice()].clone()),
+                    (b"y".to_vec(), BValue::String(b"r".to_vec())),
+                ]));
+                socket
+                    .send_to(&response.to_bytes().unwrap(), from)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_follows_closer_nodes() {
+        let info_hash = [0xab; 20];
+        let peer: SocketAddr = "10.1.2.3:6881".parse().unwrap();
+
+        let close_node = mock_node(BTreeMap::from([
+            (b"id".to_vec(), BValue::String(vec![0xab; 20])),
+            (
+                b"values".to_vec(),
+                BValue::List(vec![BValue::String(compact(peer))]),
+            ),
+        ]))
+        .await;
+
+        let mut nodes = vec![0xaa; 20];
+        nodes.extend(compact(close_node));
+        let bootstrap = mock_node(BTreeMap::from([
+            (b"id".to_vec(), BValue::String(vec![0x01; 20])),
+            (b"nodes".to_vec(), BValue::String(nodes)),
+        ]))
+        .await;
+
+        let config = DhtConfig {
+            bootstrap_nodes: vec![bootstrap.to_string()],
+            query_timeout: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let mut dht = Dht::bind("127.0.0.1:0".parse().unwrap(), config)
+            .await
+            .unwrap();
+
+        let peers = dht.get_peers(info_hash).await.unwrap();
+
+        assert_eq!(peers, vec![peer]);
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_fails_without_reachable_nodes() {
+        let config = DhtConfig {
+            bootstrap_nodes: vec![],
+            ..Default::default()
+        };
+        let mut dht = Dht::bind("127.0.0.1:0".parse().unwrap(), config)
+            .await
+            .unwrap();
+
+        assert!(dht.get_peers([0u8; 20]).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_response_rejects_errors() {
+        let message = b"d1:eli201e9:A Generic Errore1:t2:aa1:y1:ee";
+        assert!(parse_response(message).is_err());
+    }
+}
@@ -5,75 +5,1655 @@
 //! - Downloading individual pieces and full files
 //! - Piece verification using SHA1 hashes
 //! - Retry logic for failed downloads
-//! - Tracker communication for peer discovery
+//! - Tracker communication for peer discovery, falling back to the DHT when there's no tracker
+//! - Laying out multi-file torrents on disk beneath the output directory
+//! - Cancellation, saving verified pieces of an interrupted download to a `.part` file
+//! - Magnet link downloads, fetching the torrent's metadata from peers first
+//! - Falling back to HTTP web seeds when the peers are too slow
+//! - Progress events with the current download rate and an ETA
+//! - Writing pieces through a pluggable `PieceStore`, in memory, to a file or elsewhere
+//! - A per-peer summary of the pieces each peer contributed
 //!
 //! The main entry point is the `Downloader` struct which handles the overall download process.
+//! Full downloads are delegated to `DownloadManager`, which fetches pieces from several
+//! peers concurrently.
 
 use anyhow::Result;
-use sha1::Digest;
-use std::time::Duration;
-use tracing::info;
+use futures::Stream;
+use md5::{Digest, Md5};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, watch, Mutex, Semaphore};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
 
 use crate::torrent::{
+    dht::{Dht, DhtConfig},
+    magnet_link::MagnetLink,
     message::Message,
-    metainfo::TorrentMetainfo,
-    peer::{Peer, PeerConfig},
-    tracker::{self, TrackerConfig},
+    metadata,
+    metainfo::{FileEntry, TorrentInfo, TorrentMetainfo},
+    peer::{Peer, PeerConfig, PeerError, CONNECT_PARALLELISM},
+    seeder::Seeder,
+    storage::{FileStore, MemoryStore, PieceStore},
+    tracker::{self, AnnounceEvent, Tracker, TrackerConfig},
+    webseed::WebSeed,
 };
 
+/// How long to spend fetching a magnet link's metadata from a single peer
+const METADATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Completed pieces a worker may fall behind on announcing before older ones are skipped
+const HAVE_BROADCAST_CAPACITY: usize = 256;
+
+/// Period the download rate in progress events is averaged over
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// Verified pieces `piece_stream` buffers before workers wait for the consumer
+const PIECE_STREAM_CAPACITY: usize = 4;
+
+/// Period over which peer throughput is measured to decide whether web seeds are needed
+const WEB_SEED_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Order in which workers claim pieces.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PiecePriority {
+    /// Pieces held by the fewest connected peers first, so rare pieces aren't lost
+    /// if their holders leave; ties go to the lowest index
+    #[default]
+    Rarest,
+    /// Lowest index first, so media can be played back while it downloads
+    Sequential,
+}
+
+/// Tuning options for a concurrent multi-peer download.
+#[derive(Debug, Clone)]
+pub struct DownloadConfig {
+    /// Maximum number of pieces a worker claims from the queue at once. A worker
+    /// only claims again once its batch is done, so this is also the most pieces
+    /// one peer holds claimed at a time, which keeps a fast peer from emptying the
+    /// queue while other peers sit idle. Separate from the per-peer window of
+    /// in-flight block requests
+    pub max_pending: usize,
+    /// Number of times a worker reconnects to its peer before giving up on it
+    pub peer_retries: usize,
+    /// Wait before the first reconnect to a peer; doubled before every further one
+    pub reconnect_base_delay: Duration,
+    /// Upper bound on the doubled `reconnect_base_delay`
+    pub reconnect_max_delay: Duration,
+    /// Maximum time allowed to download a single piece from one peer
+    pub piece_timeout: Duration,
+    /// Maximum number of peers connected at once; further peers wait for a free slot
+    pub max_peers: usize,
+    /// Number of times in a row the tracker is asked for fresh peers after every
+    /// worker has given up without the swarm providing any new pieces
+    pub reannounce_rounds: usize,
+    /// Order in which pieces are claimed
+    pub priority: PiecePriority,
+    /// Combined peer throughput, in bytes per second, below which web seeds are
+    /// used to fetch pieces as well
+    pub web_seed_min_rate: u64,
+    /// Whether peers are connected in random order, so clients given the same peer
+    /// list don't all crowd the peers at the front of it
+    pub shuffle_peers: bool,
+    /// Time without any piece completing after which every peer connection is
+    /// dropped, the tracker is asked for fresh peers and the workers are restarted.
+    /// Peers that delivered nothing since the previous stall are only reconnected
+    /// if no other peer is left. Each stall counts as a re-announce round without
+    /// progress
+    pub stall_timeout: Duration,
+    /// Seeds the download's randomness, the order peers are connected in, so a run
+    /// can be reproduced; seeded from the OS when `None`. Ties between equally rare
+    /// pieces always go to the lowest index
+    pub rng_seed: Option<u64>,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            max_pending: 5,
+            peer_retries: 3,
+            reconnect_base_delay: Duration::from_millis(500),
+            reconnect_max_delay: Duration::from_secs(30),
+            piece_timeout: Duration::from_secs(30),
+            max_peers: 50,
+            reannounce_rounds: 3,
+            priority: PiecePriority::default(),
+            web_seed_min_rate: 64 * 1024,
+            shuffle_peers: true,
+            stall_timeout: Duration::from_secs(120),
+            rng_seed: None,
+        }
+    }
+}
+
+/// Per-peer bookkeeping kept by the download manager.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PeerState {
+    /// Pieces downloaded and verified from this peer
+    pub successful_pieces: usize,
+    /// Piece downloads from this peer that failed or did not verify
+    pub failed_pieces: usize,
+    /// Times the worker reconnected to this peer after a failure
+    pub reconnects: usize,
+    /// How long the worker last waited before reconnecting
+    pub backoff: Duration,
+}
+
+/// What a download got from each peer, recorded when `DownloadManager` finishes;
+/// see `DownloadManager::summary`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DownloadSummary {
+    /// Pieces each connected peer delivered or failed. Pieces fetched from web
+    /// seeds aren't attributed to any peer
+    pub per_peer: HashMap<SocketAddr, PeerState>,
+    /// Number of pieces the download was asked for
+    pub total_pieces: usize,
+    /// How long the download ran
+    pub elapsed: Duration,
+}
+
+/// Bytes transferred during a session, for ratio tracking and tracker announces.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TransferStats {
+    /// Bytes of piece data downloaded and verified
+    pub downloaded: u64,
+    /// Bytes of piece data sent to other peers
+    pub uploaded: u64,
+}
+
+/// Session byte counters shared between the manager and its workers.
+#[derive(Debug, Default)]
+struct TransferCounters {
+    downloaded: AtomicU64,
+    /// Shared with the seeder started by `seed_partial`
+    uploaded: Arc<AtomicU64>,
+}
+
+/// Reported by the download manager each time a piece is verified; see
+/// `DownloadManager::with_progress`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    /// Piece that was just verified
+    pub piece_index: usize,
+    /// Pieces verified so far, out of `total_pieces`
+    pub completed_pieces: usize,
+    /// Number of pieces being downloaded
+    pub total_pieces: usize,
+    /// Bytes of verified piece data so far, out of `total_bytes`
+    pub downloaded_bytes: u64,
+    /// Size of the pieces being downloaded
+    pub total_bytes: u64,
+    /// Download rate over the last few seconds
+    pub bytes_per_sec: u64,
+    /// Estimated time until the download completes at the current rate; `None`
+    /// while nothing has been downloaded recently
+    pub eta: Option<Duration>,
+}
+
+/// Measures a transfer rate over a sliding time window.
+#[derive(Debug)]
+pub struct SpeedMeter {
+    window: Duration,
+    /// When measuring began; the rate is averaged over less than `window` until
+    /// that much time has passed
+    started: Instant,
+    /// Bytes recorded, with when they arrived, oldest first
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SpeedMeter {
+    /// Creates a meter averaging over `window`, counting from `started`.
+    pub fn new(window: Duration, started: Instant) -> Self {
+        Self {
+            window,
+            started,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Records `bytes` transferred at `at`.
+    pub fn record(&mut self, at: Instant, bytes: u64) {
+        self.samples.push_back((at, bytes));
+        while let Some(&(oldest, _)) = self.samples.front() {
+            if at.duration_since(oldest) < self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the average rate in bytes per second over the window ending at `now`.
+    pub fn bytes_per_sec(&self, now: Instant) -> u64 {
+        let span = now.duration_since(self.started).min(self.window);
+        if span.is_zero() {
+            return 0;
+        }
+        let bytes: u64 = self
+            .samples
+            .iter()
+            .filter(|&&(at, _)| now.duration_since(at) < self.window)
+            .map(|&(_, bytes)| bytes)
+            .sum();
+        (bytes as f64 / span.as_secs_f64()) as u64
+    }
+
+    /// Estimates how long `remaining` bytes take at the current rate.
+    pub fn eta(&self, now: Instant, remaining: u64) -> Option<Duration> {
+        match self.bytes_per_sec(now) {
+            0 => None,
+            rate => Some(Duration::from_secs_f64(remaining as f64 / rate as f64)),
+        }
+    }
+}
+
+/// Turns piece completions into `ProgressEvent`s.
+struct ProgressTracker {
+    speed: SpeedMeter,
+    completed_pieces: usize,
+    total_pieces: usize,
+    downloaded_bytes: u64,
+    total_bytes: u64,
+}
+
+impl ProgressTracker {
+    /// Records that a piece of `piece_size` bytes was verified.
+    fn complete(&mut self, piece_index: usize, piece_size: usize) -> ProgressEvent {
+        let now = Instant::now();
+        self.completed_pieces += 1;
+        self.downloaded_bytes += piece_size as u64;
+        self.speed.record(now, piece_size as u64);
+        ProgressEvent {
+            piece_index,
+            completed_pieces: self.completed_pieces,
+            total_pieces: self.total_pieces,
+            downloaded_bytes: self.downloaded_bytes,
+            total_bytes: self.total_bytes,
+            bytes_per_sec: self.speed.bytes_per_sec(now),
+            eta: self
+                .speed
+                .eta(now, self.total_bytes.saturating_sub(self.downloaded_bytes)),
+        }
+    }
+}
+
+/// Returned by `DownloadManager::download` or `download_to` when it is cancelled
+/// before every piece has been verified.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Download cancelled with {} of {} pieces verified",
+    .verified.iter().filter(|&&v| v).count(),
+    .verified.len()
+)]
+pub struct DownloadCancelled {
+    /// Whether each piece had been verified, indexed by piece
+    pub verified: Vec<bool>,
+    /// Verified piece data, indexed by piece; `None` for pieces not yet downloaded.
+    /// Always `None` for `download_to` and `download_to_store`, whose verified pieces
+    /// are already stored.
+    pub pieces: Vec<Option<Vec<u8>>>,
+}
+
+/// Returned when none of the connected peers advertise the requested piece.
+#[derive(Debug, thiserror::Error)]
+#[error("no connected peer has piece {0}")]
+pub struct PieceUnavailable(pub usize);
+
+/// Downloads every piece of a torrent concurrently, running one worker per peer.
+///
+/// Workers claim pieces the peer advertises from a shared queue, download and verify
+/// them, and return failed pieces to the queue so another worker can retry them.
+///
+/// Blocks are written through a `PieceStore` as they arrive: `download` collects
+/// the pieces in a `MemoryStore`, `download_to` streams them into a file so memory
+/// use doesn't grow with the torrent or piece size, and `download_to_store` uses
+/// the store set with `with_store`.
+pub struct DownloadManager {
+    /// Info dictionary of the torrent being downloaded
+    info: Arc<TorrentInfo>,
+    /// Addresses of the peers to download from, without duplicates
+    peers: Vec<SocketAddr>,
+    /// Configuration for peer connections
+    peer_config: PeerConfig,
+    /// Download tuning options
+    config: DownloadConfig,
+    /// Stops the download early when cancelled
+    cancel: CancellationToken,
+    /// Bytes transferred so far this session
+    counters: Arc<TransferCounters>,
+    /// Tracker asked for fresh peers once every worker has given up
+    tracker: Option<Mutex<Tracker>>,
+    /// HTTP servers pieces are fetched from while the peers are too slow
+    web_seeds: Vec<WebSeed>,
+    /// Receives a `ProgressEvent` for each verified piece
+    progress: Option<mpsc::Sender<ProgressEvent>>,
+    /// Store used by `download_to_store`
+    store: Option<Arc<dyn PieceStore>>,
+    /// Per-peer results of the most recent download
+    summary: std::sync::Mutex<Option<DownloadSummary>>,
+    /// Whether workers should hold off claiming new pieces; see `DownloadHandle::pause`
+    paused: Arc<watch::Sender<bool>>,
+    /// Holds the latest `ProgressEvent` for `DownloadHandle::progress`
+    latest_progress: Option<watch::Sender<Option<ProgressEvent>>>,
+}
+
+/// Controls a download running in the background; returned by
+/// `DownloadManager::start`.
+///
+/// Dropping the handle leaves the download running.
+pub struct DownloadHandle {
+    cancel: CancellationToken,
+    paused: Arc<watch::Sender<bool>>,
+    progress: watch::Receiver<Option<ProgressEvent>>,
+    task: tokio::task::JoinHandle<Result<Vec<u8>>>,
+}
+
+impl DownloadHandle {
+    /// Stops the download; `wait` then returns a `DownloadCancelled` error.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Stops workers from starting new pieces. Pieces already being downloaded
+    /// are finished, and peers stay connected.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Lets workers start new pieces again after `pause`.
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Returns whether the download is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Returns a receiver holding the most recent `ProgressEvent`, `None` until the
+    /// first piece has been verified.
+    pub fn progress(&self) -> watch::Receiver<Option<ProgressEvent>> {
+        self.progress.clone()
+    }
+
+    /// Waits for the download to finish and returns its result, as `download` would.
+    pub async fn wait(self) -> Result<Vec<u8>> {
+        self.task.await?
+    }
+}
+
+/// State shared between the download manager and its workers.
+#[derive(Clone)]
+struct WorkerContext {
+    info: Arc<TorrentInfo>,
+    peer_config: PeerConfig,
+    config: DownloadConfig,
+    /// Pieces not yet claimed by any worker
+    queue: Arc<Mutex<VecDeque<usize>>>,
+    /// Whether each piece was selected for download
+    wanted: Arc<Vec<bool>>,
+    /// Number of connected peers that have each piece
+    availability: Arc<std::sync::Mutex<Vec<usize>>>,
+    /// Whether each piece has been downloaded and verified
+    verified: Arc<Mutex<Vec<bool>>>,
+    /// Where blocks are written as they arrive
+    store: Arc<dyn PieceStore>,
+    /// Receives each verified piece's data, which is then released from `store`
+    /// (see `piece_stream`)
+    pieces: Option<mpsc::Sender<(usize, Vec<u8>)>>,
+    /// Per-peer success/failure counts
+    peer_states: Arc<Mutex<HashMap<SocketAddr, PeerState>>>,
+    /// Notifies the manager of each completed piece
+    results: mpsc::Sender<usize>,
+    /// Tells every connected worker about each completed piece, so it can send
+    /// its peer a `Have`
+    haves: broadcast::Sender<usize>,
+    /// Tells workers to stop, even in the middle of connecting or of a piece
+    cancel: CancellationToken,
+    /// Holds workers back from claiming pieces while `true`
+    paused: watch::Receiver<bool>,
+    /// Session byte counters
+    counters: Arc<TransferCounters>,
+    /// Source of the download's randomness, seeded from `rng_seed`
+    rng: Arc<std::sync::Mutex<StdRng>>,
+}
+
+impl DownloadManager {
+    /// Creates a manager that will download `info` from the given peers.
+    ///
+    /// Duplicate addresses are dropped; each peer is connected at most once per
+    /// download.
+    pub fn new(
+        info: TorrentInfo,
+        peers: Vec<SocketAddr>,
+        peer_config: PeerConfig,
+        config: DownloadConfig,
+    ) -> Self {
+        Self {
+            info: Arc::new(info),
+            peers: merge_peers(&mut HashSet::new(), peers),
+            peer_config,
+            config,
+            cancel: CancellationToken::new(),
+            counters: Arc::default(),
+            tracker: None,
+            web_seeds: Vec::new(),
+            progress: None,
+            store: None,
+            summary: std::sync::Mutex::new(None),
+            paused: Arc::new(watch::channel(false).0),
+            latest_progress: None,
+        }
+    }
+
+    /// Returns the bytes transferred so far this session.
+    ///
+    /// Only verified piece data counts as downloaded, so bytes from pieces that
+    /// failed their hash check are not included. Uploaded bytes are those served
+    /// by the seeder from `seed_partial`.
+    pub fn stats(&self) -> TransferStats {
+        TransferStats {
+            downloaded: self.counters.downloaded.load(Ordering::Relaxed),
+            uploaded: self.counters.uploaded.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns how many pieces each peer contributed to the most recent download,
+    /// whether it completed, failed or was cancelled. `None` before any download
+    /// has run.
+    pub fn summary(&self) -> Option<DownloadSummary> {
+        self.summary.lock().unwrap().clone()
+    }
+
+    /// Uses `cancel` to stop the download early; see `download`.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Re-announces to `tracker` for fresh peers when every worker has given up with
+    /// pieces still missing, or when no piece has completed for `stall_timeout`, up
+    /// to `reannounce_rounds` times in a row without progress.
+    pub fn with_tracker(mut self, tracker: Tracker) -> Self {
+        self.tracker = Some(Mutex::new(tracker));
+        self
+    }
+
+    /// Also fetches pieces from the web seeds at `urls` whenever the peers' combined
+    /// throughput is below `web_seed_min_rate`, or once every peer has given up.
+    pub fn with_web_seeds(mut self, urls: &[String]) -> Self {
+        self.web_seeds = urls.iter().map(WebSeed::new).collect();
+        self
+    }
+
+    /// Sends a `ProgressEvent` to `progress` each time a piece is verified.
+    ///
+    /// Events are dropped rather than holding up the download if the receiver
+    /// falls behind.
+    pub fn with_progress(mut self, progress: mpsc::Sender<ProgressEvent>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Writes pieces into `store` when downloading with `download_to_store`.
+    pub fn with_store(mut self, store: Box<dyn PieceStore>) -> Self {
+        self.store = Some(store.into());
+        self
+    }
+
+    /// Returns the store set with `with_store`, e.g. to read pieces back after
+    /// `download_to_store`.
+    pub fn store(&self) -> Option<&dyn PieceStore> {
+        self.store.as_deref()
+    }
+
+    /// Downloads all pieces and returns the reassembled torrent data.
+    ///
+    /// Fails if every worker gives up before all pieces have been verified and the
+    /// tracker (see `with_tracker`) has no peers that make further progress. If the
+    /// cancellation token fires first, the workers are stopped and the error is a
+    /// `DownloadCancelled` holding the pieces verified so far.
+    pub async fn download(&self) -> Result<Vec<u8>> {
+        let all: Vec<_> = (0..self.info.total_pieces()).collect();
+        let store = self.run_in_memory(&all).await?;
+        let mut data = Vec::with_capacity(self.info.length);
+        for piece_index in all {
+            data.extend_from_slice(&read_piece_checked(&*store, &self.info, piece_index).await?);
+        }
+        if data.len() != self.info.length {
+            return Err(anyhow::anyhow!(
+                "Reassembled {} bytes but the torrent is {} bytes long",
+                data.len(),
+                self.info.length
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Downloads only the given pieces and returns their data keyed by piece index.
+    ///
+    /// Only the selected pieces are queued, and the download completes once each of
+    /// them has been verified. Errors match `download`; indices past the end of the
+    /// torrent are rejected up front.
+    pub async fn download_pieces(&self, indices: &[usize]) -> Result<BTreeMap<usize, Vec<u8>>> {
+        let store = self.run_in_memory(indices).await?;
+        let mut pieces = BTreeMap::new();
+        for &piece_index in indices {
+            let piece = read_piece_checked(&*store, &self.info, piece_index).await?;
+            pieces.insert(piece_index, piece);
+        }
+        Ok(pieces)
+    }
+
+    /// Downloads all pieces straight into the file at `path`, at their offsets.
+    ///
+    /// The file is created if needed and extended to the torrent's length. Blocks are
+    /// written and hashed as they arrive, so only about one block per peer is held in
+    /// memory. Errors match `download`; on cancellation the pieces verified so far
+    /// are already in the file.
+    pub async fn download_to(&self, path: &Path) -> Result<()> {
+        let all: Vec<_> = (0..self.info.total_pieces()).collect();
+        self.download_pieces_to(path, &all).await
+    }
+
+    /// Downloads only the given pieces into the file at `path`, as `download_to`
+    /// would. The rest of the file is left as it is, so pieces already in it from
+    /// an earlier download are kept.
+    pub async fn download_pieces_to(&self, path: &Path, indices: &[usize]) -> Result<()> {
+        let store = FileStore::create(path, &self.info).await?;
+        self.run(Arc::new(store), indices, None).await
+    }
+
+    /// Downloads all pieces into the store set with `with_store`.
+    ///
+    /// Errors match `download`; on cancellation the pieces verified so far are
+    /// already in the store.
+    pub async fn download_to_store(&self) -> Result<()> {
+        let store = self
+            .store
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No piece store set; see with_store"))?;
+        let all: Vec<_> = (0..self.info.total_pieces()).collect();
+        self.run(store, &all, None).await
+    }
+
+    /// Starts seeding whatever the store set with `with_store` holds, even if the
+    /// download is incomplete, so a partial download still contributes to the swarm
+    /// (a partial seed, BEP 21).
+    ///
+    /// Only pieces that verify against their hashes are advertised in the bitfield
+    /// or served; like every `Seeder`, it keeps them in memory. Fails if the store
+    /// returns a piece of the wrong size. If a tracker was set with `with_tracker`,
+    /// it is sent a `paused` announce with the seeder's port and the bytes still
+    /// missing. The seeder listens on `peer_config.listen_ip` and `port`; call
+    /// `Seeder::run` to serve.
+    ///
+    /// Bytes the seeder serves are counted as uploaded in `stats`.
+    pub async fn seed_partial(&self) -> Result<Seeder> {
+        let store = self
+            .store
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No piece store set; see with_store"))?;
+        let mut data = vec![0; self.info.length];
+        let mut verified = vec![false; self.info.total_pieces()];
+        let mut left = self.info.length;
+        for (piece_index, valid) in verified.iter_mut().enumerate() {
+            if !store.has_piece(piece_index) {
+                continue;
+            }
+            let piece = read_piece_checked(&**store, &self.info, piece_index).await?;
+            if !self.info.verify_piece(piece_index, &piece) {
+                continue;
+            }
+            let start = piece_index * self.info.piece_length;
+            data[start..start + piece.len()].copy_from_slice(&piece);
+            *valid = true;
+            left -= piece.len();
+        }
+
+        // The pieces were just hashed, so the seeder needn't check them again
+        let mut seeder = Seeder::listen_verified(
+            (*self.info).clone(),
+            data,
+            &verified,
+            self.peer_config.clone(),
+        )
+        .await?;
+        seeder.count_uploads(&self.peer_config.info_hash, self.counters.uploaded.clone())?;
+        if let Some(tracker) = &self.tracker {
+            let announce_url = tracker.lock().await.announce_url().to_string();
+            let stats = self.stats();
+            let config = TrackerConfig {
+                uploaded: stats.uploaded,
+                downloaded: stats.downloaded,
+                ..seeder.tracker_config()?
+            };
+            if let Err(e) = tracker::announce_event(
+                &announce_url,
+                self.peer_config.info_hash,
+                Some(left as u64),
+                AnnounceEvent::Paused,
+                Some(config),
+            )
+            .await
+            {
+                warn!("Failed to announce partial seed to tracker: {}", e);
+            }
+        }
+        Ok(seeder)
+    }
+
+    /// Downloads all pieces in the background, yielding each piece's index and
+    /// verified data as soon as it completes.
+    ///
+    /// Pieces arrive in completion order; with `PiecePriority::Sequential` and a
+    /// single peer that is piece order, so the data can be consumed progressively.
+    /// Only a few pieces are buffered: workers wait while the consumer falls behind,
+    /// and dropping the stream cancels the download. The stream ends when the
+    /// download finishes or fails; failures are logged. Must be called from within
+    /// a tokio runtime.
+    pub fn piece_stream(self) -> impl Stream<Item = (usize, Vec<u8>)> {
+        let (sender, receiver) = mpsc::channel(PIECE_STREAM_CAPACITY);
+        tokio::spawn(async move {
+            let all: Vec<_> = (0..self.info.total_pieces()).collect();
+            let store = Arc::new(MemoryStore::new(&self.info));
+            if let Err(e) = self.run(store, &all, Some(sender)).await {
+                warn!("Piece stream ended early: {}", e);
+            }
+        });
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            let piece = receiver.recv().await?;
+            Some((piece, receiver))
+        })
+    }
+
+    /// Starts downloading all pieces in the background, as `download` would, and
+    /// returns a handle to cancel, pause or follow the download. Must be called from
+    /// within a tokio runtime.
+    pub fn start(mut self) -> DownloadHandle {
+        let (progress, receiver) = watch::channel(None);
+        self.latest_progress = Some(progress);
+        DownloadHandle {
+            cancel: self.cancel.clone(),
+            paused: self.paused.clone(),
+            progress: receiver,
+            task: tokio::spawn(async move { self.download().await }),
+        }
+    }
+
+    /// Downloads `wanted` into a fresh `MemoryStore` and returns it.
+    ///
+    /// On cancellation the `DownloadCancelled` error is filled in with the data of
+    /// the pieces verified so far.
+    async fn run_in_memory(&self, wanted: &[usize]) -> Result<Arc<MemoryStore>> {
+        let store = Arc::new(MemoryStore::new(&self.info));
+        if let Err(e) = self.run(store.clone(), wanted, None).await {
+            let mut cancelled = e.downcast::<DownloadCancelled>()?;
+            for (piece_index, piece) in cancelled.pieces.iter_mut().enumerate() {
+                if cancelled.verified[piece_index] {
+                    *piece = read_piece_checked(&*store, &self.info, piece_index)
+                        .await
+                        .ok();
+                }
+            }
+            return Err(cancelled.into());
+        }
+        Ok(store)
+    }
+
+    /// Runs the workers until every piece is verified, the workers give up or the
+    /// download is cancelled, writing blocks into `store` as they arrive.
+    ///
+    /// With `pieces` set, each verified piece's data is also sent there and then
+    /// released from `store`.
+    async fn run(
+        &self,
+        store: Arc<dyn PieceStore>,
+        wanted: &[usize],
+        pieces: Option<mpsc::Sender<(usize, Vec<u8>)>>,
+    ) -> Result<()> {
+        if !self.info.has_metadata() {
+            return Err(anyhow::anyhow!("Torrent metadata is not available yet"));
+        }
+        let total_pieces = self.info.total_pieces();
+        if let Some(&piece_index) = wanted.iter().find(|&&i| i >= total_pieces) {
+            return Err(anyhow::anyhow!(
+                "Piece {} is out of range; the torrent has {} pieces",
+                piece_index,
+                total_pieces
+            ));
+        }
+        let mut is_wanted = vec![false; total_pieces];
+        for &piece_index in wanted {
+            is_wanted[piece_index] = true;
+        }
+        let wanted_count = is_wanted.iter().filter(|&&w| w).count();
+        let started = Instant::now();
+        // Workers send the index of each piece they complete, so the loop below
+        // counts completions without rescanning the verified pieces
+        let (results, mut completions) = mpsc::channel(32);
+        let context = self.worker_context(store, is_wanted, results, pieces);
+
+        // Each worker holds a slot while connected, so at most `max_peers` peers are
+        // connected at once and waiting peers rotate in as others give up or finish
+        let slots = Arc::new(Semaphore::new(self.config.max_peers));
+        let mut workers = JoinSet::new();
+        // Every peer handed to a worker this session, so re-announces that return
+        // the same peers don't connect to them again
+        let mut attempted = HashSet::new();
+        Self::spawn_workers(
+            &mut workers,
+            merge_peers(&mut attempted, self.peers.iter().copied()),
+            &context,
+            &slots,
+        );
+        for seed in &self.web_seeds {
+            workers.spawn(Self::web_seed_task(seed.clone(), context.clone()));
+        }
+        let verified = context.verified.clone();
+        let peer_states = context.peer_states.clone();
+
+        let mut completed_count = 0;
+        let mut progress = ProgressTracker {
+            speed: SpeedMeter::new(SPEED_WINDOW, Instant::now()),
+            completed_pieces: 0,
+            total_pieces: wanted_count,
+            downloaded_bytes: 0,
+            total_bytes: wanted
+                .iter()
+                .filter_map(|&i| self.info.piece_size(i))
+                .sum::<usize>() as u64,
+        };
+        let mut cancelled = false;
+        // Pieces verified when the tracker was last asked for peers, and how many
+        // rounds in a row have passed without any new pieces
+        let mut reannounced_at = 0;
+        let mut idle_rounds = 0;
+        let mut exhausted = None;
+        // When a piece last completed or the workers were last restarted, for the
+        // stall watchdog
+        let mut last_progress = Instant::now();
+        // Pieces each peer had delivered at the last stall, so the next one can tell
+        // which peers made progress in between
+        let mut pieces_at_stall: HashMap<SocketAddr, usize> = HashMap::new();
+        while completed_count < wanted_count {
+            let mut stalled = false;
+            tokio::select! {
+                Some(piece_index) = completions.recv() => {
+                    completed_count += 1;
+                    last_progress = Instant::now();
+                    self.report_progress(&mut progress, piece_index);
+                    continue;
+                }
+                joined = workers.join_next() => {
+                    if joined.is_some() {
+                        continue;
+                    }
+                }
+                _ = tokio::time::sleep_until((last_progress + self.config.stall_timeout).into()) => {
+                    if *self.paused.borrow() {
+                        // Nothing completes while paused; that isn't a stall
+                        last_progress = Instant::now();
+                        continue;
+                    }
+                    stalled = true;
+                }
+                _ = self.cancel.cancelled() => {
+                    cancelled = true;
+                    break;
+                }
+            }
+
+            if stalled {
+                warn!(
+                    "No piece completed in {:?}, dropping every peer connection",
+                    self.config.stall_timeout
+                );
+                workers.abort_all();
+                while workers.join_next().await.is_some() {}
+            }
+            // Every worker has exited; count any completions still in the channel
+            while let Ok(piece_index) = completions.try_recv() {
+                completed_count += 1;
+                self.report_progress(&mut progress, piece_index);
+            }
+            if stalled {
+                // An aborted worker may have verified a piece without reporting it,
+                // or been holding claimed pieces that now need queueing again
+                completed_count = Self::requeue_unverified(&context).await;
+            }
+            if completed_count == wanted_count {
+                break;
+            }
+            // Workers return as soon as the token fires, so they can all be gone
+            // before the select above sees the cancellation
+            if self.cancel.is_cancelled() {
+                cancelled = true;
+                break;
+            }
+            if self.tracker.is_none() && !stalled {
+                break;
+            }
+            if completed_count > reannounced_at {
+                idle_rounds = 0;
+            }
+            if idle_rounds == self.config.reannounce_rounds {
+                exhausted = Some(anyhow::anyhow!(
+                    "Swarm exhausted: no progress after {} re-announce rounds, {} of {} pieces verified",
+                    idle_rounds,
+                    completed_count,
+                    wanted_count
+                ));
+                break;
+            }
+            idle_rounds += 1;
+            reannounced_at = completed_count;
+
+            // After a stall, old peers that delivered pieces since the last stall get
+            // fresh connections alongside any new ones. Peers that made no progress
+            // could claim the requeued pieces and stall the download again, so they
+            // are only reconnected when there is no other peer to try
+            let mut peers = Vec::new();
+            let mut idle_peers = Vec::new();
+            if stalled {
+                let states = peer_states.lock().await;
+                for &addr in &attempted {
+                    let pieces = states.get(&addr).map_or(0, |s| s.successful_pieces);
+                    let before = pieces_at_stall.insert(addr, pieces).unwrap_or(0);
+                    match pieces > before {
+                        true => peers.push(addr),
+                        false => idle_peers.push(addr),
+                    }
+                }
+            }
+            if let Some(tracker) = &self.tracker {
+                let left = (self.info.length as u64)
+                    .saturating_sub(self.counters.downloaded.load(Ordering::Relaxed));
+                let info_hash = self.peer_config.info_hash;
+                let stats = self.stats();
+                info!(
+                    "No peers left with {} pieces missing, asking the tracker for more",
+                    wanted_count - completed_count
+                );
+                let announced = tokio::select! {
+                    announced = async {
+                        let mut tracker = tracker.lock().await;
+                        tracker.set_transferred(stats.uploaded, stats.downloaded);
+                        tracker.announce(info_hash, Some(left)).await
+                    } => announced,
+                    _ = self.cancel.cancelled() => {
+                        cancelled = true;
+                        break;
+                    }
+                };
+                match announced {
+                    Ok(announced) => {
+                        let returned = announced.len();
+                        let new_peers = merge_peers(
+                            &mut attempted,
+                            announced
+                                .into_iter()
+                                .map(|p| SocketAddr::new(p.ip.into(), p.port)),
+                        );
+                        info!(
+                            "Tracker returned {} peers, {} new",
+                            returned,
+                            new_peers.len()
+                        );
+                        peers.extend(new_peers);
+                    }
+                    Err(e) => warn!("Re-announce failed: {}", e),
+                }
+            }
+            if peers.is_empty() {
+                peers = idle_peers;
+            }
+            Self::spawn_workers(&mut workers, peers, &context, &slots);
+            if stalled {
+                for seed in &self.web_seeds {
+                    workers.spawn(Self::web_seed_task(seed.clone(), context.clone()));
+                }
+            }
+            last_progress = Instant::now();
+        }
+
+        workers.abort_all();
+        drop(context);
+
+        *self.summary.lock().unwrap() = Some(DownloadSummary {
+            per_peer: peer_states.lock().await.clone(),
+            total_pieces: wanted_count,
+            elapsed: started.elapsed(),
+        });
+        if let Some(e) = exhausted {
+            return Err(e);
+        }
+        let verified = std::mem::take(&mut *verified.lock().await);
+        if cancelled {
+            info!("Download cancelled");
+            return Err(DownloadCancelled {
+                pieces: vec![None; verified.len()],
+                verified,
+            }
+            .into());
+        }
+        if let Some(&piece_index) = wanted.iter().find(|&&i| !verified[i]) {
+            return Err(anyhow::anyhow!(
+                "Download incomplete: piece {} could not be downloaded from any peer",
+                piece_index
+            ));
+        }
+        Ok(())
+    }
+
+    /// Queues every wanted piece that is neither verified nor already queued, once
+    /// the workers have been stopped, and returns how many wanted pieces are verified.
+    async fn requeue_unverified(ctx: &WorkerContext) -> usize {
+        let verified = ctx.verified.lock().await;
+        let mut queue = ctx.queue.lock().await;
+        let missing: Vec<usize> = (0..verified.len())
+            .filter(|&i| ctx.wanted[i] && !verified[i] && !queue.contains(&i))
+            .collect();
+        queue.extend(missing);
+        (0..verified.len())
+            .filter(|&i| ctx.wanted[i] && verified[i])
+            .count()
+    }
+
+    /// Logs a verified piece and passes its progress event on to `progress`.
+    fn report_progress(&self, progress: &mut ProgressTracker, piece_index: usize) {
+        let piece_size = self.info.piece_size(piece_index).unwrap_or_default();
+        let event = progress.complete(piece_index, piece_size);
+        info!(
+            "Completed piece {} ({}/{}, {} KiB/s)",
+            piece_index,
+            event.completed_pieces,
+            event.total_pieces,
+            event.bytes_per_sec / 1024
+        );
+        if let Some(latest) = &self.latest_progress {
+            latest.send_replace(Some(event.clone()));
+        }
+        if let Some(sender) = &self.progress {
+            let _ = sender.try_send(event);
+        }
+    }
+
+    /// Builds the state shared by the workers of one `run`, with every piece in
+    /// `is_wanted` queued.
+    fn worker_context(
+        &self,
+        store: Arc<dyn PieceStore>,
+        is_wanted: Vec<bool>,
+        results: mpsc::Sender<usize>,
+        pieces: Option<mpsc::Sender<(usize, Vec<u8>)>>,
+    ) -> WorkerContext {
+        let total_pieces = is_wanted.len();
+        WorkerContext {
+            info: self.info.clone(),
+            peer_config: PeerConfig {
+                total_pieces: Some(total_pieces),
+                ..self.peer_config.clone()
+            },
+            config: self.config.clone(),
+            queue: Arc::new(Mutex::new(
+                (0..total_pieces).filter(|&i| is_wanted[i]).collect(),
+            )),
+            wanted: Arc::new(is_wanted),
+            availability: Arc::new(std::sync::Mutex::new(vec![0; total_pieces])),
+            verified: Arc::new(Mutex::new(vec![false; total_pieces])),
+            store,
+            pieces,
+            peer_states: Arc::new(Mutex::new(HashMap::new())),
+            results,
+            haves: broadcast::channel(HAVE_BROADCAST_CAPACITY).0,
+            cancel: self.cancel.clone(),
+            paused: self.paused.subscribe(),
+            counters: self.counters.clone(),
+            rng: Arc::new(std::sync::Mutex::new(match self.config.rng_seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            })),
+        }
+    }
+
+    /// Starts a worker for each of `peers`, each waiting for a free connection slot.
+    ///
+    /// Slots are handed out in spawn order, so with `shuffle_peers` set the peers
+    /// are shuffled first.
+    fn spawn_workers(
+        workers: &mut JoinSet<()>,
+        mut peers: Vec<SocketAddr>,
+        context: &WorkerContext,
+        slots: &Arc<Semaphore>,
+    ) {
+        if context.config.shuffle_peers {
+            shuffle_peers(&mut peers, &mut *context.rng.lock().unwrap());
+        }
+        for addr in peers {
+            let ctx = context.clone();
+            let slots = slots.clone();
+            workers.spawn(async move {
+                let Ok(_slot) = slots.acquire_owned().await else {
+                    return;
+                };
+                Self::worker_task(addr, ctx).await
+            });
+        }
+    }
+
+    /// Downloads pieces from a single peer until nothing it has is left to fetch.
+    ///
+    /// Reconnects up to `peer_retries` times after connection or download failures,
+    /// returning any claimed but unfinished pieces to the shared queue. Each reconnect
+    /// waits `reconnect_base_delay`, doubled per consecutive failure up to
+    /// `reconnect_max_delay`, so a peer that is briefly unreachable gets time to
+    /// recover instead of burning every retry at once. Pieces
+    /// completed by any worker are announced to the peer with `Have` between batches.
+    ///
+    /// Returns as soon as `ctx.cancel` fires: the token is checked before each batch
+    /// and raced against connecting and every piece download, so a stalled peer
+    /// can't hold up shutdown.
+    #[instrument(name = "worker", skip(ctx), fields(peer = %addr))]
+    async fn worker_task(addr: SocketAddr, ctx: WorkerContext) {
+        let mut failures = 0;
+        while failures <= ctx.config.peer_retries && !ctx.cancel.is_cancelled() {
+            if failures > 0 {
+                let delay = Self::reconnect_delay(&ctx.config, failures);
+                {
+                    let mut peer_states = ctx.peer_states.lock().await;
+                    let state = peer_states.entry(addr).or_default();
+                    state.reconnects += 1;
+                    state.backoff = delay;
+                }
+                info!("Reconnecting to peer {} in {:?}", addr, delay);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = ctx.cancel.cancelled() => return,
+                }
+            }
+            let mut peer = Peer::new(addr, ctx.peer_config.clone());
+            let connected = tokio::select! {
+                connected = async {
+                    peer.connect().await?;
+                    peer.wait_for_bitfield().await
+                } => connected,
+                _ = ctx.cancel.cancelled() => return,
+            };
+            if let Err(e) = connected {
+                info!("Failed to connect to peer {}: {}", addr, e);
+                failures += 1;
+                continue;
+            }
+            let _available = AvailabilityGuard::new(&ctx.availability, &peer);
+            let mut haves = ctx.haves.subscribe();
+
+            loop {
+                if ctx.cancel.is_cancelled() {
+                    return;
+                }
+                if let Err(e) = Self::send_haves(&mut peer, &mut haves).await {
+                    info!("Failed to announce pieces to peer {}: {}", addr, e);
+                    failures += 1;
+                    break;
+                }
+                let batch = Self::claim_pieces(&ctx, &peer).await;
+                let Some(batch) = batch else {
+                    info!("Peer {} has no remaining pieces to offer", addr);
+                    return;
+                };
+                if batch.is_empty() {
+                    // Everything this peer has is in progress elsewhere; wait in case it is requeued
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+
+                if let Err(e) = Self::download_batch(&ctx, &mut peer, addr, &batch).await {
+                    if ctx.cancel.is_cancelled() {
+                        return;
+                    }
+                    info!("Worker for peer {} failed: {}", addr, e);
+                    if let Some(PeerError::ChokeTimeout(_) | PeerError::ReadTimeout(_)) =
+                        e.downcast_ref()
+                    {
+                        // Reconnecting won't help a peer that refuses to serve us or
+                        // has stalled; a dropped connection is worth retrying
+                        return;
+                    }
+                    failures += 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Wait before reconnecting after `failures` consecutive failures: the base delay
+    /// doubled for each failure after the first, capped at `reconnect_max_delay`.
+    fn reconnect_delay(config: &DownloadConfig, failures: usize) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1) as u32);
+        config
+            .reconnect_base_delay
+            .saturating_mul(factor)
+            .min(config.reconnect_max_delay)
+    }
+
+    /// Sends the peer a `Have` for every piece completed since the last call.
+    async fn send_haves(peer: &mut Peer, haves: &mut broadcast::Receiver<usize>) -> Result<()> {
+        loop {
+            match haves.try_recv() {
+                Ok(piece_index) => peer.send_message(Message::Have(piece_index as u32)).await?,
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    warn!("Skipped announcing {} completed pieces", skipped)
+                }
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    /// Fetches pieces from a web seed whenever the peers' combined throughput over
+    /// the last `WEB_SEED_RATE_WINDOW` was below `web_seed_min_rate`.
+    ///
+    /// Gives up after `peer_retries` failed pieces, returning each to the queue.
+    #[instrument(name = "web_seed", skip(seed, ctx), fields(url = %seed.url))]
+    async fn web_seed_task(seed: WebSeed, ctx: WorkerContext) {
+        let mut failures = 0;
+
+        // Bytes fetched from this seed are excluded from the peer throughput
+        let mut window_start = tokio::time::Instant::now();
+        let mut window_base = ctx.counters.downloaded.load(Ordering::Relaxed);
+        let mut own_bytes = 0;
+        let mut peers_slow = false;
+        while failures <= ctx.config.peer_retries && !ctx.cancel.is_cancelled() {
+            let elapsed = window_start.elapsed();
+            if elapsed >= WEB_SEED_RATE_WINDOW {
+                let total = ctx.counters.downloaded.load(Ordering::Relaxed);
+                let peer_bytes = total.saturating_sub(window_base + own_bytes);
+                let rate = peer_bytes * 1000 / elapsed.as_millis().max(1) as u64;
+                peers_slow = rate < ctx.config.web_seed_min_rate;
+                window_start = tokio::time::Instant::now();
+                window_base = total;
+                own_bytes = 0;
+            }
+            if !peers_slow {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+
+            let Some(batch) = Self::claim(&ctx, |_| true, 1).await else {
+                return;
+            };
+            let Some(&piece_index) = batch.first() else {
+                // Everything missing is in progress elsewhere; wait in case it is requeued
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            };
+
+            let fetched = async {
+                let data = seed.fetch_piece(&ctx.info, piece_index).await?;
+                ctx.store.write_piece(piece_index, 0, &data).await?;
+                Ok::<_, anyhow::Error>(data.len())
+            };
+            match fetched.await {
+                Ok(piece_size) => {
+                    own_bytes += piece_size as u64;
+                    Self::complete_piece(&ctx, piece_index, piece_size).await;
+                }
+                Err(e) => {
+                    info!("Failed to fetch piece {} from web seed: {}", piece_index, e);
+                    ctx.queue.lock().await.push_back(piece_index);
+                    failures += 1;
+                }
+            }
+        }
+    }
+
+    /// Claims up to `max_pending` queued pieces that the peer has, in the order
+    /// given by the configured `PiecePriority`.
+    ///
+    /// Returns `None` once the peer has none of the pieces that are still missing.
+    async fn claim_pieces(ctx: &WorkerContext, peer: &Peer) -> Option<Vec<usize>> {
+        Self::claim(
+            ctx,
+            |piece_index| peer.has_piece(piece_index),
+            ctx.config.max_pending.max(1),
+        )
+        .await
+    }
+
+    /// Claims up to `max` queued pieces for which `has` holds; see `claim_pieces`.
+    ///
+    /// Waits first while the download is paused, unless it is cancelled.
+    async fn claim(
+        ctx: &WorkerContext,
+        has: impl Fn(usize) -> bool,
+        max: usize,
+    ) -> Option<Vec<usize>> {
+        let mut paused = ctx.paused.clone();
+        tokio::select! {
+            _ = paused.wait_for(|&paused| !paused) => {}
+            _ = ctx.cancel.cancelled() => {}
+        }
+
+        let mut queue = ctx.queue.lock().await;
+        let mut candidates: Vec<usize> = queue
+            .iter()
+            .copied()
+            .filter(|&piece_index| has(piece_index))
+            .collect();
+        match ctx.config.priority {
+            PiecePriority::Sequential => candidates.sort_unstable(),
+            PiecePriority::Rarest => {
+                let availability = ctx.availability.lock().unwrap();
+                candidates
+                    .sort_unstable_by_key(|&piece_index| (availability[piece_index], piece_index));
+            }
+        }
+        candidates.truncate(max);
+        queue.retain(|piece_index| !candidates.contains(piece_index));
+        drop(queue);
+        let batch = candidates;
+
+        if batch.is_empty() {
+            let verified = ctx.verified.lock().await;
+            let still_needed = verified
+                .iter()
+                .enumerate()
+                .any(|(piece_index, &done)| !done && ctx.wanted[piece_index] && has(piece_index));
+            if !still_needed {
+                return None;
+            }
+        }
+        Some(batch)
+    }
+
+    /// Downloads and verifies each piece of a claimed batch in order, writing its
+    /// blocks into the store as they arrive.
+    ///
+    /// On failure or cancellation the unfinished piece and the rest of the batch are
+    /// returned to the queue.
+    async fn download_batch(
+        ctx: &WorkerContext,
+        peer: &mut Peer,
+        addr: SocketAddr,
+        batch: &[usize],
+    ) -> Result<()> {
+        for (position, &piece_index) in batch.iter().enumerate() {
+            if ctx.cancel.is_cancelled() {
+                ctx.queue.lock().await.extend(&batch[position..]);
+                return Err(anyhow::anyhow!("Cancelled before piece {}", piece_index));
+            }
+            let piece_size = ctx
+                .info
+                .piece_size(piece_index)
+                .ok_or_else(|| anyhow::anyhow!("Piece {} is out of range", piece_index))?;
+            let download = async {
+                let expected = ctx.info.pieces.hash(piece_index)?;
+                peer.download_piece_to(piece_index, piece_size, expected, &*ctx.store)
+                    .await
+            };
+            let download = tokio::time::timeout(ctx.config.piece_timeout, download);
+            let result = tokio::select! {
+                result = download => match result {
+                    Ok(result) => result,
+                    Err(_) => {
+                        let _ = peer.cancel_pending().await;
+                        Err(anyhow::anyhow!(
+                            "Timed out downloading piece {}",
+                            piece_index
+                        ))
+                    }
+                },
+                _ = ctx.cancel.cancelled() => {
+                    // Not the peer's fault, so its failure count is left alone
+                    ctx.queue.lock().await.extend(&batch[position..]);
+                    return Err(anyhow::anyhow!("Cancelled during piece {}", piece_index));
+                }
+            };
+
+            let mut peer_states = ctx.peer_states.lock().await;
+            let state = peer_states.entry(addr).or_default();
+            match result {
+                Ok(()) => {
+                    state.successful_pieces += 1;
+                    drop(peer_states);
+                    Self::complete_piece(ctx, piece_index, piece_size).await;
+                }
+                Err(e) => {
+                    state.failed_pieces += 1;
+                    drop(peer_states);
+                    ctx.queue.lock().await.extend(&batch[position..]);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a verified piece: counts its bytes, hands its data to a piece stream
+    /// if there is one, and notifies the manager and the connected workers.
+    ///
+    /// Waits while a piece stream's buffer is full; if the stream was dropped the
+    /// download is cancelled.
+    async fn complete_piece(ctx: &WorkerContext, piece_index: usize, piece_size: usize) {
+        ctx.counters
+            .downloaded
+            .fetch_add(piece_size as u64, Ordering::Relaxed);
+        if let Some(pieces) = &ctx.pieces {
+            match read_piece_checked(&*ctx.store, &ctx.info, piece_index).await {
+                Ok(piece) => {
+                    ctx.store.release_piece(piece_index);
+                    if pieces.send((piece_index, piece)).await.is_err() {
+                        info!("Piece stream dropped, cancelling download");
+                        ctx.cancel.cancel();
+                    }
+                }
+                Err(e) => warn!("Failed to read back piece {}: {}", piece_index, e),
+            }
+        }
+        ctx.verified.lock().await[piece_index] = true;
+        // No receivers just means no peer is connected right now
+        let _ = ctx.haves.send(piece_index);
+        let _ = ctx.results.send(piece_index).await;
+    }
+}
+
+/// Reads a piece back from `store`, failing unless it is exactly the piece's size
+/// so a misbehaving store can't truncate or shift the data reassembled after it.
+async fn read_piece_checked(
+    store: &dyn PieceStore,
+    info: &TorrentInfo,
+    piece_index: usize,
+) -> Result<Vec<u8>> {
+    let expected = info
+        .piece_size(piece_index)
+        .ok_or_else(|| anyhow::anyhow!("Piece {} is out of range", piece_index))?;
+    let piece = store.read_piece(piece_index).await?;
+    if piece.len() != expected {
+        return Err(anyhow::anyhow!(
+            "Piece {} has {} bytes, expected {}",
+            piece_index,
+            piece.len(),
+            expected
+        ));
+    }
+    Ok(piece)
+}
+
+/// Returns the addresses in `peers` not already in `seen`, in order and without
+/// duplicates, and adds them to `seen`.
+///
+/// Trackers, the DHT and magnet peer hints often report the same peers, so every
+/// source is merged through this before connecting.
+fn merge_peers(
+    seen: &mut HashSet<SocketAddr>,
+    peers: impl IntoIterator<Item = SocketAddr>,
+) -> Vec<SocketAddr> {
+    peers
+        .into_iter()
+        .filter(|&addr| seen.insert(addr))
+        .collect()
+}
+
+/// Shuffles `peers` in place using `rng`.
+fn shuffle_peers(peers: &mut [SocketAddr], rng: &mut impl rand::Rng) {
+    use rand::seq::SliceRandom;
+    peers.shuffle(rng);
+}
+
+/// Counts a connected peer's pieces towards their availability until dropped.
+struct AvailabilityGuard {
+    availability: Arc<std::sync::Mutex<Vec<usize>>>,
+    pieces: Vec<usize>,
+}
+
+impl AvailabilityGuard {
+    fn new(availability: &Arc<std::sync::Mutex<Vec<usize>>>, peer: &Peer) -> Self {
+        let mut counts = availability.lock().unwrap();
+        let pieces: Vec<usize> = (0..counts.len()).filter(|&i| peer.has_piece(i)).collect();
+        for &piece_index in &pieces {
+            counts[piece_index] += 1;
+        }
+        drop(counts);
+        Self {
+            availability: availability.clone(),
+            pieces,
+        }
+    }
+}
+
+impl Drop for AvailabilityGuard {
+    fn drop(&mut self) {
+        let mut counts = self.availability.lock().unwrap();
+        for &piece_index in &self.pieces {
+            counts[piece_index] -= 1;
+        }
+    }
+}
+
 /// Manages the download of a torrent, coordinating peer connections and piece retrieval.
 pub struct Downloader {
     /// Metadata about the torrent being downloaded
     torrent: TorrentMetainfo,
     /// List of available peer addresses
-    peers: Vec<String>,
+    peers: Vec<SocketAddr>,
     /// Configuration for peer connections
     peer_config: PeerConfig,
+    /// Interrupts `download_all` when cancelled
+    cancel: CancellationToken,
+    /// Whether `download_all` may ask the tracker for more peers; off when the
+    /// peers were provided explicitly
+    reannounce: bool,
+    /// Download tuning options, used for single pieces as well as whole downloads
+    config: DownloadConfig,
 }
 
 impl Downloader {
     /// Creates a new downloader instance for the given torrent.
     ///
     /// Contacts the tracker to discover peers and initializes the download configuration.
+    /// When the torrent has no tracker, or the tracker can't be reached, peers are looked
+    /// up in the DHT instead unless the torrent is private. A torrent with web seeds can
+    /// be downloaded even if no peers are found.
     ///
     /// # Arguments
     /// * `torrent` - Metadata for the torrent to download
     ///
-    /// # Returns
-    /// * `Result<Downloader>` - New downloader instance on success, error if no peers found
-    pub async fn new(torrent: TorrentMetainfo) -> Result<Self> {
-        let announce = torrent
-            .announce
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("No tracker URL"))?;
-        let info_hash = torrent.info_hash()?;
+    /// # Returns
+    /// * `Result<Downloader>` - New downloader instance on success, error if no peers found
+    pub async fn new(torrent: TorrentMetainfo) -> Result<Self> {
+        let peers = match Self::discover_peers(
+            torrent.announce.as_deref(),
+            torrent.info_hash()?,
+            torrent.info.as_ref().map(|i| i.length as u64),
+            torrent.info.as_ref().is_some_and(|i| i.private),
+        )
+        .await
+        {
+            Ok(peers) => peers,
+            Err(e) if !torrent.url_list.is_empty() => {
+                warn!("No peers found ({}), downloading from web seeds only", e);
+                Vec::new()
+            }
+            Err(e) => return Err(e),
+        };
+        let mut downloader = Self::with_peers(torrent, peers)?;
+        downloader.reannounce = true;
+        Ok(downloader)
+    }
+
+    /// Creates a downloader for a magnet link.
+    ///
+    /// Peers are found the same way as in `new`, then the torrent's metadata is
+    /// fetched from them (see `from_magnet_with_peers`). The link's peer hints are
+    /// tried first, and are enough on their own if no other peers can be found.
+    pub async fn from_magnet(magnet: &MagnetLink) -> Result<Self> {
+        let discovered =
+            Self::discover_peers(magnet.tracker.as_deref(), magnet.info_hash, None, false).await;
+        let mut seen = HashSet::new();
+        let mut peers = merge_peers(&mut seen, magnet.peer_hints.iter().copied());
+        match discovered {
+            Ok(discovered) => peers.extend(merge_peers(&mut seen, discovered)),
+            Err(e) if !peers.is_empty() => {
+                warn!("No peers found ({}), using the magnet link's peer hints", e)
+            }
+            Err(e) => return Err(e),
+        }
+        let mut downloader = Self::from_magnet_with_peers(magnet, peers).await?;
+        downloader.reannounce = true;
+        Ok(downloader)
+    }
+
+    /// Creates a downloader for a magnet link that uses the given peers.
+    ///
+    /// The info dictionary is requested from each peer in turn until one serves
+    /// metadata whose hash matches the magnet link's info hash.
+    pub async fn from_magnet_with_peers(
+        magnet: &MagnetLink,
+        peers: Vec<SocketAddr>,
+    ) -> Result<Self> {
         let peer_config = PeerConfig {
-            info_hash,
+            info_hash: magnet.info_hash,
             ..Default::default()
         };
 
-        let peers = tracker::get_peers(
-            announce,
-            info_hash,
-            torrent.info.as_ref().map(|i| i.length as u64),
-            Some(TrackerConfig::default()),
-        )
-        .await?;
+        let mut metadata = None;
+        for peer_addr in &peers {
+            let mut peer = Peer::new(*peer_addr, peer_config.clone());
+            let fetch = async {
+                peer.connect().await?;
+                metadata::fetch_metadata(&mut peer, magnet.info_hash).await
+            };
+            match tokio::time::timeout(METADATA_TIMEOUT, fetch).await {
+                Ok(Ok(bytes)) => {
+                    metadata = Some(bytes);
+                    break;
+                }
+                Ok(Err(e)) => info!("Failed to fetch metadata from {}: {}", peer_addr, e),
+                Err(_) => info!("Timed out fetching metadata from {}", peer_addr),
+            }
+        }
+        let metadata = metadata
+            .ok_or_else(|| anyhow::anyhow!("No peer could provide the torrent metadata"))?;
+        let mut torrent = TorrentMetainfo::from_info_bytes(magnet.tracker.clone(), &metadata)?;
+        torrent.url_list = magnet.web_seeds.clone();
+        info!(
+            "Fetched metadata for {}",
+            torrent.info.as_ref().map_or("", |i| i.name.as_str())
+        );
+        Self::with_peers(torrent, peers)
+    }
+
+    /// Finds peers for a torrent, asking the tracker first and falling back to the
+    /// DHT when there is no tracker or it can't be reached. Private torrents never
+    /// use the DHT.
+    async fn discover_peers(
+        announce: Option<&str>,
+        info_hash: [u8; 20],
+        left: Option<u64>,
+        private: bool,
+    ) -> Result<Vec<SocketAddr>> {
+        let tracker_result = match announce {
+            Some(announce) => {
+                tracker::get_peers(announce, info_hash, left, Some(TrackerConfig::default()))
+                    .await
+                    .map(|peers| {
+                        peers
+                            .into_iter()
+                            .map(|p| SocketAddr::new(p.ip.into(), p.port))
+                            .collect()
+                    })
+            }
+            None => Err(anyhow::anyhow!("No tracker URL")),
+        };
+
+        match tracker_result {
+            Ok(peers) => Ok(peers),
+            Err(e) if private => Err(e),
+            Err(e) => {
+                warn!("Tracker unavailable ({}), searching the DHT for peers", e);
+                Self::dht_peers(info_hash).await
+            }
+        }
+    }
+
+    /// Looks up peers for `info_hash` in the DHT.
+    async fn dht_peers(info_hash: [u8; 20]) -> Result<Vec<SocketAddr>> {
+        let mut dht = Dht::bind(SocketAddr::from(([0, 0, 0, 0], 0)), DhtConfig::default()).await?;
+        dht.get_peers(info_hash).await
+    }
 
-        if peers.is_empty() {
+    /// Creates a downloader that uses the given peers instead of asking the tracker.
+    ///
+    /// `peers` may only be empty if the torrent has web seeds. Duplicate addresses
+    /// are dropped.
+    pub fn with_peers(torrent: TorrentMetainfo, peers: Vec<SocketAddr>) -> Result<Self> {
+        let peers = merge_peers(&mut HashSet::new(), peers);
+        if peers.is_empty() && torrent.url_list.is_empty() {
             return Err(anyhow::anyhow!("No peers available"));
         }
 
+        let peer_config = PeerConfig {
+            info_hash: torrent.info_hash()?,
+            total_pieces: torrent.info.as_ref().map(TorrentInfo::total_pieces),
+            ..Default::default()
+        };
+
         Ok(Self {
             torrent,
-            peers: peers.into_iter().map(|p| p.to_string()).collect(),
+            peers,
             peer_config,
+            cancel: CancellationToken::new(),
+            reannounce: false,
+            config: DownloadConfig::default(),
         })
     }
 
+    /// Uses `config` instead of the default `DownloadConfig` for every download.
+    pub fn with_config(mut self, config: DownloadConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns the torrent being downloaded.
+    pub fn torrent(&self) -> &TorrentMetainfo {
+        &self.torrent
+    }
+
+    /// Writes the torrent to `path` as a `.torrent` file, e.g. to keep the
+    /// metadata fetched for a magnet link.
+    ///
+    /// The encoded file is checked to hash to the torrent's info hash before it
+    /// is written.
+    pub async fn save_torrent(&self, path: &Path) -> Result<()> {
+        let bytes = self.torrent.to_bytes()?;
+        let info_hash = self.torrent.info_hash()?;
+        if TorrentMetainfo::info_hash_from_bytes(&bytes)? != info_hash {
+            return Err(anyhow::anyhow!(
+                "Encoded torrent does not match info hash {}",
+                hex::encode(info_hash)
+            ));
+        }
+        tokio::fs::write(path, bytes).await?;
+        Ok(())
+    }
+
+    /// Returns a token that interrupts `download_all` when cancelled.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
     /// Downloads a single piece of the torrent.
     ///
-    /// Will retry with different peers if the download fails.
+    /// Makes up to three passes over the peers (see `download_piece_from_any`),
+    /// pausing between passes in case peers were only briefly unavailable.
     ///
     /// # Arguments
     /// * `piece_index` - Index of the piece to download
@@ -81,20 +1661,73 @@ impl Downloader {
     /// # Returns
     /// * `Result<Vec<u8>>` - The piece data on success
     pub async fn download_piece(&self, piece_index: usize) -> Result<Vec<u8>> {
+        let mut last_error = None;
+        for attempt in 0..3 {
+            if attempt > 0 {
+                info!("Retrying piece {} with all peers", piece_index);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            match self.download_piece_from_any(piece_index).await {
+                Ok(data) => return Ok(data),
+                Err(e) if e.is::<PieceUnavailable>() => return Err(e),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No peers available")))
+    }
+
+    /// Returns the first copy of the piece, from any peer, that passes hash
+    /// verification.
+    ///
+    /// Up to `CONNECT_PARALLELISM` peers are connected and their bitfields read at
+    /// once, and whenever an attempt finishes the next peer is tried, so dead peers
+    /// at the front of the list don't hold up the rest. The first peer that
+    /// advertises the piece is downloaded from straight away; attempts still in
+    /// flight once the piece verifies are cancelled. Peers that time out or send
+    /// corrupt data are skipped. If none of the reachable peers advertise the piece,
+    /// this fails with `PieceUnavailable`.
+    pub async fn download_piece_from_any(&self, piece_index: usize) -> Result<Vec<u8>> {
         let piece_length = self
             .torrent
             .info
             .as_ref()
-            .map(|i| i.piece_size(piece_index))
-            .unwrap_or(0);
+            .and_then(|i| i.piece_size(piece_index))
+            .ok_or_else(|| anyhow::anyhow!("Piece {} is out of range", piece_index))?;
 
-        for peer_addr in self.peers.iter().cycle().take(3 * self.peers.len()) {
-            if peer_addr != &self.peers[0] {
-                info!("Retrying piece {} with new peer", piece_index);
-                tokio::time::sleep(Duration::from_secs(1)).await;
+        let mut remaining = self.peers.iter().copied();
+        let mut attempts = JoinSet::new();
+        let mut start_next = |attempts: &mut JoinSet<_>| {
+            if let Some(peer_addr) = remaining.next() {
+                let mut peer = Peer::new(peer_addr, self.peer_config.clone());
+                attempts.spawn(async move {
+                    let ready = async {
+                        peer.connect().await?;
+                        peer.wait_for_bitfield().await
+                    };
+                    (peer_addr, ready.await.map(|()| peer))
+                });
             }
+        };
+        for _ in 0..CONNECT_PARALLELISM {
+            start_next(&mut attempts);
+        }
 
-            let mut peer = Peer::new(peer_addr.parse()?, self.peer_config.clone());
+        let mut connected = 0;
+        let mut holders = 0;
+        while let Some(joined) = attempts.join_next().await {
+            start_next(&mut attempts);
+            let (peer_addr, mut peer) = match joined? {
+                (peer_addr, Ok(peer)) => (peer_addr, peer),
+                (peer_addr, Err(e)) => {
+                    info!("Failed to connect to peer {}: {}", peer_addr, e);
+                    continue;
+                }
+            };
+            connected += 1;
+            if !peer.has_piece(piece_index) {
+                continue;
+            }
+            holders += 1;
             match self
                 .download_piece_from_peer(&mut peer, piece_index, piece_length)
                 .await
@@ -104,110 +1737,945 @@ impl Downloader {
             }
         }
 
+        if connected == 0 {
+            return Err(anyhow::anyhow!("Failed to connect to any peer"));
+        }
+        if holders == 0 {
+            return Err(PieceUnavailable(piece_index).into());
+        }
         Err(anyhow::anyhow!(
             "Failed to download piece {} after trying all peers",
             piece_index
         ))
     }
 
-    /// Downloads the complete torrent and saves it to a file.
+    /// Downloads the complete torrent from all known peers and saves it to disk.
+    ///
+    /// Single-file torrents, including those with a one-entry file list, are written
+    /// to `output`. Multi-file torrents are written beneath `output/<name>/`, one file
+    /// per entry in the torrent's file list.
+    ///
+    /// Downloaders that found their peers through the tracker ask it for fresh peers
+    /// if all of them give up before the download completes. Pieces are also fetched
+    /// from the torrent's web seeds while the peers are slow.
+    ///
+    /// If the download is cancelled (see `cancellation_token`), the verified pieces
+    /// are saved at their offsets in `<output>.part`, the tracker is sent a `stopped`
+    /// event and an error is returned. The `.part` file can be checked with `verify`.
+    /// Running the download again resumes it: if `<output>.part` already has the
+    /// torrent's length, its pieces are hash-checked and only the failing ones are
+    /// downloaded.
+    ///
+    /// Otherwise the `.part` file is preallocated to the torrent's length before any
+    /// piece is requested, so an unwritable output path or a full disk fails the
+    /// download early.
+    /// Pieces are streamed into it as they arrive rather than collected in memory, and
+    /// nothing is written to `output` unless every piece was downloaded and verified.
     ///
     /// # Arguments
-    /// * `output` - Path where the downloaded file should be saved
+    /// * `output` - Path where the downloaded file (or directory root) should be saved
     ///
     /// # Returns
-    /// * `Result<()>` - Success or error status
-    pub async fn download_all(&self, output: &str) -> Result<()> {
-        let mut file_data = Vec::with_capacity(self.torrent.info.as_ref().unwrap().length);
+    /// * `Result<DownloadSummary>` - How many pieces each peer contributed, or an error
+    pub async fn download_all(&self, output: &str) -> Result<DownloadSummary> {
+        let info = self
+            .torrent
+            .info
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No info dictionary"))?;
 
-        for piece_index in 0..self.torrent.info.as_ref().unwrap().total_pieces() {
+        let part_path = PathBuf::from(format!("{}.part", output));
+        let resumed = matches!(
+            tokio::fs::metadata(&part_path).await,
+            Ok(metadata) if metadata.len() == info.length as u64
+        );
+        let missing = if resumed {
+            let missing = failing_pieces_in(info, &part_path).await?;
             info!(
-                "Downloading piece {}/{}",
-                piece_index + 1,
-                self.torrent.info.as_ref().unwrap().total_pieces()
+                "Resuming from {}: {} of {} pieces missing",
+                part_path.display(),
+                missing.len(),
+                info.total_pieces()
             );
+            missing
+        } else {
+            tokio::fs::File::create(&part_path)
+                .await?
+                .set_len(info.length as u64)
+                .await?;
+            (0..info.total_pieces()).collect()
+        };
 
-            let piece_data = self.download_piece(piece_index).await?;
-            file_data.extend_from_slice(&piece_data);
+        let mut manager = DownloadManager::new(
+            info.clone(),
+            self.peers.clone(),
+            self.peer_config.clone(),
+            self.config.clone(),
+        )
+        .with_cancellation(self.cancel.clone())
+        .with_web_seeds(&self.torrent.url_list);
+        if let (true, Some(announce)) = (self.reannounce, &self.torrent.announce) {
+            manager = manager.with_tracker(Tracker::new(announce, TrackerConfig::default()));
+        }
+        let downloaded = match missing.is_empty() {
+            true => Ok(()),
+            false => manager.download_pieces_to(&part_path, &missing).await,
+        };
+        if let Err(e) = downloaded {
+            return match e.downcast::<DownloadCancelled>() {
+                Ok(mut cancelled) => {
+                    // Pieces that were already valid in the resumed file count as verified
+                    let mut saved = vec![true; cancelled.verified.len()];
+                    for &piece_index in &missing {
+                        saved[piece_index] = false;
+                    }
+                    for (verified, saved) in cancelled.verified.iter_mut().zip(saved) {
+                        *verified |= saved;
+                    }
+                    let stats = manager.stats();
+                    Err(self
+                        .save_interrupted(&part_path, info, cancelled, stats)
+                        .await)
+                }
+                Err(e) => {
+                    // A resumed file still holds the pieces saved by earlier runs
+                    if !resumed {
+                        let _ = tokio::fs::remove_file(&part_path).await;
+                    }
+                    Err(e)
+                }
+            };
         }
 
-        tokio::fs::write(output, file_data).await?;
+        match info.multi_file() {
+            Some(files) => {
+                split_files(&part_path, Path::new(output), &info.name, files).await?;
+                tokio::fs::remove_file(&part_path).await?;
+            }
+            None => tokio::fs::rename(&part_path, output).await?,
+        }
         info!("Download completed successfully");
+        Ok(manager.summary().unwrap_or_default())
+    }
+
+    /// Downloads only the given pieces and writes them at their offsets in `output`.
+    ///
+    /// Ranges of pieces that were not selected are left zeroed, so the file has the
+    /// torrent's full length and can be checked with `verify`. Multi-file torrents
+    /// are rejected; use `download_file` to fetch one of their files.
+    pub async fn download_selected(&self, output: &str, pieces: &[usize]) -> Result<()> {
+        let info = self
+            .torrent
+            .info
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No info dictionary"))?;
+        if info.multi_file().is_some() {
+            return Err(anyhow::anyhow!(
+                "Selecting pieces of multi-file torrent {} isn't supported",
+                info.name
+            ));
+        }
+
+        let downloaded = self.selective_manager(info).download_pieces(pieces).await?;
+
+        let mut selected = vec![None; info.total_pieces()];
+        for (piece_index, data) in downloaded {
+            selected[piece_index] = Some(data);
+        }
+        write_partial(Path::new(output), info, &selected).await?;
+        info!("Downloaded {} selected pieces", pieces.len());
         Ok(())
     }
 
-    /// Attempts to download a piece from a specific peer.
+    /// Downloads a single file of a multi-file torrent, chosen by its path (components
+    /// joined with `/`) or its index in the file list, and writes it beneath
+    /// `output/<name>/` as `download_all` would. Returns the path written.
+    ///
+    /// Only the pieces overlapping the file are downloaded; the parts of the first
+    /// and last of them that belong to neighbouring files are discarded.
+    pub async fn download_file(&self, name_or_index: &str, output: &str) -> Result<PathBuf> {
+        let info = self
+            .torrent
+            .info
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No info dictionary"))?;
+        let index = info.find_file(name_or_index)?;
+        let file = &info.multi_file().unwrap_or_default()[index];
+        let path = resolve_output_path(Path::new(output), &info.name, &file.path)?;
+        let range = info
+            .file_range(index)
+            .ok_or_else(|| anyhow::anyhow!("No file {} in the torrent", index))?;
+
+        let pieces: Vec<usize> = info.pieces_in(&range).collect();
+        let downloaded = self
+            .selective_manager(info)
+            .download_pieces(&pieces)
+            .await?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut output = tokio::fs::File::create(&path).await?;
+        for (piece_index, data) in downloaded {
+            let piece_start = piece_index * info.piece_length;
+            let start = range.start.max(piece_start) - piece_start;
+            let end = range.end.min(piece_start + data.len()) - piece_start;
+            output.write_all(&data[start..end]).await?;
+        }
+        output.flush().await?;
+        info!(
+            "Wrote {} bytes from {} pieces to {}",
+            file.length,
+            pieces.len(),
+            path.display()
+        );
+        Ok(path)
+    }
+
+    /// Re-checks an existing download at `output` and re-downloads only the pieces
+    /// that fail their hash check, overwriting them in place.
+    ///
+    /// Like `verify`, this expects the torrent's data concatenated in file order; a
+    /// file of the wrong length is resized first, so missing pieces are fetched too.
+    /// Returns the indices of the repaired pieces, which is empty if every piece
+    /// was already valid.
+    pub async fn repair(&self, output: &str) -> Result<Vec<usize>> {
+        let info = self
+            .torrent
+            .info
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No info dictionary"))?;
+
+        let failing = failing_pieces_in(info, Path::new(output)).await?;
+        if failing.is_empty() {
+            info!("All {} pieces are valid", info.total_pieces());
+            return Ok(failing);
+        }
+        info!(
+            "Repairing {} of {} pieces",
+            failing.len(),
+            info.total_pieces()
+        );
+
+        let downloaded = self
+            .selective_manager(info)
+            .download_pieces(&failing)
+            .await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(output)
+            .await?;
+        if file.metadata().await?.len() != info.length as u64 {
+            file.set_len(info.length as u64).await?;
+        }
+        for (piece_index, piece) in downloaded {
+            let offset = (piece_index * info.piece_length) as u64;
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            file.write_all(&piece).await?;
+        }
+        file.flush().await?;
+        Ok(failing)
+    }
+
+    /// Builds a manager for downloading a subset of the pieces, without
+    /// re-announcing to the tracker.
+    fn selective_manager(&self, info: &TorrentInfo) -> DownloadManager {
+        DownloadManager::new(
+            info.clone(),
+            self.peers.clone(),
+            self.peer_config.clone(),
+            self.config.clone(),
+        )
+        .with_cancellation(self.cancel.clone())
+        .with_web_seeds(&self.torrent.url_list)
+    }
+
+    /// Tells the tracker we are leaving the swarm after a cancelled download, whose
+    /// verified pieces are already in `part_path`, with the session's byte counts
+    /// from `stats`, and returns the error to report.
+    async fn save_interrupted(
+        &self,
+        part_path: &Path,
+        info: &TorrentInfo,
+        cancelled: DownloadCancelled,
+        stats: TransferStats,
+    ) -> anyhow::Error {
+        if let Some(announce) = &self.torrent.announce {
+            let left = cancelled
+                .verified
+                .iter()
+                .enumerate()
+                .filter(|(_, &verified)| !verified)
+                .filter_map(|(piece_index, _)| info.piece_size(piece_index))
+                .sum::<usize>();
+            let config = TrackerConfig {
+                request_timeout: Duration::from_secs(5),
+                max_attempts: 1,
+                uploaded: stats.uploaded,
+                downloaded: stats.downloaded,
+                ..Default::default()
+            };
+            if let Err(e) = tracker::announce_event(
+                announce,
+                self.peer_config.info_hash,
+                Some(left as u64),
+                AnnounceEvent::Stopped,
+                Some(config),
+            )
+            .await
+            {
+                warn!("Failed to send stopped event to tracker: {}", e);
+            }
+        }
+
+        anyhow::anyhow!(
+            "Download interrupted: {}; saved to {}, run the download again to resume",
+            cancelled,
+            part_path.display()
+        )
+    }
+
+    /// Attempts to download a piece from a peer that is connected and has
+    /// advertised the piece.
     ///
     /// Handles the peer protocol including:
-    /// - Connecting to the peer
-    /// - Waiting for bitfield to verify piece availability
     /// - Waiting to be unchoked
-    /// - Downloading and verifying the piece
+    /// - Downloading and verifying the piece, cancelling outstanding requests on timeout
     async fn download_piece_from_peer(
         &self,
         peer: &mut Peer,
         piece_index: usize,
         piece_length: usize,
     ) -> Result<Vec<u8>> {
-        peer.connect().await?;
-        self.wait_for_bitfield(peer, piece_index).await?;
-        self.wait_for_unchoke(peer).await?;
+        peer.wait_for_unchoke().await?;
 
-        let piece_data = peer.download_piece(piece_index, piece_length).await?;
+        let piece_data = match tokio::time::timeout(
+            self.config.piece_timeout,
+            peer.download_piece(piece_index, piece_length),
+        )
+        .await
+        {
+            Ok(result) => result?,
+            Err(_) => {
+                peer.cancel_pending().await?;
+                return Err(anyhow::anyhow!(
+                    "Timed out downloading piece {}",
+                    piece_index
+                ));
+            }
+        };
         self.verify_piece(&piece_data, piece_index)?;
 
         Ok(piece_data)
     }
 
-    /// Waits to receive the peer's bitfield and verifies they have the requested piece.
-    async fn wait_for_bitfield(&self, peer: &mut Peer, piece_index: usize) -> Result<()> {
-        loop {
-            match peer.receive_message().await? {
-                Message::Bitfield(b) => {
-                    let byte_index = piece_index / 8;
-                    let bit_index = 7 - (piece_index % 8);
+    /// Verifies a downloaded piece matches its expected SHA1 hash.
+    fn verify_piece(&self, piece_data: &[u8], piece_index: usize) -> Result<()> {
+        let info = self.torrent.info.as_ref().unwrap();
+        if !info.verify_piece(piece_index, piece_data) {
+            return Err(anyhow::anyhow!("Piece hash verification failed"));
+        }
 
-                    if byte_index >= b.len() || (b[byte_index] & (1 << bit_index)) == 0 {
-                        return Err(anyhow::anyhow!("Peer does not have piece {}", piece_index));
-                    }
-                    return Ok(());
-                }
-                Message::KeepAlive => continue,
-                msg => {
-                    return Err(anyhow::anyhow!(
-                        "Unexpected message before bitfield: {:?}",
-                        msg
-                    ))
+        Ok(())
+    }
+}
+
+/// Returns the indices of the pieces of the file at `path` that fail their hash
+/// check, reading it one piece at a time so the whole file is never held in memory.
+/// Pieces past the end of a short file fail too.
+async fn failing_pieces_in(info: &TorrentInfo, path: &Path) -> Result<Vec<usize>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut piece = vec![0; info.piece_length];
+    let mut failing = Vec::new();
+    for piece_index in 0..info.total_pieces() {
+        let size = info.piece_size(piece_index).unwrap_or(0);
+        let valid = match file.read_exact(&mut piece[..size]).await {
+            Ok(_) => info.verify_piece(piece_index, &piece[..size]),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                failing.extend(piece_index..info.total_pieces());
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !valid {
+            failing.push(piece_index);
+        }
+    }
+    Ok(failing)
+}
+
+/// Resolves the on-disk location of a torrent file as `root/name/path...`.
+///
+/// Every component of `name` and `path` must be a plain file or directory name;
+/// `..`, absolute paths and embedded separators are rejected so a crafted torrent
+/// cannot write outside of `root`.
+pub fn resolve_output_path(root: &Path, name: &str, path: &[String]) -> Result<PathBuf> {
+    if path.is_empty() {
+        return Err(anyhow::anyhow!("File path in torrent is empty"));
+    }
+    join_safe(
+        root,
+        std::iter::once(name).chain(path.iter().map(String::as_str)),
+    )
+}
+
+/// Works out the path to pass to `Downloader::download_all` for `output`.
+///
+/// If `output` is an existing directory, a single-file torrent is saved inside it as
+/// `output/<name>`; multi-file torrents always go beneath `output/<name>/`. Fails if
+/// that destination already exists, unless `force` is set.
+pub fn output_destination(output: &Path, info: &TorrentInfo, force: bool) -> Result<PathBuf> {
+    let (path, destination) = match info.multi_file() {
+        Some(_) => (
+            output.to_path_buf(),
+            join_safe(output, [info.name.as_str()])?,
+        ),
+        None if output.is_dir() => {
+            let path = join_safe(output, [info.name.as_str()])?;
+            (path.clone(), path)
+        }
+        None => (output.to_path_buf(), output.to_path_buf()),
+    };
+    if !force && destination.exists() {
+        return Err(anyhow::anyhow!(
+            "{} already exists; pass --force to overwrite it",
+            destination.display()
+        ));
+    }
+    Ok(path)
+}
+
+/// Appends each of `components` to `root`, rejecting any that isn't a plain name.
+fn join_safe<'a>(root: &Path, components: impl IntoIterator<Item = &'a str>) -> Result<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    for component in components {
+        let mut components = Path::new(component).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(part)), None) if part == component => resolved.push(part),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unsafe path component in torrent: {:?}",
+                    component
+                ))
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Writes the verified pieces of an incomplete download to `path` at their offsets,
+/// leaving the ranges of missing pieces zeroed.
+///
+/// Fails without writing anything if a piece isn't exactly its expected size.
+pub async fn write_partial(
+    path: &Path,
+    info: &TorrentInfo,
+    pieces: &[Option<Vec<u8>>],
+) -> Result<()> {
+    for (piece_index, piece) in pieces.iter().enumerate() {
+        if let Some(piece) = piece {
+            let expected = info.piece_size(piece_index);
+            if expected != Some(piece.len()) {
+                return Err(anyhow::anyhow!(
+                    "Piece {} has {} bytes, expected {:?}",
+                    piece_index,
+                    piece.len(),
+                    expected
+                ));
+            }
+        }
+    }
+    let mut file = tokio::fs::File::create(path).await?;
+    file.set_len(info.length as u64).await?;
+    for (piece_index, piece) in pieces.iter().enumerate() {
+        if let Some(piece) = piece {
+            let offset = (piece_index * info.piece_length) as u64;
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            file.write_all(piece).await?;
+        }
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Copies the concatenated torrent data in `source` into its files beneath
+/// `root/name`, streaming each file's range rather than loading it into memory.
+///
+/// Zero-length entries are created as empty files and consume none of the data.
+pub async fn split_files(
+    source: &Path,
+    root: &Path,
+    name: &str,
+    files: &[FileEntry],
+) -> Result<()> {
+    let mut source = tokio::fs::File::open(source).await?;
+    for file in files {
+        let path = resolve_output_path(root, name, &file.path)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut output = tokio::fs::File::create(&path).await?;
+        let copied =
+            tokio::io::copy(&mut (&mut source).take(file.length as u64), &mut output).await?;
+        if copied != file.length as u64 {
+            return Err(anyhow::anyhow!(
+                "Torrent data too short for file {:?}",
+                path
+            ));
+        }
+        output.flush().await?;
+        info!("Wrote {} bytes to {}", file.length, path.display());
+    }
+    Ok(())
+}
+
+/// Checks every downloaded file that has an `md5sum` in the torrent against it.
+///
+/// `output` is the path that was passed to `Downloader::download_all`. Files
+/// without an `md5sum` are skipped; all mismatching files are listed in the error.
+pub async fn verify_md5(output: &Path, info: &TorrentInfo) -> Result<()> {
+    let mut expected = Vec::new();
+    match (info.multi_file(), &info.files) {
+        (Some(files), _) => {
+            for file in files {
+                if let Some(md5sum) = &file.md5sum {
+                    let path = resolve_output_path(output, &info.name, &file.path)?;
+                    expected.push((path, md5sum));
                 }
             }
         }
+        // A one-entry file list is saved straight to `output`
+        (None, Some(files)) => {
+            if let Some(md5sum) = files.first().and_then(|file| file.md5sum.as_ref()) {
+                expected.push((output.to_path_buf(), md5sum));
+            }
+        }
+        (None, None) => {}
+    }
+
+    let mut mismatches = Vec::new();
+    for (path, md5sum) in expected {
+        let actual = hex::encode(file_md5(&path).await?);
+        if actual.eq_ignore_ascii_case(md5sum.trim()) {
+            info!("MD5 verified for {}", path.display());
+        } else {
+            mismatches.push(format!(
+                "{} (expected {}, got {})",
+                path.display(),
+                md5sum,
+                actual
+            ));
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(anyhow::anyhow!(
+            "MD5 mismatch in {} file(s): {}",
+            mismatches.len(),
+            mismatches.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Computes the MD5 of the file at `path`, reading it in chunks.
+async fn file_md5(path: &Path) -> Result<[u8; 16]> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(&buf[..read]);
     }
+}
 
-    /// Sends interested message and waits to be unchoked by the peer.
-    async fn wait_for_unchoke(&self, peer: &mut Peer) -> Result<()> {
-        peer.send_message(Message::Interested).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        loop {
-            match peer.receive_message().await? {
-                Message::Unchoke => return Ok(()),
-                Message::KeepAlive | Message::Choke | _ => continue,
+    fn entry(length: usize, path: &[&str]) -> FileEntry {
+        FileEntry {
+            length,
+            path: path.iter().map(|p| p.to_string()).collect(),
+            md5sum: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_returns_promptly_when_cancelled_mid_piece() {
+        // A peer that unchokes but never answers block requests
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (requested, mut request_seen) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            stream.write_all(&handshake).await.unwrap();
+            stream
+                .write_all(&Message::Bitfield(vec![0x80]).to_bytes())
+                .await
+                .unwrap();
+            loop {
+                let mut len = [0u8; 4];
+                if stream.read_exact(&mut len).await.is_err() {
+                    return;
+                }
+                let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+                stream.read_exact(&mut body).await.unwrap();
+                match Message::from_bytes(&body) {
+                    Ok(Message::Interested) => stream
+                        .write_all(&Message::Unchoke.to_bytes())
+                        .await
+                        .unwrap(),
+                    Ok(Message::Request { .. }) => {
+                        let _ = requested.try_send(());
+                    }
+                    _ => {}
+                }
             }
+        });
+
+        let info = TorrentInfo {
+            name: "stalled.bin".to_string(),
+            length: 16384,
+            piece_length: 16384,
+            pieces: vec![0u8; 20].into(),
+            ..Default::default()
+        };
+        let manager =
+            DownloadManager::new(info, vec![addr], Default::default(), Default::default());
+        let (results, _completions) = mpsc::channel(1);
+        let store = Arc::new(MemoryStore::new(&manager.info));
+        let ctx = manager.worker_context(store, vec![true], results, None);
+        let queue = ctx.queue.clone();
+        let worker = tokio::spawn(DownloadManager::worker_task(addr, ctx));
+
+        request_seen.recv().await.unwrap();
+        assert!(queue.lock().await.is_empty());
+        manager.cancel.cancel();
+
+        tokio::time::timeout(Duration::from_secs(1), worker)
+            .await
+            .expect("worker should stop promptly once cancelled")
+            .unwrap();
+        // The unfinished piece is handed back for other workers
+        assert_eq!(*queue.lock().await, VecDeque::from([0]));
+    }
+
+    #[tokio::test]
+    async fn test_write_partial_places_pieces_at_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.part");
+        let info = TorrentInfo {
+            length: 10,
+            piece_length: 4,
+            pieces: vec![0u8; 60].into(),
+            ..Default::default()
+        };
+
+        write_partial(
+            &path,
+            &info,
+            &[None, Some(b"efgh".to_vec()), Some(b"ij".to_vec())],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"\0\0\0\0efghij");
+
+        // The last piece is only two bytes long
+        let err = write_partial(&path, &info, &[None, None, Some(b"ijkl".to_vec())])
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Piece 2 has 4 bytes, expected Some(2)");
+    }
+
+    #[tokio::test]
+    async fn test_failing_pieces_in_checks_each_piece_of_a_short_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.part");
+        let data = b"abcdefghijklmn".to_vec();
+        let info = TorrentInfo::from_data("out", 4, &data);
+
+        let mut contents = data.clone();
+        contents[5] ^= 0xFF;
+        contents.truncate(9);
+        std::fs::write(&path, &contents).unwrap();
+        assert_eq!(
+            failing_pieces_in(&info, &path).await.unwrap(),
+            vec![1, 2, 3]
+        );
+
+        std::fs::write(&path, &data).unwrap();
+        assert!(failing_pieces_in(&info, &path).await.unwrap().is_empty());
+    }
+
+    /// A store that returns the same bytes for every piece, whatever their size.
+    struct FixedStore(Vec<u8>);
+
+    impl PieceStore for FixedStore {
+        fn write_piece<'a>(
+            &'a self,
+            _index: usize,
+            _offset: usize,
+            _data: &'a [u8],
+        ) -> futures::future::BoxFuture<'a, Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn read_piece(&self, _index: usize) -> futures::future::BoxFuture<'_, Result<Vec<u8>>> {
+            Box::pin(async { Ok(self.0.clone()) })
+        }
+
+        fn has_piece(&self, _index: usize) -> bool {
+            true
         }
     }
 
-    /// Verifies a downloaded piece matches its expected SHA1 hash.
-    fn verify_piece(&self, piece_data: &[u8], piece_index: usize) -> Result<()> {
-        let mut hasher = sha1::Sha1::new();
-        hasher.update(piece_data);
-        let hash = hasher.finalize();
+    #[tokio::test]
+    async fn test_read_piece_checked_rejects_wrong_length() {
+        let info = TorrentInfo {
+            length: 10,
+            piece_length: 4,
+            pieces: vec![0u8; 60].into(),
+            ..Default::default()
+        };
 
-        let expected_hash =
-            &self.torrent.info.as_ref().unwrap().pieces[piece_index * 20..(piece_index + 1) * 20];
+        let full = FixedStore(b"abcd".to_vec());
+        assert_eq!(read_piece_checked(&full, &info, 0).await.unwrap(), b"abcd");
+        let err = read_piece_checked(&full, &info, 2).await.unwrap_err();
+        assert_eq!(err.to_string(), "Piece 2 has 4 bytes, expected 2");
 
-        if hash.as_slice() != expected_hash {
-            return Err(anyhow::anyhow!("Piece hash verification failed"));
+        let short = FixedStore(b"ij".to_vec());
+        assert_eq!(read_piece_checked(&short, &info, 2).await.unwrap(), b"ij");
+        assert!(read_piece_checked(&short, &info, 1).await.is_err());
+        assert!(read_piece_checked(&short, &info, 3).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_seed_partial_rejects_oversized_piece() {
+        let info = TorrentInfo {
+            length: 10,
+            piece_length: 4,
+            pieces: vec![0u8; 60].into(),
+            ..Default::default()
+        };
+        let manager = DownloadManager::new(
+            info,
+            Vec::new(),
+            PeerConfig::default(),
+            DownloadConfig::default(),
+        )
+        .with_store(Box::new(FixedStore(vec![0u8; 64])));
+
+        let err = manager.seed_partial().await.err().unwrap();
+        assert_eq!(err.to_string(), "Piece 0 has 64 bytes, expected 4");
+    }
+
+    #[tokio::test]
+    async fn test_split_files_nested_layout() {
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("data.part");
+        std::fs::write(&source, b"abcdefghi").unwrap();
+        let files = vec![
+            entry(3, &["readme.txt"]),
+            entry(4, &["sub", "dir", "data.bin"]),
+            entry(2, &["sub", "other.bin"]),
+        ];
+
+        split_files(&source, root.path(), "album", &files)
+            .await
+            .unwrap();
+
+        let base = root.path().join("album");
+        assert_eq!(std::fs::read(base.join("readme.txt")).unwrap(), b"abc");
+        assert_eq!(
+            std::fs::read(base.join("sub/dir/data.bin")).unwrap(),
+            b"defg"
+        );
+        assert_eq!(std::fs::read(base.join("sub/other.bin")).unwrap(), b"hi");
+    }
+
+    #[tokio::test]
+    async fn test_split_files_creates_zero_length_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("data.part");
+        std::fs::write(&source, b"abcdef").unwrap();
+        let files = vec![
+            entry(2, &["first.bin"]),
+            entry(0, &["empty", "placeholder"]),
+            entry(4, &["last.bin"]),
+        ];
+
+        split_files(&source, dir.path(), "album", &files)
+            .await
+            .unwrap();
+
+        let base = dir.path().join("album");
+        assert_eq!(std::fs::read(base.join("first.bin")).unwrap(), b"ab");
+        assert_eq!(
+            std::fs::metadata(base.join("empty/placeholder"))
+                .unwrap()
+                .len(),
+            0
+        );
+        assert_eq!(std::fs::read(base.join("last.bin")).unwrap(), b"cdef");
+    }
+
+    #[test]
+    fn test_resolve_output_path_rejects_traversal() {
+        let root = Path::new("/tmp/out");
+        let cases: Vec<(&str, Vec<String>)> = vec![
+            ("album", vec!["..".into(), "escape.txt".into()]),
+            ("album", vec!["a/../../b".into()]),
+            ("album", vec!["/etc/passwd".into()]),
+            ("..", vec!["file.txt".into()]),
+            ("album", vec![]),
+        ];
+
+        for (name, path) in cases {
+            assert!(
+                resolve_output_path(root, name, &path).is_err(),
+                "expected {:?}/{:?} to be rejected",
+                name,
+                path
+            );
         }
 
-        Ok(())
+        assert_eq!(
+            resolve_output_path(root, "album", &["cd1".into(), "track.flac".into()]).unwrap(),
+            Path::new("/tmp/out/album/cd1/track.flac")
+        );
+    }
+
+    #[test]
+    fn test_speed_meter_rate_and_eta() {
+        let start = Instant::now();
+        let mut meter = SpeedMeter::new(Duration::from_secs(1), start);
+        assert_eq!(meter.bytes_per_sec(start), 0);
+        assert_eq!(meter.eta(start, 1024), None);
+
+        // 64 KiB every 100 ms is 640 KiB/s, first over a partial window, then a full one
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        for ms in (100..=500).step_by(100) {
+            meter.record(at(ms), 64 * 1024);
+        }
+        let within = |rate: u64, expected: u64| rate.abs_diff(expected) <= expected / 20;
+        assert!(within(meter.bytes_per_sec(at(500)), 640 * 1024));
+        for ms in (600..=3000).step_by(100) {
+            meter.record(at(ms), 64 * 1024);
+        }
+        let rate = meter.bytes_per_sec(at(3000));
+        assert!(within(rate, 640 * 1024), "rate {}", rate);
+        let eta = meter.eta(at(3000), 1280 * 1024).unwrap();
+        assert!(eta.abs_diff(Duration::from_secs(2)) < Duration::from_millis(100));
+
+        // The rate decays once transfers stop
+        assert_eq!(meter.bytes_per_sec(at(4500)), 0);
+    }
+
+    #[test]
+    fn test_shuffle_peers_with_fixed_seed() {
+        let mut peers: Vec<SocketAddr> = (1..=5)
+            .map(|i| SocketAddr::from(([10, 0, 0, i], 6881)))
+            .collect();
+
+        shuffle_peers(&mut peers, &mut StdRng::seed_from_u64(42));
+
+        let order: Vec<u8> = peers
+            .iter()
+            .map(|addr| match addr.ip() {
+                std::net::IpAddr::V4(ip) => ip.octets()[3],
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(order, vec![1, 5, 4, 3, 2]);
+    }
+
+    #[test]
+    fn test_merge_peers_deduplicates_across_sources() {
+        let addr = |i| SocketAddr::from(([10, 0, 0, i], 6881));
+        let tracker = vec![addr(1), addr(2), addr(1)];
+        let dht = vec![addr(2), addr(3)];
+
+        let mut seen = HashSet::new();
+        let mut peers = merge_peers(&mut seen, tracker);
+        peers.extend(merge_peers(&mut seen, dht));
+        assert_eq!(peers, vec![addr(1), addr(2), addr(3)]);
+
+        // Peers already connected to this session are never returned again
+        assert!(merge_peers(&mut seen, vec![addr(3), addr(1)]).is_empty());
+    }
+
+    #[test]
+    fn test_output_destination_refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("out.bin");
+        std::fs::write(&existing, b"keep me").unwrap();
+        let info = TorrentInfo {
+            name: "movie.mkv".to_string(),
+            ..Default::default()
+        };
+
+        let err = output_destination(&existing, &info, false).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{}", err);
+        assert_eq!(
+            output_destination(&existing, &info, true).unwrap(),
+            existing
+        );
+        let fresh = dir.path().join("new.bin");
+        assert_eq!(output_destination(&fresh, &info, false).unwrap(), fresh);
+    }
+
+    #[test]
+    fn test_output_destination_saves_into_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let single = TorrentInfo {
+            name: "movie.mkv".to_string(),
+            ..Default::default()
+        };
+        let multi = TorrentInfo {
+            name: "album".to_string(),
+            files: Some(vec![entry(1, &["a"]), entry(1, &["b"])]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            output_destination(dir.path(), &single, false).unwrap(),
+            dir.path().join("movie.mkv")
+        );
+        // Multi-file torrents are laid out beneath the directory by `download_all`
+        assert_eq!(
+            output_destination(dir.path(), &multi, false).unwrap(),
+            dir.path()
+        );
+
+        std::fs::write(dir.path().join("movie.mkv"), b"").unwrap();
+        std::fs::create_dir(dir.path().join("album")).unwrap();
+        assert!(output_destination(dir.path(), &single, false).is_err());
+        assert!(output_destination(dir.path(), &multi, false).is_err());
+        assert!(output_destination(dir.path(), &multi, true).is_ok());
+
+        let unsafe_name = TorrentInfo {
+            name: "../escape".to_string(),
+            ..Default::default()
+        };
+        assert!(output_destination(dir.path(), &unsafe_name, true).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_split_files_rejects_malicious_path() {
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("data.part");
+        std::fs::write(&source, b"abc").unwrap();
+        let files = vec![entry(3, &["..", "evil.txt"])];
+
+        assert!(split_files(&source, root.path(), "album", &files)
+            .await
+            .is_err());
+        assert!(!root.path().parent().unwrap().join("evil.txt").exists());
     }
 }
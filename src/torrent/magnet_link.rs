@@ -4,14 +4,28 @@
 //! - Info hash (xt parameter)
 //! - Display name (dn parameter)
 //! - Tracker URLs (tr parameter)
+//! - Web seed URLs (ws parameter, BEP 19)
+//! - Peer addresses to connect to directly (x.pe parameter)
 //!
 //! Magnet links allow sharing torrent metadata without a .torrent file.
 //! Format: magnet:?xt=urn:btih:<info-hash>&dn=<name>&tr=<tracker-url>
+//!
+//! The info hash is either 40 hex characters or, in older links, 32 base32 characters;
+//! `info_hash_to_base32` gives the base32 form.
+//!
+//! `MagnetLink::to_uri` builds such a URI, for example from a parsed torrent file
+//! with `MagnetLink::from_torrent`.
 
 use anyhow::Result;
+use std::net::SocketAddr;
 use tracing::info;
 
+use crate::torrent::metainfo::TorrentMetainfo;
+use crate::torrent::peer::{self, PeerConfig};
+use crate::utils::percent_encode;
+
 /// Represents a parsed BitTorrent magnet link
+#[derive(Debug, Clone, PartialEq)]
 pub struct MagnetLink {
     /// 20-byte SHA-1 hash of the info dictionary
     pub info_hash: [u8; 20],
@@ -19,6 +33,10 @@ pub struct MagnetLink {
     pub name: Option<String>,
     /// Optional tracker URL for peer discovery
     pub tracker: Option<String>,
+    /// HTTP servers hosting the content (BEP 19 web seeds)
+    pub web_seeds: Vec<String>,
+    /// Peers known to have the torrent, tried without asking a tracker or the DHT
+    pub peer_hints: Vec<SocketAddr>,
 }
 
 impl MagnetLink {
@@ -37,6 +55,8 @@ impl MagnetLink {
         let mut info_hash = None;
         let mut tracker = None;
         let mut name = None;
+        let mut web_seeds = Vec::new();
+        let mut peer_hints = Vec::new();
         let query = &magnet_link["magnet:?".len()..];
         for param in query.split('&') {
             let mut parts = param.split('=');
@@ -46,16 +66,19 @@ impl MagnetLink {
             match key {
                 "xt" => {
                     if let Some(hash) = value.strip_prefix("urn:btih:") {
-                        let mut arr = [0u8; 20];
-                        for i in 0..20 {
-                            let byte = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16)?;
-                            arr[i] = byte;
-                        }
-                        info_hash = Some(arr);
+                        info_hash = Some(parse_info_hash(hash)?);
                     }
                 }
                 "tr" => tracker = Some(url_decode(value)?),
                 "dn" => name = Some(url_decode(value)?),
+                "ws" => web_seeds.push(url_decode(value)?),
+                "x.pe" => {
+                    let addr = url_decode(value)?;
+                    let addr = addr
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid peer address {:?}", addr))?;
+                    peer_hints.push(addr);
+                }
                 _ => {}
             }
         }
@@ -66,9 +89,84 @@ impl MagnetLink {
             info_hash,
             name,
             tracker,
+            web_seeds,
+            peer_hints,
         })
     }
 
+    /// Creates a magnet link carrying only `info_hash`; see the `with_*` methods
+    /// for adding the optional parameters.
+    pub fn new(info_hash: [u8; 20]) -> Self {
+        Self {
+            info_hash,
+            name: None,
+            tracker: None,
+            web_seeds: Vec::new(),
+            peer_hints: Vec::new(),
+        }
+    }
+
+    /// Sets the display name (`dn`).
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the tracker URL (`tr`).
+    pub fn with_tracker(mut self, tracker: impl Into<String>) -> Self {
+        self.tracker = Some(tracker.into());
+        self
+    }
+
+    /// Adds a web seed URL (`ws`).
+    pub fn with_web_seed(mut self, url: impl Into<String>) -> Self {
+        self.web_seeds.push(url.into());
+        self
+    }
+
+    /// Adds a peer address (`x.pe`).
+    pub fn with_peer_hint(mut self, addr: SocketAddr) -> Self {
+        self.peer_hints.push(addr);
+        self
+    }
+
+    /// Builds a magnet link for a parsed torrent file, carrying its info hash,
+    /// name, tracker and web seeds.
+    pub fn from_torrent(torrent: &TorrentMetainfo) -> Result<Self> {
+        let mut magnet = Self::new(torrent.info_hash()?);
+        if let Some(info) = torrent.info.as_ref().filter(|i| !i.name.is_empty()) {
+            magnet = magnet.with_name(&info.name);
+        }
+        if let Some(announce) = &torrent.announce {
+            magnet = magnet.with_tracker(announce);
+        }
+        for url in &torrent.url_list {
+            magnet = magnet.with_web_seed(url);
+        }
+        Ok(magnet)
+    }
+
+    /// Formats the link as a `magnet:?` URI that `parse` accepts.
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!("magnet:?xt=urn:btih:{}", hex::encode(self.info_hash));
+        if let Some(name) = &self.name {
+            uri.push_str(&format!("&dn={}", percent_encode(name.as_bytes())));
+        }
+        if let Some(tracker) = &self.tracker {
+            uri.push_str(&format!("&tr={}", percent_encode(tracker.as_bytes())));
+        }
+        for url in &self.web_seeds {
+            uri.push_str(&format!("&ws={}", percent_encode(url.as_bytes())));
+        }
+        for addr in &self.peer_hints {
+            uri.push_str(&format!(
+                "&x.pe={}",
+                percent_encode(addr.to_string().as_bytes())
+            ));
+        }
+        uri
+    }
+
     pub async fn perform_handshake(&self) -> Result<Vec<u8>> {
         let tracker = self
             .tracker
@@ -90,19 +188,23 @@ impl MagnetLink {
             return Err(anyhow::anyhow!("No peers available"));
         }
 
-        let peer_config = crate::torrent::peer::PeerConfig {
+        let peer_config = PeerConfig {
             info_hash: self.info_hash,
             ..Default::default()
         };
 
-        info!("Attempting to connect to peer: {}", peers[0]);
-        let mut peer = crate::torrent::peer::Peer::new(peers[0].to_string().parse()?, peer_config);
-
-        info!("Initiating connection...");
-        peer.connect().await?;
+        let addrs: Vec<std::net::SocketAddr> = peers
+            .iter()
+            .map(|p| std::net::SocketAddr::new(p.ip.into(), p.port))
+            .collect();
+        info!(
+            "Racing handshakes with up to {} peers",
+            peer::CONNECT_PARALLELISM
+        );
+        let connected = peer::connect_any(&addrs, peer_config, peer::CONNECT_PARALLELISM).await?;
 
         info!("Connection established, retrieving peer ID");
-        let peer_id = peer
+        let peer_id = connected
             .peer_id
             .ok_or_else(|| anyhow::anyhow!("No peer ID received"))?;
 
@@ -139,7 +241,7 @@ impl std::fmt::Display for MagnetLink {
 /// # Returns
 /// * `Result<String>` - Decoded string on success, error on invalid encoding
 fn url_decode(input: &str) -> Result<String> {
-    let mut output = String::with_capacity(input.len());
+    let mut output = Vec::with_capacity(input.len());
     let mut chars = input.chars();
 
     while let Some(c) = chars.next() {
@@ -149,12 +251,192 @@ fn url_decode(input: &str) -> Result<String> {
                 .and_then(|c1| chars.next().map(|c2| (c1, c2)))
                 .ok_or_else(|| anyhow::anyhow!("Invalid percent encoding"))?;
 
+            // Decoded bytes may be part of a multi-byte UTF-8 character
             let byte = u8::from_str_radix(&format!("{}{}", hex.0, hex.1), 16)?;
-            output.push(byte as char);
+            output.push(byte);
         } else {
-            output.push(c);
+            let mut buf = [0u8; 4];
+            output.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
         }
     }
 
-    Ok(output)
+    String::from_utf8(output).map_err(|_| anyhow::anyhow!("Percent-decoded value is not UTF-8"))
+}
+
+/// Encodes an info hash as the 32 uppercase base32 characters (RFC 4648, no
+/// padding) older magnet links and some trackers use.
+pub fn info_hash_to_base32(info_hash: &[u8; 20]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    // 160 bits split evenly into 32 characters of 5 bits, most significant first
+    let mut encoded = String::with_capacity(32);
+    let mut bits = 0u16;
+    let mut bit_count = 0;
+    for &byte in info_hash {
+        bits = (bits << 8) | byte as u16;
+        bit_count += 8;
+        while bit_count >= 5 {
+            bit_count -= 5;
+            encoded.push(ALPHABET[(bits >> bit_count) as usize & 0x1f] as char);
+        }
+        bits &= (1 << bit_count) - 1;
+    }
+    encoded
+}
+
+/// Decodes the info hash of an `xt=urn:btih:` parameter, given as 40 hex or
+/// 32 base32 characters.
+fn parse_info_hash(hash: &str) -> Result<[u8; 20]> {
+    let mut info_hash = [0u8; 20];
+    match hash.len() {
+        40 => {
+            if let Some((position, c)) = hash.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+                return Err(anyhow::anyhow!(
+                    "magnet xt info hash has non-hex character {:?} at position {}",
+                    c,
+                    position
+                ));
+            }
+            hex::decode_to_slice(hash, &mut info_hash)?;
+        }
+        32 => {
+            // Each character carries 5 bits, most significant first
+            let mut bits = 0u16;
+            let mut bit_count = 0;
+            let mut bytes = info_hash.iter_mut();
+            for (position, c) in hash.char_indices() {
+                let value = match c.to_ascii_uppercase() {
+                    c @ 'A'..='Z' => c as u16 - 'A' as u16,
+                    c @ '2'..='7' => c as u16 - '2' as u16 + 26,
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "magnet xt info hash has non-base32 character {:?} at position {}",
+                            c,
+                            position
+                        ))
+                    }
+                };
+                bits = (bits << 5) | value;
+                bit_count += 5;
+                if bit_count >= 8 {
+                    bit_count -= 8;
+                    if let Some(byte) = bytes.next() {
+                        *byte = (bits >> bit_count) as u8;
+                    }
+                    bits &= (1 << bit_count) - 1;
+                }
+            }
+        }
+        len => {
+            return Err(anyhow::anyhow!(
+                "magnet xt info hash must be 40 hex or 32 base32 chars, got {}",
+                len
+            ))
+        }
+    }
+    Ok(info_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rejects_short_info_hash() {
+        let err = MagnetLink::parse("magnet:?xt=urn:btih:ad42ce8109f54c99613ce38f9b4d87e70f24a16")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "magnet xt info hash must be 40 hex or 32 base32 chars, got 39"
+        );
+
+        // Multi-byte characters are counted in bytes but must not cause a panic
+        assert!(MagnetLink::parse("magnet:?xt=urn:btih:é").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_non_hex_info_hash() {
+        let err = MagnetLink::parse("magnet:?xt=urn:btih:ad42ce8109f54c99613ce38f9b4d87e70f24a1zz")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "magnet xt info hash has non-hex character 'z' at position 38"
+        );
+    }
+
+    #[test]
+    fn test_parse_base32_info_hash() {
+        let hex = MagnetLink::parse("magnet:?xt=urn:btih:ad42ce8109f54c99613ce38f9b4d87e70f24a165")
+            .unwrap();
+        let base32 =
+            MagnetLink::parse("magnet:?xt=urn:btih:VVBM5AIJ6VGJSYJ44OHZWTMH44HSJILF").unwrap();
+        assert_eq!(base32.info_hash, hex.info_hash);
+        assert_eq!(
+            MagnetLink::parse("magnet:?xt=urn:btih:vvbm5aij6vgjsyj44ohzwtmh44hsjilf")
+                .unwrap()
+                .info_hash,
+            hex.info_hash
+        );
+        assert!(MagnetLink::parse("magnet:?xt=urn:btih:VVBM5AIJ6VGJSYJ44OHZWTMH44HSJIL1").is_err());
+
+        assert_eq!(
+            info_hash_to_base32(&hex.info_hash),
+            "VVBM5AIJ6VGJSYJ44OHZWTMH44HSJILF"
+        );
+    }
+
+    #[test]
+    fn test_to_uri_round_trips_through_parse() {
+        let uri = "magnet:?xt=urn:btih:ad42ce8109f54c99613ce38f9b4d87e70f24a165&dn=magnet1.gif&tr=http%3A%2F%2Fbittorrent-test-tracker.codecrafters.io%2Fannounce";
+        let magnet = MagnetLink::parse(uri).unwrap();
+
+        let regenerated = magnet.to_uri();
+        assert_eq!(regenerated, uri);
+        assert_eq!(MagnetLink::parse(&regenerated).unwrap(), magnet);
+
+        let built = MagnetLink::new([0xab; 20])
+            .with_name("caf\u{e9} & friends.mkv")
+            .with_tracker("http://tracker.example/announce?passkey=a=b")
+            .with_web_seed("http://seed.example/files/")
+            .with_peer_hint("[2001:db8::1]:6881".parse().unwrap());
+        assert_eq!(MagnetLink::parse(&built.to_uri()).unwrap(), built);
+    }
+
+    #[test]
+    fn test_parse_peer_hints() {
+        let magnet = MagnetLink::parse(
+            "magnet:?xt=urn:btih:ad42ce8109f54c99613ce38f9b4d87e70f24a165&x.pe=192.0.2.10:6881&x.pe=%5B2001%3Adb8%3A%3A1%5D%3A51413",
+        )
+        .unwrap();
+
+        assert_eq!(
+            magnet.peer_hints,
+            vec![
+                "192.0.2.10:6881".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::1]:51413".parse().unwrap(),
+            ]
+        );
+        for invalid in ["192.0.2.10", "example.com:6881", "2001:db8::1:6881"] {
+            let uri = format!(
+                "magnet:?xt=urn:btih:ad42ce8109f54c99613ce38f9b4d87e70f24a165&x.pe={}",
+                invalid
+            );
+            assert!(MagnetLink::parse(&uri).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_from_torrent_file() {
+        let bytes = std::fs::read("sample.torrent").unwrap();
+        let torrent = TorrentMetainfo::from_bytes(&bytes).unwrap();
+
+        let magnet = MagnetLink::from_torrent(&torrent).unwrap();
+
+        assert_eq!(magnet.info_hash, torrent.info_hash().unwrap());
+        assert_eq!(magnet.name.as_deref(), Some("sample.txt"));
+        assert_eq!(magnet.tracker, torrent.announce);
+        assert!(magnet.to_uri().starts_with(&format!(
+            "magnet:?xt=urn:btih:{}&dn=sample.txt&tr=http%3A%2F%2F",
+            hex::encode(magnet.info_hash)
+        )));
+    }
 }
@@ -5,6 +5,8 @@
 //! - Peer state messages (choke, unchoke, interested, not interested)
 //! - Piece availability messages (have, bitfield)
 //! - Data transfer messages (request, piece, cancel)
+//! - Fast extension messages (suggest piece, have all/none, reject request, allowed fast; BEP 6)
+//! - Extension protocol messages, carrying an extended message id and payload (BEP 10)
 //!
 //! Messages are encoded to and decoded from bytes according to the BitTorrent protocol specification.
 //! Each message consists of:
@@ -12,8 +14,9 @@
 //! - Message ID (1 byte)
 //! - Payload (variable length)
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub enum Message {
+    #[default]
     KeepAlive,
     Choke,
     Unchoke,
@@ -36,19 +39,26 @@ pub enum Message {
         begin: u32,
         length: u32,
     },
-}
-
-impl Default for Message {
-    fn default() -> Self {
-        Message::KeepAlive
-    }
+    SuggestPiece(u32),
+    HaveAll,
+    HaveNone,
+    RejectRequest {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    AllowedFast(u32),
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
 }
 
 impl Message {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         match self {
-            Message::KeepAlive => return bytes,
+            Message::KeepAlive => bytes.extend_from_slice(&0u32.to_be_bytes()),
             Message::Choke => {
                 bytes.extend_from_slice(&1u32.to_be_bytes());
                 bytes.push(0);
@@ -108,6 +118,41 @@ impl Message {
                 bytes.extend_from_slice(&begin.to_be_bytes());
                 bytes.extend_from_slice(&length.to_be_bytes());
             }
+            Message::SuggestPiece(index) => {
+                bytes.extend_from_slice(&5u32.to_be_bytes());
+                bytes.push(0x0D);
+                bytes.extend_from_slice(&index.to_be_bytes());
+            }
+            Message::HaveAll => {
+                bytes.extend_from_slice(&1u32.to_be_bytes());
+                bytes.push(0x0E);
+            }
+            Message::HaveNone => {
+                bytes.extend_from_slice(&1u32.to_be_bytes());
+                bytes.push(0x0F);
+            }
+            Message::RejectRequest {
+                index,
+                begin,
+                length,
+            } => {
+                bytes.extend_from_slice(&13u32.to_be_bytes());
+                bytes.push(0x10);
+                bytes.extend_from_slice(&index.to_be_bytes());
+                bytes.extend_from_slice(&begin.to_be_bytes());
+                bytes.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::AllowedFast(index) => {
+                bytes.extend_from_slice(&5u32.to_be_bytes());
+                bytes.push(0x11);
+                bytes.extend_from_slice(&index.to_be_bytes());
+            }
+            Message::Extended { id, payload } => {
+                bytes.extend_from_slice(&(2 + payload.len() as u32).to_be_bytes());
+                bytes.push(20);
+                bytes.push(*id);
+                bytes.extend_from_slice(payload);
+            }
         }
         bytes
     }
@@ -126,11 +171,13 @@ impl Message {
             2 => Ok(Message::Interested),
             3 => Ok(Message::NotInterested),
             4 => {
+                check_payload_len("have", payload, 4)?;
                 let index = u32::from_be_bytes(payload[..4].try_into()?);
                 Ok(Message::Have(index))
             }
             5 => Ok(Message::Bitfield(payload.to_vec())),
             6 => {
+                check_payload_len("request", payload, 12)?;
                 let index = u32::from_be_bytes(payload[..4].try_into()?);
                 let begin = u32::from_be_bytes(payload[4..8].try_into()?);
                 let length = u32::from_be_bytes(payload[8..12].try_into()?);
@@ -141,6 +188,7 @@ impl Message {
                 })
             }
             7 => {
+                check_payload_len("piece", payload, 8)?;
                 let index = u32::from_be_bytes(payload[..4].try_into()?);
                 let begin = u32::from_be_bytes(payload[4..8].try_into()?);
                 let block = payload[8..].to_vec();
@@ -151,6 +199,7 @@ impl Message {
                 })
             }
             8 => {
+                check_payload_len("cancel", payload, 12)?;
                 let index = u32::from_be_bytes(payload[..4].try_into()?);
                 let begin = u32::from_be_bytes(payload[4..8].try_into()?);
                 let length = u32::from_be_bytes(payload[8..12].try_into()?);
@@ -160,7 +209,50 @@ impl Message {
                     length,
                 })
             }
+            0x0D => {
+                check_payload_len("suggest piece", payload, 4)?;
+                let index = u32::from_be_bytes(payload[..4].try_into()?);
+                Ok(Message::SuggestPiece(index))
+            }
+            0x0E => Ok(Message::HaveAll),
+            0x0F => Ok(Message::HaveNone),
+            0x10 => {
+                check_payload_len("reject request", payload, 12)?;
+                let index = u32::from_be_bytes(payload[..4].try_into()?);
+                let begin = u32::from_be_bytes(payload[4..8].try_into()?);
+                let length = u32::from_be_bytes(payload[8..12].try_into()?);
+                Ok(Message::RejectRequest {
+                    index,
+                    begin,
+                    length,
+                })
+            }
+            0x11 => {
+                check_payload_len("allowed fast", payload, 4)?;
+                let index = u32::from_be_bytes(payload[..4].try_into()?);
+                Ok(Message::AllowedFast(index))
+            }
+            20 => {
+                check_payload_len("extended", payload, 1)?;
+                Ok(Message::Extended {
+                    id: payload[0],
+                    payload: payload[1..].to_vec(),
+                })
+            }
             _ => Err(anyhow::anyhow!("Unknown message ID: {}", id)),
         }
     }
 }
+
+/// Ensures a message payload holds at least `expected` bytes before it is sliced.
+fn check_payload_len(kind: &str, payload: &[u8], expected: usize) -> anyhow::Result<()> {
+    if payload.len() < expected {
+        return Err(anyhow::anyhow!(
+            "Truncated {} message: expected at least {} payload bytes, got {}",
+            kind,
+            expected,
+            payload.len()
+        ));
+    }
+    Ok(())
+}
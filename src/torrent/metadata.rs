@@ -0,0 +1,278 @@
+//! Fetching torrent metadata from peers for magnet links.
+//!
+//! A magnet link only carries the info hash, so the info dictionary has to be
+//! downloaded from peers before any piece can be requested. This uses:
+//! - The extension protocol (BEP 10): an extended handshake in which each side
+//!   announces the message ids it wants to receive for each extension
+//! - The `ut_metadata` extension (BEP 9): the raw info dictionary, split into
+//!   16 KiB pieces that are requested one at a time
+//!
+//! The reassembled dictionary is only accepted if its SHA-1 hash matches the
+//! info hash from the magnet link.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use sha1::{Digest, Sha1};
+use tracing::info;
+
+use crate::bencode::{bvalue::BValue, tokenizer::Tokenizer, Bencode};
+
+use super::message::Message;
+use super::peer::Peer;
+
+/// Extended message id reserved for the extension handshake
+pub const EXTENSION_HANDSHAKE_ID: u8 = 0;
+
+/// Extended message id we ask peers to use for `ut_metadata` messages sent to us
+pub const UT_METADATA_ID: u8 = 1;
+
+/// Size of a single metadata piece (16 KiB)
+pub const METADATA_PIECE_SIZE: usize = 16 * 1024;
+
+/// Largest metadata we are willing to download (10 MiB)
+pub const MAX_METADATA_SIZE: usize = 10 * 1024 * 1024;
+
+/// The fields of an extension handshake that metadata exchange relies on.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExtensionHandshake {
+    /// Id the sender wants `ut_metadata` messages sent with; `None` if unsupported
+    pub ut_metadata: Option<u8>,
+    /// Size of the sender's info dictionary in bytes, if it has it
+    pub metadata_size: Option<usize>,
+}
+
+impl ExtensionHandshake {
+    /// Encodes the handshake as the payload of an extended message.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut extensions = BTreeMap::new();
+        if let Some(id) = self.ut_metadata {
+            extensions.insert(b"ut_metadata".to_vec(), BValue::Integer(id as i64));
+        }
+        let mut dict = BTreeMap::from([(b"m".to_vec(), BValue::Dict(extensions))]);
+        if let Some(size) = self.metadata_size {
+            dict.insert(b"metadata_size".to_vec(), BValue::Integer(size as i64));
+        }
+        Bencode::encode_value(&BValue::Dict(dict))
+    }
+
+    /// Decodes the payload of a peer's extension handshake.
+    pub fn from_bytes(payload: &[u8]) -> Result<Self> {
+        let bvalue = Bencode::decode_bytes(payload)?;
+        let dict = bvalue.get_dict()?;
+        let ut_metadata = match dict.get(b"m".as_slice()) {
+            Some(BValue::Dict(m)) => match m.get(b"ut_metadata".as_slice()) {
+                // An id of 0 means the extension is disabled
+                Some(BValue::Integer(id)) if (1..=255).contains(id) => Some(*id as u8),
+                _ => None,
+            },
+            _ => None,
+        };
+        let metadata_size = match dict.get(b"metadata_size".as_slice()) {
+            Some(BValue::Integer(size)) if *size > 0 => Some(*size as usize),
+            _ => None,
+        };
+        Ok(Self {
+            ut_metadata,
+            metadata_size,
+        })
+    }
+}
+
+/// A `ut_metadata` message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataMessage {
+    /// Asks for one piece of the metadata
+    Request { piece: usize },
+    /// Carries one piece of the metadata
+    Data {
+        piece: usize,
+        total_size: usize,
+        data: Vec<u8>,
+    },
+    /// The sender won't serve the requested piece
+    Reject { piece: usize },
+}
+
+impl MetadataMessage {
+    /// Encodes the message as the payload of an extended message.
+    ///
+    /// A data message is a bencoded dictionary immediately followed by the piece bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let (msg_type, piece) = match self {
+            MetadataMessage::Request { piece } => (0, piece),
+            MetadataMessage::Data { piece, .. } => (1, piece),
+            MetadataMessage::Reject { piece } => (2, piece),
+        };
+        let mut dict = BTreeMap::from([
+            (b"msg_type".to_vec(), BValue::Integer(msg_type)),
+            (b"piece".to_vec(), BValue::Integer(*piece as i64)),
+        ]);
+        if let MetadataMessage::Data { total_size, .. } = self {
+            dict.insert(b"total_size".to_vec(), BValue::Integer(*total_size as i64));
+        }
+
+        let mut bytes = Bencode::encode_value(&BValue::Dict(dict))?;
+        if let MetadataMessage::Data { data, .. } = self {
+            bytes.extend_from_slice(data);
+        }
+        Ok(bytes)
+    }
+
+    /// Decodes the payload of a `ut_metadata` extended message.
+    pub fn from_bytes(payload: &[u8]) -> Result<Self> {
+        // The piece data of a data message follows the dictionary, so only the
+        // dictionary itself is decoded
+        let mut tokenizer = Tokenizer::new(payload);
+        let header = tokenizer.next_raw_value()?;
+        let trailing = &payload[tokenizer.position()..];
+
+        let bvalue = Bencode::decode_bytes(header)?;
+        let dict = bvalue.get_dict()?;
+        let field = |key: &str| match dict.get(key.as_bytes()) {
+            Some(BValue::Integer(n)) if *n >= 0 => Ok(*n as usize),
+            _ => Err(anyhow::anyhow!("Missing or invalid {} field", key)),
+        };
+
+        let piece = field("piece")?;
+        match field("msg_type")? {
+            0 => Ok(MetadataMessage::Request { piece }),
+            1 => Ok(MetadataMessage::Data {
+                piece,
+                total_size: field("total_size")?,
+                data: trailing.to_vec(),
+            }),
+            2 => Ok(MetadataMessage::Reject { piece }),
+            other => Err(anyhow::anyhow!(
+                "Unknown ut_metadata message type {}",
+                other
+            )),
+        }
+    }
+}
+
+/// Downloads the info dictionary from a connected peer and checks it against
+/// `info_hash`, returning its raw bencoded bytes.
+///
+/// The peer must have completed the handshake and advertised the extension
+/// protocol. Messages unrelated to metadata exchange are ignored.
+pub async fn fetch_metadata(peer: &mut Peer, info_hash: [u8; 20]) -> Result<Vec<u8>> {
+    if !peer.reserved().supports_extension() {
+        return Err(anyhow::anyhow!(
+            "Peer does not support the extension protocol"
+        ));
+    }
+
+    let handshake = ExtensionHandshake {
+        ut_metadata: Some(UT_METADATA_ID),
+        metadata_size: None,
+    };
+    peer.send_message(Message::Extended {
+        id: EXTENSION_HANDSHAKE_ID,
+        payload: handshake.to_bytes()?,
+    })
+    .await?;
+
+    let remote = loop {
+        if let Message::Extended {
+            id: EXTENSION_HANDSHAKE_ID,
+            payload,
+        } = peer.receive_message().await?
+        {
+            break ExtensionHandshake::from_bytes(&payload)?;
+        }
+    };
+    let remote_id = remote
+        .ut_metadata
+        .ok_or_else(|| anyhow::anyhow!("Peer does not support ut_metadata"))?;
+    let size = remote
+        .metadata_size
+        .ok_or_else(|| anyhow::anyhow!("Peer did not report a metadata size"))?;
+    if size > MAX_METADATA_SIZE {
+        return Err(anyhow::anyhow!(
+            "Metadata size {} exceeds maximum of {} bytes",
+            size,
+            MAX_METADATA_SIZE
+        ));
+    }
+    info!("Fetching {} bytes of metadata", size);
+
+    let mut metadata = Vec::with_capacity(size);
+    for piece in 0..size.div_ceil(METADATA_PIECE_SIZE) {
+        peer.send_message(Message::Extended {
+            id: remote_id,
+            payload: MetadataMessage::Request { piece }.to_bytes()?,
+        })
+        .await?;
+
+        let data = loop {
+            let Message::Extended {
+                id: UT_METADATA_ID,
+                payload,
+            } = peer.receive_message().await?
+            else {
+                continue;
+            };
+            match MetadataMessage::from_bytes(&payload)? {
+                MetadataMessage::Data {
+                    piece: received,
+                    data,
+                    ..
+                } if received == piece => break data,
+                MetadataMessage::Reject { piece } => {
+                    return Err(anyhow::anyhow!("Peer rejected metadata piece {}", piece))
+                }
+                _ => continue,
+            }
+        };
+
+        let expected = std::cmp::min(METADATA_PIECE_SIZE, size - metadata.len());
+        if data.len() != expected {
+            return Err(anyhow::anyhow!(
+                "Metadata piece {} has length {}, expected {}",
+                piece,
+                data.len(),
+                expected
+            ));
+        }
+        metadata.extend_from_slice(&data);
+    }
+
+    if Sha1::digest(&metadata).as_slice() != info_hash {
+        return Err(anyhow::anyhow!("Metadata does not match the info hash"));
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_handshake_roundtrip() {
+        let handshake = ExtensionHandshake {
+            ut_metadata: Some(3),
+            metadata_size: Some(31235),
+        };
+        let bytes = handshake.to_bytes().unwrap();
+
+        assert_eq!(bytes, b"d1:md11:ut_metadatai3ee13:metadata_sizei31235ee");
+        assert_eq!(ExtensionHandshake::from_bytes(&bytes).unwrap(), handshake);
+    }
+
+    #[test]
+    fn test_metadata_data_message_keeps_trailing_bytes() {
+        let message = MetadataMessage::Data {
+            piece: 1,
+            total_size: 20000,
+            data: b"d4:name1:xe".to_vec(),
+        };
+        let bytes = message.to_bytes().unwrap();
+
+        assert_eq!(
+            bytes,
+            b"d8:msg_typei1e5:piecei1e10:total_sizei20000eed4:name1:xe"
+        );
+        assert_eq!(MetadataMessage::from_bytes(&bytes).unwrap(), message);
+    }
+}
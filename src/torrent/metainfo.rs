@@ -8,34 +8,76 @@
 //! The torrent file is a bencoded dictionary containing:
 //!
 //! - `announce`: URL of the tracker server that coordinates peers
+//! - `created by`, `creation date`, `comment`: Optional descriptive metadata
+//! - `url-list`: Optional web seed URL, or list of URLs, serving the content over HTTP (BEP 19)
 //! - `info`: Dictionary containing core metadata about the file(s):
-//!   - `name`: Suggested filename/directory name
+//!   - `name`: Suggested filename (single-file) or top-level directory name (multi-file)
+//!   - `name.utf-8`: Optional UTF-8 form of `name`, preferred over it when present
 //!   - `length`: Total size in bytes (single-file torrents only)
-//!   - `piece length`: Number of bytes per piece
+//!   - `files`: List of `length`/`path` entries, each with an optional `md5sum` and
+//!     an optional `path.utf-8` that is preferred over `path`
+//!     (multi-file torrents, though some single-file torrents use a one-entry list
+//!     instead of `length`)
+//!   - `piece length`: Number of bytes per piece, at most `MAX_PIECE_LENGTH`
 //!   - `pieces`: Concatenated SHA-1 hashes of all pieces
+//!   - `source`: Optional origin tag (often set by private trackers); part of the info hash
+//!   - `private`: Optional flag (1) restricting peer discovery to the trackers
 //!
-//! This implementation currently only supports single-file torrents. Multi-file torrents
-//! have a different structure in the info dictionary and are not supported.
+//! For multi-file torrents the content is treated as the concatenation of all files in
+//! list order, and `length` is set to their combined size. A `files` list with a single
+//! entry is saved like a single-file torrent (see `TorrentInfo::multi_file`).
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use std::fmt;
-
-use crate::bencode::{bvalue::BValue, Bencode};
+use tracing::warn;
 
 use super::magnet_link::MagnetLink;
+use crate::bencode::{
+    bvalue::BValue,
+    tokenizer::{self, Token, Tokenizer, UnexpectedEof},
+    Bencode,
+};
+
+/// Largest `piece length` accepted from a torrent file (128 MiB); a whole piece may
+/// be buffered while it is verified, so a crafted torrent mustn't make it huge
+pub const MAX_PIECE_LENGTH: usize = 128 * 1024 * 1024;
 
 /// Represents a parsed BitTorrent metainfo file.
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct TorrentMetainfo {
     /// URL of the tracker server
     pub announce: Option<String>,
     /// Core metadata about the torrent content
     pub info: Option<TorrentInfo>,
+    /// Name and version of the program that created the torrent
+    #[serde(rename = "created by")]
+    pub created_by: Option<String>,
+    /// Creation time as a unix timestamp
+    #[serde(rename = "creation date")]
+    pub creation_date: Option<i64>,
+    /// Free-form comment from the author
+    pub comment: Option<String>,
+    /// HTTP servers hosting the content (BEP 19 web seeds)
+    #[serde(rename = "url-list", default)]
+    pub url_list: Vec<String>,
+    /// Hash of the info dictionary as it appeared in the original bytes, or as
+    /// given by a magnet link; `info_hash` prefers it over re-encoding `info`
+    #[serde(skip)]
+    pub(crate) raw_info_hash: Option<[u8; 20]>,
 }
 
 impl TorrentMetainfo {
+    /// Builds a torrent for `info`, announced to `announce` if given.
+    pub fn new(announce: Option<String>, info: TorrentInfo) -> Self {
+        TorrentMetainfo {
+            announce,
+            info: Some(info),
+            ..Default::default()
+        }
+    }
+
     /// Parse a torrent file from its raw bytes.
     ///
     /// # Arguments
@@ -46,53 +88,54 @@ impl TorrentMetainfo {
     ///
     /// The parsed `TorrentMetainfo` structure wrapped in a `Result`
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        let bvalue = Bencode::decode_bytes(bytes)?;
+        let bvalue = Bencode::decode_with_raw(bytes, &["info"])
+            .map_err(|e| truncation_error(bytes).unwrap_or(e))?;
         match bvalue {
             BValue::Dict(dict) => {
-                let announce = match dict.get("announce") {
+                let announce = match dict.get(b"announce".as_slice()) {
                     Some(BValue::String(s)) => String::from_utf8_lossy(s).into_owned(),
                     _ => return Err(anyhow::anyhow!("Missing or invalid announce field")),
                 };
 
-                let info = match dict.get("info") {
-                    Some(BValue::Dict(info_dict)) => {
-                        let name = match info_dict.get("name") {
-                            Some(BValue::String(s)) => String::from_utf8_lossy(s).into_owned(),
-                            _ => return Err(anyhow::anyhow!("Missing or invalid name field")),
-                        };
-
-                        let length = match info_dict.get("length") {
-                            Some(BValue::Integer(n)) => *n as usize,
-                            _ => return Err(anyhow::anyhow!("Missing or invalid length field")),
-                        };
-
-                        let piece_length = match info_dict.get("piece length") {
-                            Some(BValue::Integer(n)) => *n as usize,
-                            _ => {
-                                return Err(anyhow::anyhow!(
-                                    "Missing or invalid piece length field"
-                                ))
-                            }
-                        };
-
-                        let pieces = match info_dict.get("pieces") {
-                            Some(BValue::String(s)) => s.clone(),
-                            _ => return Err(anyhow::anyhow!("Missing or invalid pieces field")),
-                        };
-
-                        TorrentInfo {
-                            name,
-                            length,
-                            piece_length,
-                            pieces,
-                        }
-                    }
+                let info = match dict.get(b"info".as_slice()) {
+                    Some(BValue::Raw(info_bytes)) => TorrentInfo::from_bytes(info_bytes)
+                        .map_err(|e| anyhow::anyhow!("Invalid info dictionary: {}", e))?,
                     _ => return Err(anyhow::anyhow!("Missing or invalid info dictionary")),
                 };
 
+                let creation_date = match dict.get(b"creation date".as_slice()) {
+                    Some(BValue::Integer(n)) => Some(*n),
+                    Some(_) => return Err(anyhow::anyhow!("Invalid creation date field")),
+                    None => None,
+                };
+
+                // Either a single URL or a list of them; empty entries are ignored
+                let url_list = match dict.get(b"url-list".as_slice()) {
+                    Some(BValue::String(url)) => vec![url.clone()],
+                    Some(BValue::List(urls)) => urls
+                        .iter()
+                        .map(|url| match url {
+                            BValue::String(url) => Ok(url.clone()),
+                            _ => Err(anyhow::anyhow!("Invalid url-list entry")),
+                        })
+                        .collect::<Result<_>>()?,
+                    Some(_) => return Err(anyhow::anyhow!("Invalid url-list field")),
+                    None => Vec::new(),
+                };
+                let url_list = url_list
+                    .into_iter()
+                    .filter(|url| !url.is_empty())
+                    .map(|url| String::from_utf8_lossy(&url).into_owned())
+                    .collect();
+
                 Ok(TorrentMetainfo {
                     announce: Some(announce),
                     info: Some(info),
+                    created_by: optional_string(&dict, "created by")?,
+                    creation_date,
+                    comment: optional_string(&dict, "comment")?,
+                    url_list,
+                    raw_info_hash: Some(Self::info_hash_from_bytes(bytes)?),
                 })
             }
             _ => Err(anyhow::anyhow!("Invalid torrent file format")),
@@ -106,9 +149,7 @@ impl TorrentMetainfo {
         let info = if magnet.name.is_some() {
             Some(TorrentInfo {
                 name: magnet.name.unwrap_or_default(),
-                length: 0,
-                piece_length: 0,
-                pieces: vec![],
+                ..Default::default()
             })
         } else {
             None
@@ -117,6 +158,20 @@ impl TorrentMetainfo {
         Ok(TorrentMetainfo {
             announce: magnet.tracker,
             info,
+            url_list: magnet.web_seeds,
+            raw_info_hash: Some(magnet.info_hash),
+            ..Default::default()
+        })
+    }
+
+    /// Builds a torrent from a bencoded info dictionary, such as metadata fetched
+    /// from peers for a magnet link. The info hash is taken from `info_bytes` as-is.
+    pub fn from_info_bytes(announce: Option<String>, info_bytes: &[u8]) -> Result<Self> {
+        Ok(TorrentMetainfo {
+            announce,
+            info: Some(TorrentInfo::from_bytes(info_bytes)?),
+            raw_info_hash: Some(Sha1::digest(info_bytes).into()),
+            ..Default::default()
         })
     }
 
@@ -125,10 +180,18 @@ impl TorrentMetainfo {
     /// This hash uniquely identifies the torrent and is used in peer protocol
     /// handshakes and tracker communications.
     ///
+    /// For torrents parsed from bytes the hash of the original `info` bytes is
+    /// returned (see `info_hash_from_bytes`); otherwise `info` is re-encoded, which
+    /// reuses its original bytes if it was parsed and drops any keys this crate
+    /// doesn't model if it was built by hand.
+    ///
     /// # Returns
     ///
     /// A 20-byte array containing the SHA-1 hash
     pub fn info_hash(&self) -> Result<[u8; 20]> {
+        if let Some(hash) = self.raw_info_hash {
+            return Ok(hash);
+        }
         let info_bvalue = BValue::from(&self.info);
         let encoded = info_bvalue.to_bytes()?;
         let mut hasher = Sha1::new();
@@ -136,63 +199,1080 @@ impl TorrentMetainfo {
         let hash = hasher.finalize();
         Ok(hash.into())
     }
+
+    /// Calculates the info hash straight from the raw bytes of a torrent file.
+    ///
+    /// Hashes the `info` dictionary exactly as it appears in `bytes`, without
+    /// decoding and re-encoding it, so keys this crate doesn't model are kept.
+    pub fn info_hash_from_bytes(bytes: &[u8]) -> Result<[u8; 20]> {
+        let info = tokenizer::dict_value(bytes, b"info")?
+            .ok_or_else(|| anyhow::anyhow!("Missing info dictionary"))?;
+        Ok(Sha1::digest(info).into())
+    }
+
+    /// Encodes the torrent as the bytes of a `.torrent` file.
+    ///
+    /// A parsed info dictionary is written back verbatim (see `info_hash`), so the
+    /// file keeps the same info hash. Fails if the info dictionary isn't known yet.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let info = self
+            .info
+            .as_ref()
+            .filter(|info| info.has_metadata())
+            .ok_or_else(|| anyhow::anyhow!("Torrent metadata is not available yet"))?;
+        let mut dict = std::collections::BTreeMap::new();
+        dict.insert(b"info".to_vec(), BValue::from(info));
+        let strings = [
+            ("announce", &self.announce),
+            ("created by", &self.created_by),
+            ("comment", &self.comment),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                dict.insert(key.into(), BValue::String(value.as_bytes().to_vec()));
+            }
+        }
+        if let Some(date) = self.creation_date {
+            dict.insert(b"creation date".to_vec(), BValue::Integer(date));
+        }
+        if !self.url_list.is_empty() {
+            let urls = self
+                .url_list
+                .iter()
+                .map(|url| BValue::String(url.as_bytes().to_vec()))
+                .collect();
+            dict.insert(b"url-list".to_vec(), BValue::List(urls));
+        }
+        BValue::Dict(dict).to_bytes()
+    }
+
+    /// Summarises the torrent as JSON, with binary fields rendered as hex.
+    pub fn to_json(&self) -> serde_json::Value {
+        let info = self.info.as_ref().map(|info| {
+            serde_json::json!({
+                "name": info.name,
+                "length": info.length,
+                "piece_length": info.piece_length,
+                "piece_hashes": info.piece_hashes().iter().map(hex::encode).collect::<Vec<_>>(),
+                "files": info.files,
+                "source": info.source,
+            })
+        });
+
+        serde_json::json!({
+            "announce": self.announce,
+            "info_hash": self.info_hash().ok().map(hex::encode),
+            "created_by": self.created_by,
+            "creation_date": self.creation_date,
+            "comment": self.comment,
+            "url_list": self.url_list,
+            "info": info,
+        })
+    }
+
+    /// Formats the torrent like its `Display` summary, with `info_hash` written in
+    /// place of the hex `Info Hash:` line, e.g. to show the hash in base32.
+    pub fn display_with_info_hash<'a>(&'a self, info_hash: &'a str) -> impl fmt::Display + 'a {
+        Summary {
+            torrent: self,
+            info_hash: Some(info_hash),
+        }
+    }
+}
+
+/// The entries of a dictionary that may have been cut off part-way.
+#[derive(Default)]
+struct PartialDict<'a> {
+    /// Keys whose values are complete
+    complete: Vec<&'a [u8]>,
+    /// The key whose value was cut off, with the bytes of the value that remain
+    cut: Option<(&'a [u8], &'a [u8])>,
+}
+
+impl PartialDict<'_> {
+    fn has(&self, key: &str) -> bool {
+        self.complete.contains(&key.as_bytes())
+    }
+}
+
+/// Scans the entries of the dictionary at the start of `input`, which must end
+/// part-way through it. Returns `None` if the dictionary is malformed or complete.
+fn scan_truncated_dict(input: &[u8]) -> Option<PartialDict<'_>> {
+    let mut tokenizer = Tokenizer::new(input);
+    match tokenizer.next_token() {
+        Ok(Some(Token::DictStart)) => {}
+        Ok(None) => return Some(PartialDict::default()),
+        _ => return None,
+    }
+
+    let mut dict = PartialDict::default();
+    loop {
+        let key = match tokenizer.next_token() {
+            Ok(Some(Token::Bytes(key))) => key,
+            Err(e) if e.is::<UnexpectedEof>() => return Some(dict),
+            _ => return None,
+        };
+        let start = tokenizer.position();
+        match tokenizer.next_raw_value() {
+            Ok(_) => dict.complete.push(key),
+            Err(e) if e.is::<UnexpectedEof>() => {
+                dict.cut = Some((key, &input[start..]));
+                return Some(dict);
+            }
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Explains a torrent file that failed to decode because it ends too soon, naming
+/// the first required field that was cut off. Returns `None` if it is malformed
+/// rather than truncated.
+fn truncation_error(bytes: &[u8]) -> Option<anyhow::Error> {
+    let truncated = |what: &str| Some(anyhow::anyhow!("Torrent file truncated: {}", what));
+
+    let top = scan_truncated_dict(bytes)?;
+    if !top.has("announce") {
+        return truncated("missing 'announce'");
+    }
+    if top.has("info") {
+        return truncated("ends after the info dict");
+    }
+    let info = match top.cut {
+        Some((b"info", value)) => scan_truncated_dict(value)?,
+        _ => return truncated("missing 'info'"),
+    };
+
+    // Checked in the sorted order keys appear in, so the first one found missing
+    // is where the file was cut
+    if !info.has("files") && !info.has("length") {
+        return truncated("missing 'length' or 'files' in info dict");
+    }
+    for key in ["name", "piece length", "pieces"] {
+        if !info.has(key) {
+            return truncated(&format!("missing '{}' in info dict", key));
+        }
+    }
+    truncated("missing the end of the info dict")
+}
+
+/// Reads an optional byte-string field from a bencoded dictionary as text.
+fn optional_string(
+    dict: &std::collections::BTreeMap<Vec<u8>, BValue>,
+    key: &str,
+) -> Result<Option<String>> {
+    match dict.get(key.as_bytes()) {
+        Some(BValue::String(s)) => Ok(Some(String::from_utf8_lossy(s).into_owned())),
+        Some(_) => Err(anyhow::anyhow!("Invalid {} field", key)),
+        None => Ok(None),
+    }
 }
 
 impl fmt::Display for TorrentMetainfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.announce {
-            Some(announce) => write!(f, "Tracker URL: {}\n", announce)?,
-            None => write!(f, "Tracker URL: None\n")?,
+        Summary {
+            torrent: self,
+            info_hash: None,
         }
-        match &self.info {
+        .fmt(f)
+    }
+}
+
+/// The text summary of a torrent, optionally with its own info hash lines.
+struct Summary<'a> {
+    torrent: &'a TorrentMetainfo,
+    /// Replaces the hex `Info Hash:` line when set
+    info_hash: Option<&'a str>,
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let torrent = self.torrent;
+        match &torrent.announce {
+            Some(announce) => writeln!(f, "Tracker URL: {}", announce)?,
+            None => writeln!(f, "Tracker URL: None")?,
+        }
+        match &torrent.info {
             Some(info) => {
-                write!(f, "Length: {}\n", info.length)?;
-                if let Ok(hash) = self.info_hash() {
-                    write!(f, "Info Hash: {}\n", hex::encode(hash))?;
+                writeln!(f, "Length: {}", info.length)?;
+                if let Ok(hash) = torrent.info_hash() {
+                    match self.info_hash {
+                        Some(lines) => writeln!(f, "{}", lines)?,
+                        None => writeln!(f, "Info Hash: {}", hex::encode(hash))?,
+                    }
+                }
+                writeln!(f, "Piece Length: {}", info.piece_length)?;
+                if let Some(created_by) = &torrent.created_by {
+                    writeln!(f, "Created By: {}", created_by)?;
+                }
+                if let Some(creation_date) = torrent.creation_date {
+                    writeln!(f, "Creation Date: {}", creation_date)?;
+                }
+                if let Some(comment) = &torrent.comment {
+                    writeln!(f, "Comment: {}", comment)?;
+                }
+                if let Some(source) = &info.source {
+                    writeln!(f, "Source: {}", source)?;
                 }
-                write!(f, "Piece Length: {}\n", info.piece_length)?;
                 writeln!(f, "Piece Hashes:")?;
                 for hash in info.piece_hashes() {
                     writeln!(f, "{}", hex::encode(hash))?;
                 }
             }
-            None => write!(f, "Info: None\n")?,
+            None => writeln!(f, "Info: None")?,
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TorrentInfo {
     pub name: String,
     pub length: usize,
     #[serde(rename = "piece length")]
     pub piece_length: usize,
-    pub pieces: Vec<u8>,
+    pub pieces: Pieces,
+    /// File list for multi-file torrents, `None` for single-file torrents
+    pub files: Option<Vec<FileEntry>>,
+    /// Origin tag, usually identifying a private tracker; changes the info hash
+    pub source: Option<String>,
+    /// Private torrents (BEP 27) must only get peers from their trackers, never the DHT
+    pub private: bool,
+    /// The bencoded dictionary this was parsed from, unknown keys included; encoding
+    /// the info emits these bytes verbatim instead of the modelled fields
+    #[serde(skip)]
+    pub(crate) raw: Option<Vec<u8>>,
+}
+
+/// The concatenated 20-byte SHA-1 hashes of a torrent's pieces.
+///
+/// Hashes are borrowed straight out of the blob, so looking one up doesn't allocate.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Pieces(Vec<u8>);
+
+impl Pieces {
+    /// Returns the expected hash of the given piece.
+    pub fn hash(&self, piece_index: usize) -> Result<&[u8; 20]> {
+        piece_index
+            .checked_mul(20)
+            .and_then(|start| self.0.get(start..start.checked_add(20)?))
+            .map(|hash| hash.try_into().unwrap())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Piece {} is out of range; the torrent has {} pieces",
+                    piece_index,
+                    self.len()
+                )
+            })
+    }
+
+    /// Number of complete hashes held.
+    pub fn len(&self) -> usize {
+        self.0.len() / 20
+    }
+
+    /// Whether no hashes are held, as for a magnet link without metadata.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over the piece hashes in order.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8; 20]> {
+        self.0.chunks_exact(20).map(|hash| hash.try_into().unwrap())
+    }
+
+    /// The raw concatenated hashes, as stored in the info dictionary.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Wraps the raw `pieces` field of an info dictionary.
+impl From<Vec<u8>> for Pieces {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+/// A single file within a multi-file torrent.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FileEntry {
+    /// Size of the file in bytes
+    pub length: usize,
+    /// Path components relative to the torrent's top-level directory
+    pub path: Vec<String>,
+    /// Optional hex MD5 of the file's contents, checked with `--verify-md5`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5sum: Option<String>,
+}
+
+/// Checks that a `piece length` read from a torrent is non-zero and at most
+/// `MAX_PIECE_LENGTH`.
+///
+/// A piece may be larger than the content: small torrents often come as a single
+/// piece of the creator's default size, such as mktorrent's 256 KiB.
+fn check_piece_length(piece_length: i64) -> Result<usize> {
+    match usize::try_from(piece_length) {
+        Ok(piece_length) if (1..=MAX_PIECE_LENGTH).contains(&piece_length) => Ok(piece_length),
+        _ => Err(anyhow::anyhow!(
+            "Piece length {} is out of bounds; expected 1 to {} bytes",
+            piece_length,
+            MAX_PIECE_LENGTH
+        )),
+    }
+}
+
+/// Parses the `files` list of a multi-file info dictionary.
+fn parse_files(list: &[BValue]) -> Result<Vec<FileEntry>> {
+    list.iter()
+        .map(|entry| {
+            let dict = entry.get_dict()?;
+            // Zero-length placeholder files are allowed and take up no room in the data
+            let length = match dict.get(b"length".as_slice()) {
+                Some(BValue::Integer(n)) if *n >= 0 => *n as usize,
+                _ => return Err(anyhow::anyhow!("Missing or invalid file length field")),
+            };
+            // Older clients store `path` in a legacy encoding next to its UTF-8 form
+            let path = match (
+                dict.get(b"path.utf-8".as_slice()),
+                dict.get(b"path".as_slice()),
+            ) {
+                (Some(BValue::List(parts)), _) | (_, Some(BValue::List(parts))) => parts
+                    .iter()
+                    .map(|p| Ok(String::from_utf8_lossy(p.get_bytes()?).into_owned()))
+                    .collect::<Result<Vec<_>>>()?,
+                _ => return Err(anyhow::anyhow!("Missing or invalid file path field")),
+            };
+            let md5sum = optional_string(dict, "md5sum")?;
+            Ok(FileEntry {
+                length,
+                path,
+                md5sum,
+            })
+        })
+        .collect()
 }
 
 impl TorrentInfo {
+    /// Describes `data` as a single file called `name`, split into pieces of
+    /// `piece_length` bytes.
+    pub fn from_data(name: impl Into<String>, piece_length: usize, data: &[u8]) -> Self {
+        TorrentInfo {
+            name: name.into(),
+            length: data.len(),
+            piece_length,
+            pieces: data
+                .chunks(piece_length)
+                .flat_map(|chunk| Sha1::digest(chunk).to_vec())
+                .collect::<Vec<_>>()
+                .into(),
+            ..Default::default()
+        }
+    }
+
+    /// Parses a bencoded info dictionary, such as metadata fetched from a peer.
+    ///
+    /// The original bytes are kept, so re-encoding the info reproduces them exactly.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bvalue = Bencode::decode_bytes(bytes)?;
+        Ok(Self {
+            raw: Some(bytes.to_vec()),
+            ..Self::from_dict(bvalue.get_dict()?)?
+        })
+    }
+
+    /// Builds the info from a decoded info dictionary, validating its piece layout.
+    fn from_dict(info_dict: &std::collections::BTreeMap<Vec<u8>, BValue>) -> Result<Self> {
+        // Older clients store `name` in a legacy encoding next to its UTF-8 form
+        let name = match (
+            info_dict.get(b"name.utf-8".as_slice()),
+            info_dict.get(b"name".as_slice()),
+        ) {
+            (Some(BValue::String(s)), _) | (_, Some(BValue::String(s))) => {
+                String::from_utf8_lossy(s).into_owned()
+            }
+            _ => return Err(anyhow::anyhow!("Missing or invalid name field")),
+        };
+
+        let files = match info_dict.get(b"files".as_slice()) {
+            Some(BValue::List(list)) => Some(parse_files(list)?),
+            Some(_) => return Err(anyhow::anyhow!("Invalid files field")),
+            None => None,
+        };
+
+        // A single file has a top-level `length`, several files a `files` list; a
+        // dictionary with both or neither doesn't say which layout it describes
+        let length = match (info_dict.get(b"length".as_slice()), &files) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "Info dictionary has both length and files; expected exactly one"
+                ))
+            }
+            (None, None) => {
+                return Err(anyhow::anyhow!(
+                    "Info dictionary has neither length nor files; expected exactly one"
+                ))
+            }
+            (Some(BValue::Integer(n)), None) if *n >= 0 => *n as usize,
+            (Some(_), None) => return Err(anyhow::anyhow!("Invalid length field")),
+            (None, Some(files)) => files
+                .iter()
+                .try_fold(0usize, |total, f| total.checked_add(f.length))
+                .ok_or_else(|| anyhow::anyhow!("Total length of the files overflows"))?,
+        };
+
+        let piece_length = match info_dict.get(b"piece length".as_slice()) {
+            Some(BValue::Integer(n)) => check_piece_length(*n)?,
+            _ => return Err(anyhow::anyhow!("Missing or invalid piece length field")),
+        };
+
+        let pieces = match info_dict.get(b"pieces".as_slice()) {
+            Some(BValue::String(s)) => Pieces::from(s.clone()),
+            _ => return Err(anyhow::anyhow!("Missing or invalid pieces field")),
+        };
+
+        let source = optional_string(info_dict, "source")?;
+        let private = matches!(
+            info_dict.get(b"private".as_slice()),
+            Some(BValue::Integer(1))
+        );
+
+        let info = TorrentInfo {
+            name,
+            length,
+            piece_length,
+            pieces,
+            files,
+            source,
+            private,
+            raw: None,
+        };
+        info.validate()?;
+        Ok(info)
+    }
+
+    /// Returns the file list if the content has to be split into several files.
+    ///
+    /// A `files` list with a single entry describes the same layout as a top-level
+    /// `length`, so it is treated as a single-file torrent and yields `None`.
+    pub fn multi_file(&self) -> Option<&[FileEntry]> {
+        self.files.as_deref().filter(|files| files.len() > 1)
+    }
+
+    /// Finds a file of a multi-file torrent by its path, components joined with
+    /// `/`, or by its index in the file list, and returns its index.
+    pub fn find_file(&self, name_or_index: &str) -> Result<usize> {
+        let files = self
+            .multi_file()
+            .ok_or_else(|| anyhow::anyhow!("{} is a single-file torrent", self.name))?;
+        if let Some(index) = files.iter().position(|f| f.path.join("/") == name_or_index) {
+            return Ok(index);
+        }
+        match name_or_index.parse::<usize>() {
+            Ok(index) if index < files.len() => Ok(index),
+            _ => Err(anyhow::anyhow!(
+                "No file {:?} in the torrent; it has {} files",
+                name_or_index,
+                files.len()
+            )),
+        }
+    }
+
+    /// Byte range of file `index` of a multi-file torrent within the concatenated
+    /// data, or `None` if there is no such file.
+    pub fn file_range(&self, index: usize) -> Option<std::ops::Range<usize>> {
+        let files = self.multi_file()?;
+        let start = files.get(..index)?.iter().map(|f| f.length).sum();
+        Some(start..start + files.get(index)?.length)
+    }
+
+    /// Indices of the pieces that hold any of the bytes in `range`; empty for an
+    /// empty range.
+    pub fn pieces_in(&self, range: &std::ops::Range<usize>) -> std::ops::Range<usize> {
+        if range.is_empty() || self.piece_length == 0 {
+            return 0..0;
+        }
+        range.start / self.piece_length..range.end.div_ceil(self.piece_length)
+    }
+
     pub fn piece_hashes(&self) -> Vec<[u8; 20]> {
-        self.pieces
-            .chunks_exact(20)
-            .map(|chunk| chunk.try_into().unwrap())
-            .collect()
+        self.pieces.iter().copied().collect()
+    }
+
+    /// Returns whether the piece layout is known. A torrent created from a magnet
+    /// link has no piece data until its metadata has been fetched.
+    pub fn has_metadata(&self) -> bool {
+        self.piece_length > 0 && !self.pieces.is_empty()
     }
 
+    /// Checks that the piece layout is self-consistent.
+    ///
+    /// The piece length must be non-zero, `pieces` must hold whole SHA-1 hashes, and
+    /// the total length must fit the piece count: more than `total_pieces - 1` full
+    /// pieces and at most `total_pieces`. Piece lengths that aren't a power of two are
+    /// legal but unusual, so they only produce a warning.
+    // `is_multiple_of` needs a newer toolchain than the pinned rust-1.77
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn validate(&self) -> Result<()> {
+        if self.piece_length == 0 {
+            return Err(anyhow::anyhow!("Piece length must be greater than zero"));
+        }
+        if self.pieces.as_bytes().len() % 20 != 0 {
+            return Err(anyhow::anyhow!(
+                "Pieces field length {} is not a multiple of 20",
+                self.pieces.as_bytes().len()
+            ));
+        }
+        let total_pieces = self.total_pieces();
+        let min = total_pieces
+            .saturating_sub(1)
+            .saturating_mul(self.piece_length);
+        let max = total_pieces.saturating_mul(self.piece_length);
+        if self.length <= min || self.length > max {
+            return Err(anyhow::anyhow!(
+                "Length {} is inconsistent with {} pieces of {} bytes",
+                self.length,
+                total_pieces,
+                self.piece_length
+            ));
+        }
+        if !self.piece_length.is_power_of_two() {
+            warn!("Piece length {} is not a power of two", self.piece_length);
+        }
+        Ok(())
+    }
+
+    /// Number of pieces in the torrent; 0 when the metadata isn't known yet.
     pub fn total_pieces(&self) -> usize {
-        self.pieces.len() / 20
+        self.pieces.len()
     }
 
-    pub fn piece_size(&self, piece_index: usize) -> usize {
+    /// Size of the given piece in bytes, accounting for a shorter last piece.
+    ///
+    /// Returns `None` if the index is out of range or the metadata isn't known yet.
+    pub fn piece_size(&self, piece_index: usize) -> Option<usize> {
+        if !self.has_metadata() || piece_index >= self.total_pieces() {
+            return None;
+        }
         if piece_index == self.total_pieces() - 1 {
             let remainder = self.length % self.piece_length;
-            if remainder == 0 {
-                self.piece_length
-            } else {
-                remainder
+            if remainder != 0 {
+                return Some(remainder);
             }
-        } else {
-            self.piece_length
         }
+        Some(self.piece_length)
+    }
+
+    /// Returns whether `data` matches the expected SHA-1 hash of the given piece.
+    pub fn verify_piece(&self, piece_index: usize, data: &[u8]) -> bool {
+        let Ok(expected_hash) = self.pieces.hash(piece_index) else {
+            return false;
+        };
+        Sha1::digest(data).as_slice() == expected_hash
+    }
+
+    /// Splits `data` into pieces and checks each one against the torrent's piece hashes.
+    ///
+    /// Returns one entry per piece, `true` when that piece is present and valid.
+    pub fn verify_data(&self, data: &[u8]) -> Vec<bool> {
+        let mut offset = 0;
+        (0..self.total_pieces())
+            .map(|piece_index| {
+                let size = self.piece_size(piece_index).unwrap_or(0);
+                let valid = data
+                    .get(offset..offset + size)
+                    .is_some_and(|piece| self.verify_piece(piece_index, piece));
+                offset += size;
+                valid
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wraps a bencoded info dictionary in a minimal torrent file.
+    fn torrent_bytes(info: &[u8]) -> Vec<u8> {
+        let mut bytes = b"d8:announce13:http://tr/ann4:info".to_vec();
+        bytes.extend_from_slice(info);
+        bytes.push(b'e');
+        bytes
+    }
+
+    /// Builds single-file info for `data` split into `piece_length` pieces.
+    fn info_for(data: &[u8], piece_length: usize) -> TorrentInfo {
+        TorrentInfo::from_data("test.bin", piece_length, data)
+    }
+
+    #[tokio::test]
+    async fn test_magnet_without_metadata_has_no_pieces() {
+        let torrent = TorrentMetainfo::from_magnet(
+            "magnet:?xt=urn:btih:d69f91e6b2ae4c542468d1073a71d4ea13879a7f&dn=sample.txt",
+        )
+        .await
+        .unwrap();
+        let info = torrent.info.as_ref().unwrap();
+
+        assert!(!info.has_metadata());
+        assert_eq!(info.total_pieces(), 0);
+        assert_eq!(info.piece_size(0), None);
+        assert!(info.verify_data(b"anything").is_empty());
+    }
+
+    #[test]
+    fn test_piece_size_handles_last_piece_and_out_of_range() {
+        let info = info_for(&[7u8; 10], 4);
+
+        assert_eq!(info.piece_size(0), Some(4));
+        assert_eq!(info.piece_size(2), Some(2));
+        assert_eq!(info.piece_size(3), None);
+    }
+
+    #[test]
+    fn test_info_hash_from_bytes_matches_decoded_hash() {
+        let bytes = std::fs::read("sample.torrent").unwrap();
+        let torrent = TorrentMetainfo::from_bytes(&bytes).unwrap();
+
+        assert_eq!(
+            TorrentMetainfo::info_hash_from_bytes(&bytes).unwrap(),
+            torrent.info_hash().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_info_hash_from_bytes_known_torrent() {
+        let bytes = std::fs::read("sample.torrent").unwrap();
+
+        assert_eq!(
+            hex::encode(TorrentMetainfo::info_hash_from_bytes(&bytes).unwrap()),
+            "d69f91e6b2ae4c542468d1073a71d4ea13879a7f"
+        );
+    }
+
+    #[test]
+    fn test_info_hash_keeps_unknown_info_keys() {
+        let info = b"d6:lengthi5e4:name5:a.txt12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa6:x-tool3:abce";
+        let torrent = TorrentMetainfo::from_bytes(&torrent_bytes(info)).unwrap();
+        let expected: [u8; 20] = Sha1::digest(info).into();
+
+        assert_eq!(torrent.info_hash().unwrap(), expected);
+        // Parsed info keeps its original bytes, so re-encoding it is lossless
+        let reencoded = TorrentMetainfo {
+            info: torrent.info.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reencoded.info_hash().unwrap(), expected);
+        let rebuilt = TorrentMetainfo {
+            info: torrent
+                .info
+                .clone()
+                .map(|info| TorrentInfo { raw: None, ..info }),
+            ..Default::default()
+        };
+        assert_ne!(rebuilt.info_hash().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_private_flag_is_parsed_and_hashed() {
+        let info = b"d6:lengthi5e4:name5:a.txt12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa7:privatei1ee";
+        let torrent = TorrentMetainfo::from_bytes(&torrent_bytes(info)).unwrap();
+
+        assert!(torrent.info.as_ref().unwrap().private);
+        assert_eq!(
+            torrent.info_hash().unwrap(),
+            <[u8; 20]>::from(Sha1::digest(info))
+        );
+    }
+
+    #[test]
+    fn test_parse_optional_metadata_fields() {
+        let info = b"d6:lengthi5e4:name5:a.txt12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa6:source5:ANIMEe";
+        let mut bytes = b"d8:announce13:http://tr/ann7:comment5:hello10:created by9:mktorrent13:creation datei1700000000e4:info".to_vec();
+        bytes.extend_from_slice(info);
+        bytes.push(b'e');
+
+        let torrent = TorrentMetainfo::from_bytes(&bytes).unwrap();
+
+        assert_eq!(torrent.created_by.as_deref(), Some("mktorrent"));
+        assert_eq!(torrent.creation_date, Some(1_700_000_000));
+        assert_eq!(torrent.comment.as_deref(), Some("hello"));
+        let parsed_info = torrent.info.as_ref().unwrap();
+        assert_eq!(parsed_info.source.as_deref(), Some("ANIME"));
+
+        // The source key is part of the info dict, so it must be part of the hash
+        assert_eq!(
+            torrent.info_hash().unwrap(),
+            <[u8; 20]>::from(Sha1::digest(info))
+        );
+
+        let display = torrent.to_string();
+        assert!(display.contains("Created By: mktorrent"));
+        assert!(display.contains("Creation Date: 1700000000"));
+        assert!(display.contains("Comment: hello"));
+        assert!(display.contains("Source: ANIME"));
+
+        let display = torrent
+            .display_with_info_hash("Info Hash: custom")
+            .to_string();
+        assert!(display.contains("\nInfo Hash: custom\n"));
+        assert!(!display.contains(&hex::encode(torrent.info_hash().unwrap())));
+
+        let json = torrent.to_json();
+        assert_eq!(json["created_by"], "mktorrent");
+        assert_eq!(json["info"]["source"], "ANIME");
+    }
+
+    #[test]
+    fn test_to_bytes_round_trips_sample_torrent() {
+        let bytes = std::fs::read("sample.torrent").unwrap();
+        let torrent = TorrentMetainfo::from_bytes(&bytes).unwrap();
+
+        let encoded = torrent.to_bytes().unwrap();
+        let reparsed = TorrentMetainfo::from_bytes(&encoded).unwrap();
+        assert_eq!(reparsed.info_hash().unwrap(), torrent.info_hash().unwrap());
+        assert_eq!(reparsed.announce, torrent.announce);
+        assert_eq!(reparsed.created_by, torrent.created_by);
+
+        assert!(TorrentMetainfo::default().to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_utf8_name_and_path_are_preferred() {
+        // "caf\xe9" is "café" in Latin-1, which isn't valid UTF-8
+        let info = b"d5:filesld6:lengthi3e4:pathl5:caf\xe9\x31e10:path.utf-8l6:caf\xc3\xa9\x31eed6:lengthi2e4:pathl1:beee4:name4:caf\xe910:name.utf-85:caf\xc3\xa912:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+        let torrent = TorrentMetainfo::from_bytes(&torrent_bytes(info)).unwrap();
+        let parsed = torrent.info.as_ref().unwrap();
+        assert_eq!(parsed.name, "café");
+        let files = parsed.files.as_ref().unwrap();
+        assert_eq!(files[0].path, vec!["café1"]);
+        // Entries without `path.utf-8` keep using `path`
+        assert_eq!(files[1].path, vec!["b"]);
+        assert_eq!(torrent.to_json()["info"]["name"], "café");
+
+        // The original bytes, legacy fields included, still make up the info hash
+        assert_eq!(
+            torrent.info_hash().unwrap(),
+            <[u8; 20]>::from(Sha1::digest(info))
+        );
+    }
+
+    #[test]
+    fn test_info_needs_exactly_one_of_length_and_files() {
+        let info = |layout: &str| {
+            format!(
+                "d{}4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae",
+                layout
+            )
+        };
+        let files = "5:filesld6:lengthi3e4:pathl1:beee";
+
+        let single = TorrentInfo::from_bytes(info("6:lengthi3e").as_bytes()).unwrap();
+        assert_eq!(single.length, 3);
+        assert!(single.files.is_none());
+        let multi = TorrentInfo::from_bytes(info(files).as_bytes()).unwrap();
+        assert_eq!(multi.length, 3);
+        assert_eq!(multi.files.unwrap().len(), 1);
+
+        let both = format!("{}6:lengthi3e", files);
+        let err = TorrentInfo::from_bytes(info(&both).as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Info dictionary has both length and files; expected exactly one"
+        );
+        let err = TorrentInfo::from_bytes(info("").as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Info dictionary has neither length nor files; expected exactly one"
+        );
+    }
+
+    #[test]
+    fn test_files_whose_total_length_overflows_are_rejected() {
+        let max = i64::MAX;
+        let info = format!(
+            "d5:filesld6:lengthi{max}e4:pathl1:aeed6:lengthi{max}e4:pathl1:beed6:lengthi{max}e4:pathl1:ceee4:name1:x12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae"
+        );
+        let err = TorrentInfo::from_bytes(info.as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "Total length of the files overflows");
+    }
+
+    #[test]
+    fn test_truncated_torrent_names_missing_field() {
+        let bytes = std::fs::read("sample.torrent").unwrap();
+        let after = |marker: &[u8]| {
+            bytes
+                .windows(marker.len())
+                .position(|w| w == marker)
+                .unwrap()
+                + marker.len()
+        };
+
+        let cases = [
+            (5, "missing 'announce'"),
+            (after(b"4:info"), "missing 'length' or 'files' in info dict"),
+            (after(b"4:name"), "missing 'name' in info dict"),
+            (
+                after(b"piece lengthi32"),
+                "missing 'piece length' in info dict",
+            ),
+            (after(b"6:pieces60:") + 10, "missing 'pieces' in info dict"),
+            (bytes.len() - 2, "missing the end of the info dict"),
+            (bytes.len() - 1, "ends after the info dict"),
+        ];
+        for (len, expected) in cases {
+            let err = TorrentMetainfo::from_bytes(&bytes[..len]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Torrent file truncated: {}", expected),
+                "truncated to {} bytes",
+                len
+            );
+        }
+
+        // Malformed input keeps the decoder's own error
+        let err = TorrentMetainfo::from_bytes(b"d8:announcex").unwrap_err();
+        assert!(!err.to_string().contains("truncated"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_url_list() {
+        let info =
+            b"d6:lengthi5e4:name5:a.txt12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+        let with_url_list = |url_list: &[u8]| {
+            let mut bytes = b"d8:announce13:http://tr/ann4:info".to_vec();
+            bytes.extend_from_slice(info);
+            bytes.extend_from_slice(b"8:url-list");
+            bytes.extend_from_slice(url_list);
+            bytes.push(b'e');
+            TorrentMetainfo::from_bytes(&bytes)
+        };
+
+        let single = with_url_list(b"14:http://seed/a/").unwrap();
+        assert_eq!(single.url_list, vec!["http://seed/a/"]);
+
+        let list = with_url_list(b"l13:http://seed/a0:13:http://seed/be").unwrap();
+        assert_eq!(list.url_list, vec!["http://seed/a", "http://seed/b"]);
+
+        assert!(with_url_list(b"i1e").is_err());
+    }
+
+    #[test]
+    fn test_verify_data_all_valid() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let info = info_for(&data, 32);
+
+        assert_eq!(info.verify_data(&data), vec![true; 4]);
+    }
+
+    #[test]
+    fn test_verify_data_detects_corrupted_piece() {
+        let mut data: Vec<u8> = (0..100u8).collect();
+        let info = info_for(&data, 32);
+        data[40] ^= 0xFF;
+
+        assert_eq!(info.verify_data(&data), vec![true, false, true, true]);
+    }
+
+    #[test]
+    fn test_verify_data_short_file() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let info = info_for(&data, 32);
+
+        assert_eq!(
+            info.verify_data(&data[..70]),
+            vec![true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn test_validate_consistent_layout() {
+        let data = vec![7u8; 40_000];
+        assert!(info_for(&data, 16384).validate().is_ok());
+        // Unusual but legal
+        assert!(info_for(&data, 10_000).validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_inconsistent_layout() {
+        let data = vec![7u8; 40_000];
+
+        let too_long = TorrentInfo {
+            length: 3 * 16384 + 1,
+            ..info_for(&data, 16384)
+        };
+        let too_short = TorrentInfo {
+            length: 2 * 16384,
+            ..info_for(&data, 16384)
+        };
+        let zero_piece_length = TorrentInfo {
+            piece_length: 0,
+            ..info_for(&data, 16384)
+        };
+
+        for info in [too_long, too_short, zero_piece_length] {
+            assert!(
+                info.validate().is_err(),
+                "{:?} should be rejected",
+                info.length
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_inconsistent_length() {
+        let mut bytes =
+            b"d8:announce3:url4:infod6:lengthi100e4:name1:x12:piece lengthi16e6:pieces20:".to_vec();
+        bytes.extend_from_slice(&[0u8; 20]);
+        bytes.extend_from_slice(b"ee");
+
+        let err = TorrentMetainfo::from_bytes(&bytes).unwrap_err();
+        assert!(err.to_string().contains("inconsistent"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_out_of_bounds_piece_length() {
+        let info = |length: u64, piece_length: i64| {
+            let mut bytes = format!(
+                "d6:lengthi{}e4:name1:x12:piece lengthi{}e6:pieces20:",
+                length, piece_length
+            )
+            .into_bytes();
+            bytes.extend_from_slice(&[0u8; 20]);
+            bytes.push(b'e');
+            TorrentInfo::from_bytes(&bytes)
+        };
+
+        // 1 TiB pieces, whether or not the content is that large
+        for length in [100, 1 << 40] {
+            let err = info(length, 1 << 40).unwrap_err();
+            assert!(err.to_string().contains("out of bounds"), "{}", err);
+        }
+        let err = info(100, 0).unwrap_err();
+        assert!(err.to_string().contains("out of bounds"), "{}", err);
+        let err = info(100, -16384).unwrap_err();
+        assert!(err.to_string().contains("out of bounds"), "{}", err);
+
+        // Small content may sit in a single piece larger than itself, as mktorrent's
+        // default 256 KiB pieces do
+        assert!(info(100, 16384).is_ok());
+        assert!(info(100_000, 262_144).is_ok());
+    }
+
+    /// Bencodes an info dictionary with the given layout keys spliced in before `name`.
+    fn info_bytes(layout: &str) -> Vec<u8> {
+        let mut bytes =
+            format!("d{}4:name5:a.txt12:piece lengthi4e6:pieces40:", layout).into_bytes();
+        bytes.extend_from_slice(&[0u8; 40]);
+        bytes.push(b'e');
+        bytes
+    }
+
+    #[test]
+    fn test_single_file_length_form() {
+        let info = TorrentInfo::from_bytes(&info_bytes("6:lengthi6e")).unwrap();
+
+        assert_eq!(info.length, 6);
+        assert_eq!(info.files, None);
+        assert_eq!(info.multi_file(), None);
+        assert_eq!(info.piece_size(0), Some(4));
+        assert_eq!(info.piece_size(1), Some(2));
+    }
+
+    #[test]
+    fn test_single_entry_files_form() {
+        let info =
+            TorrentInfo::from_bytes(&info_bytes("5:filesld6:lengthi6e4:pathl5:a.txteee")).unwrap();
+
+        assert_eq!(info.length, 6);
+        assert_eq!(info.files.as_ref().map(Vec::len), Some(1));
+        assert_eq!(info.multi_file(), None);
+        assert_eq!(info.piece_size(0), Some(4));
+        assert_eq!(info.piece_size(1), Some(2));
+        assert_eq!(info.piece_size(2), None);
+
+        let two_files = TorrentInfo::from_bytes(&info_bytes(
+            "5:filesld6:lengthi2e4:pathl1:aeed6:lengthi4e4:pathl1:beee",
+        ))
+        .unwrap();
+        assert_eq!(two_files.length, 6);
+        assert_eq!(two_files.multi_file().map(<[_]>::len), Some(2));
+    }
+
+    #[test]
+    fn test_files_with_zero_length_entry() {
+        let info = TorrentInfo::from_bytes(&info_bytes(
+            "5:filesld6:lengthi2e4:pathl1:aeed6:lengthi0e4:pathl1:beed6:lengthi4e4:pathl1:ceee",
+        ))
+        .unwrap();
+        assert_eq!(info.length, 6);
+        assert_eq!(info.multi_file().map(<[_]>::len), Some(3));
+
+        assert!(TorrentInfo::from_bytes(&info_bytes(
+            "5:filesld6:lengthi-1e4:pathl1:aeed6:lengthi7e4:pathl1:beee"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_files_md5sum_is_parsed_and_encoded() {
+        let bytes = info_bytes(
+            "5:filesld6:lengthi2e6:md5sum32:0cc175b9c0f1b6a831c399e2697726614:pathl1:aeed6:lengthi4e4:pathl1:beee",
+        );
+        let mut info = TorrentInfo::from_bytes(&bytes).unwrap();
+        let files = info.files.clone().unwrap();
+        assert_eq!(
+            files[0].md5sum.as_deref(),
+            Some("0cc175b9c0f1b6a831c399e269772661")
+        );
+        assert_eq!(files[1].md5sum, None);
+
+        // Re-encoding from the modelled fields keeps the md5sum
+        info.raw = None;
+        assert_eq!(BValue::from(&info).to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_pieces_hash_accessor() {
+        let mut bytes = vec![1u8; 20];
+        bytes.extend_from_slice(&[2u8; 20]);
+        let pieces = Pieces::from(bytes);
+
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces.hash(0).unwrap(), &[1u8; 20]);
+        assert_eq!(pieces.hash(1).unwrap(), &[2u8; 20]);
+        assert_eq!(pieces.iter().count(), 2);
+
+        let err = pieces.hash(2).unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{}", err);
+        assert!(pieces.hash(usize::MAX).is_err());
+        assert!(Pieces::default().hash(0).is_err());
+    }
+
+    #[test]
+    fn test_file_ranges_map_to_pieces() {
+        let entry = |length, name: &str| FileEntry {
+            length,
+            path: vec![name.into()],
+            md5sum: None,
+        };
+        let info = TorrentInfo {
+            length: 30,
+            piece_length: 8,
+            pieces: vec![0u8; 80].into(),
+            files: Some(vec![entry(10, "a"), entry(0, "empty"), entry(20, "b")]),
+            ..Default::default()
+        };
+
+        assert_eq!(info.find_file("b").unwrap(), 2);
+        assert_eq!(info.find_file("1").unwrap(), 1);
+        assert!(info.find_file("3").is_err());
+        assert_eq!(info.file_range(0), Some(0..10));
+        assert_eq!(info.file_range(2), Some(10..30));
+        assert_eq!(info.file_range(3), None);
+        assert_eq!(info.pieces_in(&(0..10)), 0..2);
+        assert_eq!(info.pieces_in(&(10..30)), 1..4);
+        assert_eq!(info.pieces_in(&(10..10)), 0..0);
     }
 }
@@ -1,9 +1,15 @@
+pub mod codec;
+pub mod dht;
 pub mod download;
 pub mod magnet_link;
 pub mod message;
+pub mod metadata;
 pub mod metainfo;
 pub mod peer;
+pub mod seeder;
+pub mod storage;
 pub mod tracker;
+pub mod webseed;
 
 #[cfg(test)]
 mod tests;
@@ -11,19 +11,29 @@
 //! The main types are:
 //! - `Peer`: Represents a connection to a BitTorrent peer
 //! - `PeerConfig`: Configuration options for peer connections
+//! - `PeerError`: Protocol failures callers may want to react to specifically
+//! - `ReservedBits`: Protocol extensions advertised in a handshake
 //! - `PeerId`: 20-byte unique identifier for a peer
 //! - `InfoHash`: 20-byte SHA1 hash of torrent info dictionary
 
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
 
 use anyhow::Result;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tracing::info;
+use futures::{SinkExt, StreamExt};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::Instant;
+use tokio_util::codec::Framed;
+use tracing::{info, instrument, warn};
 
 use crate::{PEER_ID, PROTOCOL};
 
+use super::codec::MessageCodec;
 use super::message::Message;
+use super::storage::PieceStore;
 
 /// A peer ID is a 20-byte unique identifier for a peer
 pub type PeerId = [u8; 20];
@@ -31,6 +41,187 @@ pub type PeerId = [u8; 20];
 /// An info hash is a 20-byte SHA1 hash of the info dictionary from a .torrent file
 pub type InfoHash = [u8; 20];
 
+/// Default cap on a single message (1 MiB), comfortably above a 16 KiB block plus header
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Size of a single block request (16 KiB)
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+
+/// Maximum number of block requests kept in flight per peer
+pub const MAX_PENDING_REQUESTS: usize = 5;
+
+/// Number of handshakes raced at once when any one connected peer will do
+pub const CONNECT_PARALLELISM: usize = 5;
+
+/// Size of the buffer incoming messages are read through (64 KiB), enough for a
+/// few blocks per read
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Peer failures that callers can match on to decide how to recover
+#[derive(Debug, thiserror::Error)]
+pub enum PeerError {
+    /// The peer never unchoked us after we declared interest
+    #[error("Peer did not unchoke us within {0:?}")]
+    ChokeTimeout(Duration),
+    /// The connection was accepted but the peer never completed the handshake
+    #[error("Peer did not complete the handshake within {0:?}")]
+    HandshakeTimeout(Duration),
+    /// The connection stayed open but the peer stopped sending mid-read
+    #[error("Peer sent nothing for {0:?}")]
+    ReadTimeout(Duration),
+    /// The peer closed the connection
+    #[error("Peer closed the connection")]
+    Disconnected,
+    /// A requested block never arrived, even after asking for it again
+    #[error("Peer did not send piece {index} offset {begin} after {attempts} requests")]
+    BlockTimeout {
+        /// Piece the block belongs to
+        index: u32,
+        /// Offset of the block within the piece
+        begin: u32,
+        /// Number of times the block was requested
+        attempts: u32,
+    },
+}
+
+/// The 8 reserved handshake bytes, in which each side advertises the protocol
+/// extensions it supports.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReservedBits(pub [u8; 8]);
+
+impl ReservedBits {
+    /// Extension protocol (BEP 10): bit 20, counting from the most significant bit
+    const EXTENSION: (usize, u8) = (5, 0x10);
+    /// Fast extension (BEP 6): bit 62
+    const FAST: (usize, u8) = (7, 0x04);
+    /// DHT (BEP 5): bit 63
+    const DHT: (usize, u8) = (7, 0x01);
+
+    /// The extensions this client advertises: the extension protocol and fast extension
+    pub fn ours() -> Self {
+        let mut bytes = [0u8; 8];
+        bytes[Self::EXTENSION.0] |= Self::EXTENSION.1;
+        bytes[Self::FAST.0] |= Self::FAST.1;
+        Self(bytes)
+    }
+
+    /// Whether the extension protocol (BEP 10) is advertised
+    pub fn supports_extension(&self) -> bool {
+        self.has(Self::EXTENSION)
+    }
+
+    /// Whether the fast extension (BEP 6) is advertised
+    pub fn supports_fast(&self) -> bool {
+        self.has(Self::FAST)
+    }
+
+    /// Whether the peer runs a DHT node (BEP 5)
+    pub fn supports_dht(&self) -> bool {
+        self.has(Self::DHT)
+    }
+
+    fn has(&self, (byte, mask): (usize, u8)) -> bool {
+        self.0[byte] & mask != 0
+    }
+}
+
+/// A block request that has been sent but not yet answered
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BlockRequest {
+    index: u32,
+    begin: u32,
+    length: u32,
+}
+
+impl From<BlockRequest> for Message {
+    fn from(request: BlockRequest) -> Self {
+        Message::Request {
+            index: request.index,
+            begin: request.begin,
+            length: request.length,
+        }
+    }
+}
+
+/// A block request awaiting its block
+#[derive(Debug, Clone, Copy)]
+struct PendingRequest {
+    request: BlockRequest,
+    /// When the request was last sent
+    sent_at: Instant,
+    /// Number of times the request was sent again after timing out
+    retries: u32,
+}
+
+/// The blocks of one or more pieces still to be requested or received
+struct BlockQueue {
+    /// Blocks not yet requested, in order; rejected or dropped blocks go back on the front
+    queue: VecDeque<BlockRequest>,
+    /// Number of blocks not yet received
+    remaining: usize,
+    /// Blocks requested again after timing out, whose first answer may still
+    /// turn up once the second one has been received
+    resent: Vec<BlockRequest>,
+}
+
+impl BlockQueue {
+    /// Splits each `(index, length)` piece into `BLOCK_SIZE` requests.
+    fn new(pieces: &[(usize, usize)]) -> Self {
+        let queue: VecDeque<BlockRequest> = pieces
+            .iter()
+            .flat_map(|&(index, len)| {
+                (0..len)
+                    .step_by(BLOCK_SIZE as usize)
+                    .map(move |begin| BlockRequest {
+                        index: index as u32,
+                        begin: begin as u32,
+                        length: std::cmp::min(len - begin, BLOCK_SIZE as usize) as u32,
+                    })
+            })
+            .collect();
+        Self {
+            remaining: queue.len(),
+            queue,
+            resent: Vec::new(),
+        }
+    }
+}
+
+/// Hashes the blocks of a piece in order, holding back blocks that arrive early
+#[derive(Default)]
+struct PieceHasher {
+    hasher: Sha1,
+    /// Number of leading bytes of the piece hashed so far
+    hashed: usize,
+    /// Blocks received ahead of `hashed`, keyed by offset
+    early: BTreeMap<usize, Vec<u8>>,
+}
+
+impl PieceHasher {
+    /// Adds the block at `begin`, hashing it and any held-back blocks it unblocks
+    fn update(&mut self, begin: usize, block: Vec<u8>) {
+        self.early.insert(begin, block);
+        while let Some(block) = self.early.remove(&self.hashed) {
+            self.hasher.update(&block);
+            self.hashed += block.len();
+        }
+    }
+
+    /// Like `update`, but hashes on tokio's blocking thread pool
+    async fn update_blocking(mut self, begin: usize, block: Vec<u8>) -> Result<Self> {
+        Ok(tokio::task::spawn_blocking(move || {
+            self.update(begin, block);
+            self
+        })
+        .await?)
+    }
+
+    /// Returns the SHA-1 digest of the blocks hashed so far
+    fn finalize(self) -> [u8; 20] {
+        self.hasher.finalize().into()
+    }
+}
+
 /// Configuration options for a peer connection
 #[derive(Debug, Clone)]
 pub struct PeerConfig {
@@ -40,6 +231,39 @@ pub struct PeerConfig {
     pub info_hash: InfoHash,
     /// The port to listen on
     pub port: u16,
+    /// Address to accept inbound connections on; the unspecified IPv6 address
+    /// listens on both IPv6 and IPv4
+    pub listen_ip: IpAddr,
+    /// Largest message length prefix accepted from the peer, in bytes
+    pub max_message_size: usize,
+    /// Idle time after which a keep-alive is sent so the peer doesn't drop us
+    pub keep_alive_interval: Duration,
+    /// How long to wait for an unchoke after declaring interest
+    pub unchoke_timeout: Duration,
+    /// How long the handshake may take once the TCP connection is up, so a peer
+    /// that accepts connections but never answers is dropped quickly
+    pub handshake_timeout: Duration,
+    /// How long a single message read may wait for data before the peer is
+    /// considered stalled; peers send keep-alives at least every two minutes
+    pub io_timeout: Duration,
+    /// Hash received blocks on tokio's blocking thread pool instead of the
+    /// connection's task, so verifying large pieces doesn't hold up the runtime
+    /// thread other peers' I/O is driven on
+    pub hash_on_blocking_pool: bool,
+    /// Local address outgoing connections are made from, e.g. to route peer traffic
+    /// through a particular interface or VPN; port 0 picks only the interface.
+    /// `None` lets the OS choose both
+    pub bind_addr: Option<SocketAddr>,
+    /// How long to wait for a requested block before asking for it again, so a
+    /// block the peer silently dropped doesn't stall the piece
+    pub block_timeout: Duration,
+    /// Number of times a block is requested again before the piece fails with
+    /// `PeerError::BlockTimeout`
+    pub block_retries: u32,
+    /// Number of pieces in the torrent, used to check the length of the peer's
+    /// bitfield. `None` until it is known, e.g. before a magnet link's metadata
+    /// has arrived, in which case bitfields aren't checked
+    pub total_pieces: Option<usize>,
 }
 
 impl Default for PeerConfig {
@@ -48,6 +272,17 @@ impl Default for PeerConfig {
             peer_id: *PEER_ID,
             info_hash: [0u8; 20],
             port: 6881,
+            listen_ip: IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            keep_alive_interval: Duration::from_secs(90),
+            unchoke_timeout: Duration::from_secs(20),
+            handshake_timeout: Duration::from_secs(10),
+            io_timeout: Duration::from_secs(150),
+            hash_on_blocking_pool: true,
+            bind_addr: None,
+            block_timeout: Duration::from_secs(15),
+            block_retries: 2,
+            total_pieces: None,
         }
     }
 }
@@ -56,9 +291,25 @@ impl Default for PeerConfig {
 #[derive(Debug)]
 pub struct Peer {
     addr: SocketAddr,
-    stream: Option<TcpStream>,
+    /// The connection, split into messages by `MessageCodec`. The handshake is
+    /// exchanged on the underlying stream before the codec reads anything
+    stream: Option<Framed<TcpStream, MessageCodec>>,
     pub peer_id: Option<PeerId>,
     config: PeerConfig,
+    /// Whether we have sent an interested message to the peer
+    interested: bool,
+    /// Whether the peer is currently choking us
+    choked: bool,
+    /// Whether the peer announced it has every piece (fast extension have all)
+    have_all: bool,
+    /// Extensions the peer's handshake advertised
+    reserved: ReservedBits,
+    /// Block requests sent to the peer that have not been answered yet
+    pending_requests: Vec<PendingRequest>,
+    /// Pieces the peer has advertised via bitfield and have messages
+    bitfield: Option<Vec<u8>>,
+    /// When we last wrote to the peer, used to schedule keep-alives
+    last_write: Instant,
 }
 
 impl Peer {
@@ -69,47 +320,133 @@ impl Peer {
             stream: None,
             peer_id: None,
             config,
+            interested: false,
+            choked: true,
+            have_all: false,
+            reserved: ReservedBits::default(),
+            pending_requests: Vec::new(),
+            bitfield: None,
+            last_write: Instant::now(),
         }
     }
 
     /// Establishes a TCP connection and performs the BitTorrent handshake
     pub async fn connect(&mut self) -> Result<()> {
         info!("Connecting to peer: {}", self.addr);
-        let stream = TcpStream::connect(self.addr).await?;
-        self.stream = Some(stream);
-        self.handshake().await?;
+        let stream = match self.config.bind_addr {
+            Some(bind_addr) => {
+                let socket = match bind_addr {
+                    SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                    SocketAddr::V6(_) => TcpSocket::new_v6()?,
+                };
+                // Connections to different peers can then share a fixed source port,
+                // as long as nothing is listening on it
+                socket.set_reuseaddr(true)?;
+                socket.bind(bind_addr)?;
+                socket.connect(self.addr).await?
+            }
+            None => TcpStream::connect(self.addr).await?,
+        };
+        // Messages are small and written one at a time; with Nagle's algorithm a
+        // request queued behind an unacknowledged `Have` waits for a delayed ACK
+        stream.set_nodelay(true)?;
+        self.stream = Some(self.framed(stream));
+        let timeout = self.config.handshake_timeout;
+        match tokio::time::timeout(timeout, self.handshake()).await {
+            Ok(result) => result,
+            Err(_) => Err(PeerError::HandshakeTimeout(timeout).into()),
+        }
+    }
+
+    /// Wraps an inbound connection accepted by a listener
+    pub fn from_stream(stream: TcpStream, config: PeerConfig) -> Result<Self> {
+        let mut peer = Self::new(stream.peer_addr()?, config);
+        peer.stream = Some(peer.framed(stream));
+        Ok(peer)
+    }
+
+    /// Wraps `stream` in a `MessageCodec` enforcing our `max_message_size`
+    fn framed(&self, stream: TcpStream) -> Framed<TcpStream, MessageCodec> {
+        let codec = MessageCodec::new(self.config.max_message_size);
+        Framed::with_capacity(stream, codec, READ_BUFFER_SIZE)
+    }
+
+    /// Answers the handshake of an inbound connection created with `from_stream`.
+    ///
+    /// The remote side speaks first; its handshake must match our info hash before we
+    /// reply with our own.
+    pub async fn accept(&mut self) -> Result<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+        let handshake = read_inbound_handshake(stream.get_mut()).await?;
+        self.accept_handshake(&handshake).await
+    }
+
+    /// Answers a handshake already read from the stream with `read_inbound_handshake`.
+    ///
+    /// Listeners serving several torrents read the handshake first to pick the
+    /// torrent, then build the peer with that torrent's config and call this.
+    pub async fn accept_handshake(&mut self, handshake: &[u8; 68]) -> Result<()> {
+        self.apply_handshake(handshake)?;
+        let message = self.handshake_message();
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+        stream.get_mut().write_all(&message).await?;
+        self.last_write = Instant::now();
+        info!("Accepted handshake from {}", self.addr);
         Ok(())
     }
 
     /// Performs the BitTorrent protocol handshake with extension protocol support
     async fn handshake(&mut self) -> Result<()> {
+        let message = self.handshake_message();
         let stream = self
             .stream
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
 
-        // Construct handshake message
+        // Send handshake
+        stream.get_mut().write_all(&message).await?;
+        self.last_write = Instant::now();
+        info!("Sent handshake message with extension protocol support");
+
+        self.read_handshake().await
+    }
+
+    /// Builds our 68-byte handshake message
+    fn handshake_message(&self) -> Vec<u8> {
         let mut message = Vec::with_capacity(68);
         message.push(19);
         message.extend_from_slice(PROTOCOL.as_bytes());
 
-        // Set reserved bytes with bit 20 set to 1 (extension protocol support)
-        let mut reserved = [0u8; 8];
-        reserved[5] = 0x10; // Set bit 20 (00010000)
-        message.extend_from_slice(&reserved);
+        message.extend_from_slice(&ReservedBits::ours().0);
 
         message.extend_from_slice(&self.config.info_hash);
-        message.extend_from_slice(&*PEER_ID);
+        message.extend_from_slice(&self.config.peer_id);
+        message
+    }
 
-        // Send handshake
-        stream.write_all(&message).await?;
-        info!("Sent handshake message with extension protocol support");
+    /// Reads and validates the remote handshake, storing the remote peer ID
+    async fn read_handshake(&mut self) -> Result<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
 
         // Read response
         let mut response = [0u8; 68];
-        stream.read_exact(&mut response).await?;
+        stream.get_mut().read_exact(&mut response).await?;
         info!("Received handshake response");
+        self.apply_handshake(&response)
+    }
 
+    /// Validates a remote handshake against our config, storing its reserved bits
+    /// and peer ID
+    fn apply_handshake(&mut self, response: &[u8; 68]) -> Result<()> {
         // Verify protocol
         if response[1..20] != *PROTOCOL.as_bytes() {
             return Err(anyhow::anyhow!("Invalid protocol in handshake response"));
@@ -120,6 +457,10 @@ impl Peer {
             return Err(anyhow::anyhow!("Info hash mismatch in handshake"));
         }
 
+        let mut reserved = [0u8; 8];
+        reserved.copy_from_slice(&response[20..28]);
+        self.reserved = ReservedBits(reserved);
+
         // Store peer ID
         let mut peer_id = [0u8; 20];
         peer_id.copy_from_slice(&response[48..68]);
@@ -134,82 +475,568 @@ impl Peer {
             .stream
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
-        let bytes = message.to_bytes();
-        stream.write_all(&bytes).await?;
+        stream.send(message).await?;
+        self.last_write = Instant::now();
         Ok(())
     }
 
+    /// Waits for the next message, sending keep-alives whenever we have not written
+    /// anything for `keep_alive_interval` so long waits don't get us disconnected
+    async fn next_message(&mut self) -> Result<Message> {
+        match self.next_message_until(None).await? {
+            Some(message) => Ok(message),
+            None => unreachable!("no deadline was set"),
+        }
+    }
+
+    /// Like `next_message`, but returns `None` if no message has started to
+    /// arrive by `deadline`.
+    async fn next_message_until(&mut self, deadline: Option<Instant>) -> Result<Option<Message>> {
+        loop {
+            let keep_alive_at = self.last_write + self.config.keep_alive_interval;
+            let stream = self
+                .stream
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+            // Once part of a message has arrived, the rest must follow within
+            // `io_timeout`
+            if !stream.read_buffer().is_empty() {
+                return self.receive_message().await.map(Some);
+            }
+
+            // Reading a frame is cancel-safe: bytes read so far stay in the codec's
+            // buffer, so no partially read message is lost to the timers
+            tokio::select! {
+                message = stream.next() => {
+                    return match message {
+                        Some(message) => message.map(Some),
+                        None => Err(PeerError::Disconnected.into()),
+                    };
+                }
+                _ = tokio::time::sleep_until(keep_alive_at) => {
+                    info!("Sending keep-alive to {}", self.addr);
+                    self.send_message(Message::KeepAlive).await?;
+                }
+                _ = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
     /// Receives and parses a BitTorrent protocol message from the peer
+    ///
+    /// The whole message must arrive within the configured `io_timeout`. Fails
+    /// with `PeerError::ReadTimeout` if the peer goes silent and with
+    /// `PeerError::Disconnected` if it closes the connection, even mid-message.
     pub async fn receive_message(&mut self) -> Result<Message> {
+        let io_timeout = self.config.io_timeout;
         let stream = self
             .stream
             .as_mut()
             .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
 
-        // Read message length (4 bytes)
-        let mut len_bytes = [0u8; 4];
-        stream.read_exact(&mut len_bytes).await?;
-        let len = u32::from_be_bytes(len_bytes);
+        match tokio::time::timeout(io_timeout, stream.next()).await {
+            Ok(Some(message)) => message,
+            Ok(None) => Err(PeerError::Disconnected.into()),
+            Err(_) => Err(PeerError::ReadTimeout(io_timeout).into()),
+        }
+    }
+
+    /// Sends an interested message (once) and waits until the peer unchokes us
+    ///
+    /// Fails with `PeerError::ChokeTimeout` if the peer stays choking for longer
+    /// than the configured `unchoke_timeout`.
+    pub async fn wait_for_unchoke(&mut self) -> Result<()> {
+        if !self.interested {
+            self.send_message(Message::Interested).await?;
+            self.interested = true;
+        }
+
+        let timeout = self.config.unchoke_timeout;
+        let wait = async {
+            while self.choked {
+                match self.next_message().await? {
+                    Message::Unchoke => self.choked = false,
+                    Message::Choke => self.choked = true,
+                    message => {
+                        self.record_availability(&message)?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(result) => result,
+            Err(_) => Err(PeerError::ChokeTimeout(timeout).into()),
+        }
+    }
+
+    /// Waits for the peer's bitfield, which must precede any other state message
+    pub async fn wait_for_bitfield(&mut self) -> Result<()> {
+        loop {
+            match self.receive_message().await? {
+                message @ (Message::Bitfield(_) | Message::HaveAll | Message::HaveNone) => {
+                    self.record_availability(&message)?;
+                    return Ok(());
+                }
+                // The extension handshake may be sent straight after the handshake
+                Message::KeepAlive | Message::Extended { .. } => continue,
+                msg => {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected message before bitfield: {:?}",
+                        msg
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Returns the extensions the peer's handshake advertised
+    pub fn reserved(&self) -> ReservedBits {
+        self.reserved
+    }
+
+    /// Returns whether the peer has advertised the given piece
+    pub fn has_piece(&self, piece_index: usize) -> bool {
+        if self.have_all {
+            return true;
+        }
+        let byte_index = piece_index / 8;
+        let bit_index = 7 - (piece_index % 8);
+        self.bitfield
+            .as_ref()
+            .and_then(|b| b.get(byte_index))
+            .is_some_and(|byte| byte & (1 << bit_index) != 0)
+    }
+
+    /// Updates our view of the peer's pieces from a bitfield, have, have all or
+    /// have none message. Returns false for any other kind of message.
+    ///
+    /// Fails if the bitfield's length doesn't match `total_pieces`, or a have
+    /// message names a piece past it.
+    fn record_availability(&mut self, message: &Message) -> Result<bool> {
+        match message {
+            Message::Bitfield(bitfield) => {
+                self.bitfield = Some(self.checked_bitfield(bitfield)?);
+                self.have_all = false;
+            }
+            Message::Have(index) => self.record_have(*index as usize)?,
+            Message::HaveAll => self.have_all = true,
+            Message::HaveNone => {
+                self.bitfield = Some(Vec::new());
+                self.have_all = false;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
 
-        if len == 0 {
-            return Ok(Message::KeepAlive);
+    /// Checks a received bitfield against `total_pieces`, when known.
+    ///
+    /// The bitfield must be exactly one bit per piece rounded up to whole bytes;
+    /// any other length is a protocol violation. Spare bits set past the last piece
+    /// are also invalid, but only logged and cleared, since no piece is lost.
+    fn checked_bitfield(&self, bitfield: &[u8]) -> Result<Vec<u8>> {
+        let mut bitfield = bitfield.to_vec();
+        let Some(total_pieces) = self.config.total_pieces else {
+            return Ok(bitfield);
+        };
+        let expected = total_pieces.div_ceil(8);
+        if bitfield.len() != expected {
+            return Err(anyhow::anyhow!(
+                "Peer sent a {} byte bitfield; expected {} bytes for {} pieces",
+                bitfield.len(),
+                expected,
+                total_pieces
+            ));
         }
 
-        // Read message body
-        let mut message_bytes = vec![0u8; len as usize];
-        stream.read_exact(&mut message_bytes).await?;
+        let spare_bits = expected * 8 - total_pieces;
+        if let Some(last) = bitfield.last_mut() {
+            let spare_mask = ((1u16 << spare_bits) - 1) as u8;
+            if *last & spare_mask != 0 {
+                warn!("Peer {} set spare bits in its bitfield", self.addr);
+                *last &= !spare_mask;
+            }
+        }
+        Ok(bitfield)
+    }
 
-        Message::from_bytes(&message_bytes)
+    /// Marks a piece announced through a have message as available
+    ///
+    /// The index must be below `total_pieces` when that is known; otherwise the
+    /// bitfield may only grow as large as a bitfield message could be, so a peer
+    /// can't make us allocate for an absurd index.
+    fn record_have(&mut self, piece_index: usize) -> Result<()> {
+        let byte_index = piece_index / 8;
+        let in_range = match self.config.total_pieces {
+            Some(total_pieces) => piece_index < total_pieces,
+            None => byte_index < self.config.max_message_size,
+        };
+        if !in_range {
+            return Err(anyhow::anyhow!(
+                "Peer sent have for out of range piece {}",
+                piece_index
+            ));
+        }
+        let bitfield = self.bitfield.get_or_insert_with(Vec::new);
+        if bitfield.len() <= byte_index {
+            bitfield.resize(byte_index + 1, 0);
+        }
+        bitfield[byte_index] |= 1 << (7 - (piece_index % 8));
+        Ok(())
     }
 
-    /// Downloads a specific piece from the peer using a series of block requests
+    /// Downloads a specific piece from the peer, keeping up to
+    /// `MAX_PENDING_REQUESTS` block requests in flight at a time
+    ///
+    /// Blocks the peer rejects (fast extension) are requested again once the
+    /// peer unchokes us.
+    #[instrument(name = "piece", skip(self, piece_length), fields(peer = %self.addr, index = piece_index))]
     pub async fn download_piece(
         &mut self,
         piece_index: usize,
         piece_length: usize,
     ) -> Result<Vec<u8>> {
-        const BLOCK_SIZE: u32 = 16 * 1024; // 16 KiB
-        let mut piece_data = Vec::new();
-        let mut remaining = piece_length;
-        let mut offset = 0;
-
-        while remaining > 0 {
-            let block_size = std::cmp::min(remaining, BLOCK_SIZE as usize);
-
-            // Request block
-            self.send_message(Message::Request {
-                index: piece_index as u32,
-                begin: offset,
-                length: block_size as u32,
-            })
-            .await?;
+        let mut pieces = self.fetch_pieces(&[(piece_index, piece_length)]).await?;
+        Ok(pieces.remove(0))
+    }
 
-            // Receive block
-            match self.receive_message().await? {
+    /// Downloads several pieces over this one connection, given as
+    /// `(index, length)` pairs, and returns their data in the same order.
+    ///
+    /// Unlike calling `download_piece` in a loop, the request pipeline runs across
+    /// piece boundaries, so a fast seeder is never left idle between pieces.
+    #[instrument(name = "pieces", skip_all, fields(peer = %self.addr, count = pieces.len()))]
+    pub async fn download_pieces(&mut self, pieces: &[(usize, usize)]) -> Result<Vec<Vec<u8>>> {
+        self.fetch_pieces(pieces).await
+    }
+
+    /// Downloads a piece straight into `store`, hashing blocks as they arrive
+    /// instead of buffering the whole piece.
+    ///
+    /// Only blocks that arrive ahead of an earlier one are held in memory until the
+    /// hash catches up. Fails if the finished piece doesn't match `expected_hash`;
+    /// the bytes already written are then left for a retry to overwrite.
+    #[instrument(name = "piece", skip(self, piece_length, expected_hash, store), fields(peer = %self.addr, index = piece_index))]
+    pub async fn download_piece_to(
+        &mut self,
+        piece_index: usize,
+        piece_length: usize,
+        expected_hash: &[u8],
+        store: &dyn PieceStore,
+    ) -> Result<()> {
+        let mut blocks = BlockQueue::new(&[(piece_index, piece_length)]);
+        let mut hasher = PieceHasher::default();
+        while let Some((_, begin, block)) = self.next_block(&mut blocks).await? {
+            store
+                .write_piece(piece_index, begin as usize, &block)
+                .await?;
+            if self.config.hash_on_blocking_pool {
+                hasher = hasher.update_blocking(begin as usize, block).await?;
+            } else {
+                hasher.update(begin as usize, block);
+            }
+        }
+
+        if hasher.finalize() != expected_hash {
+            return Err(anyhow::anyhow!(
+                "Piece {} hash verification failed",
+                piece_index
+            ));
+        }
+        Ok(())
+    }
+
+    /// Shared request loop behind `download_piece` and `download_pieces`.
+    async fn fetch_pieces(&mut self, pieces: &[(usize, usize)]) -> Result<Vec<Vec<u8>>> {
+        for (i, &(index, _)) in pieces.iter().enumerate() {
+            if pieces[..i].iter().any(|&(other, _)| other == index) {
+                return Err(anyhow::anyhow!("Piece {} requested more than once", index));
+            }
+        }
+
+        let mut buffers: Vec<Vec<u8>> = pieces.iter().map(|&(_, len)| vec![0u8; len]).collect();
+        let mut blocks = BlockQueue::new(pieces);
+        while let Some((index, begin, block)) = self.next_block(&mut blocks).await? {
+            let slot = pieces
+                .iter()
+                .position(|&(i, _)| i == index as usize)
+                .expect("pending requests only cover requested pieces");
+            let begin = begin as usize;
+            buffers[slot][begin..begin + block.len()].copy_from_slice(&block);
+        }
+
+        Ok(buffers)
+    }
+
+    /// Keeps the request pipeline for `blocks` full and returns the next block
+    /// received as `(index, begin, data)`, or `None` once every block has arrived.
+    ///
+    /// When the peer chokes us we stop requesting until it unchokes us again, giving
+    /// up after `unchoke_timeout`. Without the fast extension a choke silently drops
+    /// every outstanding request, so those blocks are queued to be requested again;
+    /// with it the peer rejects them explicitly. A block that hasn't arrived within
+    /// `block_timeout` is requested again, up to `block_retries` times.
+    async fn next_block(&mut self, blocks: &mut BlockQueue) -> Result<Option<(u32, u32, Vec<u8>)>> {
+        while blocks.remaining > 0 {
+            // Blocks already requested may still arrive after a choke, so only wait
+            // for an unchoke once nothing is outstanding
+            if self.choked && self.pending_requests.is_empty() {
+                self.wait_for_unchoke().await?;
+            }
+
+            // Keep the request pipeline full
+            while !self.choked && self.pending_requests.len() < MAX_PENDING_REQUESTS {
+                let Some(request) = blocks.queue.pop_front() else {
+                    break;
+                };
+                self.send_message(request.into()).await?;
+                self.pending_requests.push(PendingRequest {
+                    request,
+                    sent_at: Instant::now(),
+                    retries: 0,
+                });
+            }
+
+            // Receive block, skipping state messages that may arrive in between
+            let deadline = self
+                .pending_requests
+                .iter()
+                .map(|pending| pending.sent_at + self.config.block_timeout)
+                .min();
+            let Some(message) = self.next_message_until(deadline).await? else {
+                self.resend_overdue(blocks).await?;
+                continue;
+            };
+            match message {
                 Message::Piece {
                     index,
                     begin,
                     block,
                 } => {
-                    if index as usize != piece_index || begin != offset {
+                    let Some(position) = self
+                        .pending_requests
+                        .iter()
+                        .position(|p| p.request.index == index && p.request.begin == begin)
+                    else {
+                        if blocks
+                            .resent
+                            .iter()
+                            .any(|r| r.index == index && r.begin == begin)
+                        {
+                            info!(
+                                "Ignoring late duplicate of piece {} offset {}",
+                                index, begin
+                            );
+                            continue;
+                        }
                         return Err(anyhow::anyhow!("Received unexpected piece/offset"));
+                    };
+                    let request = self.pending_requests.remove(position).request;
+                    if block.len() != request.length as usize {
+                        return Err(anyhow::anyhow!(
+                            "Block at offset {} has length {}, expected {}",
+                            begin,
+                            block.len(),
+                            request.length
+                        ));
                     }
-                    piece_data.extend_from_slice(&block);
+                    blocks.remaining -= 1;
+                    return Ok(Some((index, begin, block)));
                 }
-                _ => return Err(anyhow::anyhow!("Expected piece message")),
+                Message::RejectRequest {
+                    index,
+                    begin,
+                    length,
+                } => {
+                    let request = BlockRequest {
+                        index,
+                        begin,
+                        length,
+                    };
+                    if let Some(position) = self
+                        .pending_requests
+                        .iter()
+                        .position(|p| p.request == request)
+                    {
+                        info!("Peer rejected piece {} offset {}", index, begin);
+                        blocks
+                            .queue
+                            .push_front(self.pending_requests.remove(position).request);
+                    }
+                }
+                Message::Choke => {
+                    info!("Choked by {} mid-download", self.addr);
+                    self.choked = true;
+                    if !self.reserved.supports_fast() {
+                        for pending in self.pending_requests.drain(..).rev() {
+                            blocks.queue.push_front(pending.request);
+                        }
+                    }
+                }
+                Message::KeepAlive
+                | Message::Unchoke
+                | Message::SuggestPiece(_)
+                | Message::AllowedFast(_)
+                | Message::Extended { .. } => continue,
+                message => {
+                    if !self.record_availability(&message)? {
+                        return Err(anyhow::anyhow!("Expected piece message"));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Requests every block that has been outstanding for `block_timeout` again,
+    /// failing with `PeerError::BlockTimeout` once a block has used up its retries.
+    async fn resend_overdue(&mut self, blocks: &mut BlockQueue) -> Result<()> {
+        let now = Instant::now();
+        for i in 0..self.pending_requests.len() {
+            let pending = self.pending_requests[i];
+            if pending.sent_at + self.config.block_timeout > now {
+                continue;
+            }
+            let request = pending.request;
+            if pending.retries >= self.config.block_retries {
+                return Err(PeerError::BlockTimeout {
+                    index: request.index,
+                    begin: request.begin,
+                    attempts: pending.retries + 1,
+                }
+                .into());
+            }
+            info!(
+                "Piece {} offset {} timed out, requesting it again",
+                request.index, request.begin
+            );
+            self.send_message(request.into()).await?;
+            self.pending_requests[i] = PendingRequest {
+                request,
+                sent_at: now,
+                retries: pending.retries + 1,
+            };
+            if !blocks.resent.contains(&request) {
+                blocks.resent.push(request);
             }
+        }
+        Ok(())
+    }
 
-            offset += block_size as u32;
-            remaining -= block_size;
+    /// Sends a cancel message for every block request still awaiting a response.
+    ///
+    /// Should be called whenever a piece download is abandoned part-way (e.g. on
+    /// timeout) so the peer stops sending blocks we would only discard.
+    pub async fn cancel_pending(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending_requests);
+        for PendingRequest { request, .. } in pending {
+            info!(
+                "Cancelling request for piece {} offset {}",
+                request.index, request.begin
+            );
+            self.send_message(Message::Cancel {
+                index: request.index,
+                begin: request.begin,
+                length: request.length,
+            })
+            .await?;
         }
+        Ok(())
+    }
 
-        Ok(piece_data)
+    /// Returns the number of block requests still awaiting a response
+    pub fn pending_requests(&self) -> usize {
+        self.pending_requests.len()
     }
 }
 
+/// Reads the 68-byte handshake an inbound peer opens with, without replying.
+///
+/// The info hash it asks for is at bytes `28..48`; pass the handshake to
+/// `Peer::accept_handshake` to validate it and answer.
+pub async fn read_inbound_handshake(stream: &mut (impl AsyncRead + Unpin)) -> Result<[u8; 68]> {
+    let mut handshake = [0u8; 68];
+    stream.read_exact(&mut handshake).await?;
+    info!("Received inbound handshake");
+    Ok(handshake)
+}
+
+/// Connects to the first peer in `addrs` that completes a handshake.
+///
+/// Up to `k` handshakes run at once; whenever one fails the next address is tried,
+/// so dead peers at the front of the list don't hold up the rest. Attempts still in
+/// flight when one succeeds are cancelled.
+pub async fn connect_any(addrs: &[SocketAddr], config: PeerConfig, k: usize) -> Result<Peer> {
+    let mut remaining = addrs.iter().copied();
+    let mut attempts = tokio::task::JoinSet::new();
+    let mut start_next = |attempts: &mut tokio::task::JoinSet<_>| {
+        if let Some(addr) = remaining.next() {
+            let mut peer = Peer::new(addr, config.clone());
+            attempts.spawn(async move {
+                let result = peer.connect().await;
+                (addr, result.map(|()| peer))
+            });
+        }
+    };
+    for _ in 0..k.max(1) {
+        start_next(&mut attempts);
+    }
+
+    let mut last_error = None;
+    while let Some(joined) = attempts.join_next().await {
+        match joined? {
+            (_, Ok(peer)) => return Ok(peer),
+            (addr, Err(e)) => {
+                info!("Failed to connect to peer {}: {}", addr, e);
+                last_error = Some(e);
+                start_next(&mut attempts);
+            }
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| anyhow::anyhow!("No peers to connect to"))
+        .context("Failed to connect to any peer"))
+}
+
+/// Attempts a handshake with every peer in `addrs` at once and returns each one's
+/// peer ID, or why it couldn't be reached, in the order of `addrs`.
+///
+/// Each attempt, TCP connect included, is given up after `timeout`, so a few dead
+/// peers don't hold up the rest.
+pub async fn probe_peers(
+    addrs: &[SocketAddr],
+    config: PeerConfig,
+    timeout: Duration,
+) -> Vec<(SocketAddr, Result<PeerId>)> {
+    let attempts = addrs.iter().map(|&addr| {
+        let mut peer = Peer::new(addr, config.clone());
+        async move {
+            let result = match tokio::time::timeout(timeout, peer.connect()).await {
+                Ok(Ok(())) => peer
+                    .peer_id
+                    .ok_or_else(|| anyhow::anyhow!("No peer ID received")),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(anyhow::anyhow!("No handshake within {:?}", timeout)),
+            };
+            (addr, result)
+        }
+    });
+    futures::future::join_all(attempts).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::torrent::metainfo::TorrentInfo;
+    use crate::torrent::storage::MemoryStore;
     use tokio::net::TcpListener;
     async fn setup_mock_peer() -> (Peer, TcpListener) {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -228,6 +1055,61 @@ mod tests {
         assert!(peer.peer_id.is_none());
     }
 
+    #[test]
+    fn test_bitfield_checked_against_piece_count() {
+        let config = PeerConfig {
+            total_pieces: Some(10),
+            ..Default::default()
+        };
+        let mut peer = Peer::new("127.0.0.1:8080".parse().unwrap(), config);
+
+        // Exactly two bytes, the last six bits spare
+        assert!(peer
+            .record_availability(&Message::Bitfield(vec![0xff, 0b1100_0000]))
+            .unwrap());
+        assert!((0..10).all(|i| peer.has_piece(i)));
+
+        let err = peer
+            .record_availability(&Message::Bitfield(vec![0xff, 0xc0, 0x00]))
+            .unwrap_err();
+        assert!(err.to_string().contains("expected 2 bytes for 10 pieces"));
+
+        // Spare bits are cleared rather than advertising pieces that don't exist
+        peer.record_availability(&Message::Bitfield(vec![0x00, 0b0010_0001]))
+            .unwrap();
+        assert!((0..16).all(|i| !peer.has_piece(i)));
+    }
+
+    #[test]
+    fn test_have_out_of_range_is_rejected() {
+        let config = PeerConfig {
+            total_pieces: Some(10),
+            ..Default::default()
+        };
+        let mut peer = Peer::new("127.0.0.1:8080".parse().unwrap(), config);
+        peer.record_availability(&Message::Have(9)).unwrap();
+        assert!(peer.has_piece(9));
+        let err = peer.record_availability(&Message::Have(10)).unwrap_err();
+        assert!(err.to_string().contains("out of range piece 10"), "{}", err);
+        assert!(!peer.has_piece(10));
+
+        // Without a piece count, the bitfield still can't outgrow a message
+        let mut peer = Peer::new("127.0.0.1:8080".parse().unwrap(), PeerConfig::default());
+        peer.record_availability(&Message::Have(1000)).unwrap();
+        assert!(peer.has_piece(1000));
+        peer.record_availability(&Message::Have(u32::MAX))
+            .unwrap_err();
+        assert!(peer.bitfield.as_ref().unwrap().len() < 1000);
+    }
+
+    #[test]
+    fn test_bitfield_unchecked_without_piece_count() {
+        let mut peer = Peer::new("127.0.0.1:8080".parse().unwrap(), PeerConfig::default());
+        peer.record_availability(&Message::Bitfield(vec![0xff; 3]))
+            .unwrap();
+        assert!(peer.has_piece(23));
+    }
+
     #[tokio::test]
     async fn test_handshake_protocol_mismatch() {
         let (mut peer, listener) = setup_mock_peer().await;
@@ -268,4 +1150,710 @@ mod tests {
             .await
             .expect_err("Should fail with info hash mismatch");
     }
+
+    #[tokio::test]
+    async fn test_receive_message_rejects_oversized_length() {
+        let (mut peer, listener) = setup_mock_peer().await;
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            stream.write_all(&handshake).await.unwrap();
+
+            // Advertise a ~4 GiB message without ever sending its body
+            stream.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+        });
+
+        peer.connect().await.unwrap();
+        let err = peer
+            .receive_message()
+            .await
+            .expect_err("Should reject oversized message");
+        assert!(err.to_string().contains("exceeds maximum"));
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_sent_while_waiting_for_unchoke() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = PeerConfig {
+            keep_alive_interval: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let mut peer = Peer::new(listener.local_addr().unwrap(), config);
+
+        let mock = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            stream.write_all(&handshake).await.unwrap();
+
+            // Interested, then silence from our side until a keep-alive arrives
+            let mut interested = [0u8; 5];
+            stream.read_exact(&mut interested).await.unwrap();
+            assert_eq!(interested, [0, 0, 0, 1, 2]);
+
+            let mut keep_alive = [0xFFu8; 4];
+            stream.read_exact(&mut keep_alive).await.unwrap();
+            (keep_alive, stream)
+        });
+
+        peer.connect().await.unwrap();
+        let waited =
+            tokio::time::timeout(Duration::from_millis(500), peer.wait_for_unchoke()).await;
+        assert!(
+            waited.is_err(),
+            "Peer never unchokes, so the wait should time out"
+        );
+
+        let (keep_alive, _stream) = mock.await.unwrap();
+        assert_eq!(keep_alive, [0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_times_out_when_peer_never_answers_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = PeerConfig {
+            handshake_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut peer = Peer::new(listener.local_addr().unwrap(), config);
+
+        tokio::spawn(async move {
+            // Accept the connection but never reply to the handshake
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(stream);
+        });
+
+        let started = Instant::now();
+        let err = peer.connect().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(PeerError::HandshakeTimeout(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_probe_peers_reports_each_peer_within_timeout() {
+        let answering = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addrs = [
+            answering.local_addr().unwrap(),
+            silent.local_addr().unwrap(),
+        ];
+
+        tokio::spawn(async move {
+            let (mut stream, _) = answering.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            handshake[48..68].copy_from_slice(b"-XX0001-remotepeerid");
+            stream.write_all(&handshake).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+        tokio::spawn(async move {
+            // Accept the connection but never reply to the handshake
+            let (_stream, _) = silent.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let started = Instant::now();
+        let results = probe_peers(&addrs, PeerConfig::default(), Duration::from_millis(300)).await;
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, addrs[0]);
+        assert_eq!(results[0].1.as_ref().unwrap(), b"-XX0001-remotepeerid");
+        assert_eq!(results[1].0, addrs[1]);
+        let err = results[1].1.as_ref().unwrap_err();
+        assert!(err.to_string().contains("No handshake within"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_unchoke_times_out_when_peer_stays_choking() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = PeerConfig {
+            unchoke_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut peer = Peer::new(listener.local_addr().unwrap(), config);
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            stream.write_all(&handshake).await.unwrap();
+
+            // Advertise every piece but never unchoke
+            stream
+                .write_all(&Message::Bitfield(vec![0xFF]).to_bytes())
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(stream);
+        });
+
+        peer.connect().await.unwrap();
+        let err = peer
+            .download_piece(0, BLOCK_SIZE as usize)
+            .await
+            .expect_err("Should give up on a peer that never unchokes");
+
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(PeerError::ChokeTimeout(_))
+        ));
+        assert!(peer.has_piece(0));
+    }
+
+    #[tokio::test]
+    async fn test_download_piece_rerequests_rejected_block() {
+        let (mut peer, listener) = setup_mock_peer().await;
+
+        tokio::spawn(async move {
+            let mut stream = accept_unchoked(listener).await;
+
+            // Reject the first request, then serve the same block when it is asked for again
+            for attempt in 0..2 {
+                let mut request = [0u8; 17];
+                stream.read_exact(&mut request).await.unwrap();
+                let Message::Request {
+                    index,
+                    begin,
+                    length,
+                } = Message::from_bytes(&request[4..]).unwrap()
+                else {
+                    panic!("expected a request");
+                };
+                let reply = if attempt == 0 {
+                    Message::RejectRequest {
+                        index,
+                        begin,
+                        length,
+                    }
+                } else {
+                    Message::Piece {
+                        index,
+                        begin,
+                        block: vec![7u8; length as usize],
+                    }
+                };
+                stream.write_all(&reply.to_bytes()).await.unwrap();
+            }
+        });
+
+        peer.connect().await.unwrap();
+        peer.wait_for_bitfield().await.unwrap();
+        assert!(peer.has_piece(1000));
+
+        let data = tokio::time::timeout(
+            Duration::from_secs(2),
+            peer.download_piece(0, BLOCK_SIZE as usize),
+        )
+        .await
+        .expect("rejected block should be requested again")
+        .unwrap();
+        assert_eq!(data, vec![7u8; BLOCK_SIZE as usize]);
+    }
+
+    /// Accepts one connection, unchokes after the handshake and answers block
+    /// requests, ignoring the first `ignored` of them.
+    async fn serve_dropping_requests(listener: TcpListener, ignored: usize) {
+        let mut stream = accept_unchoked(listener).await;
+
+        for received in 0.. {
+            let mut request = [0u8; 17];
+            if stream.read_exact(&mut request).await.is_err() {
+                return;
+            }
+            let Message::Request {
+                index,
+                begin,
+                length,
+            } = Message::from_bytes(&request[4..]).unwrap()
+            else {
+                panic!("expected a request");
+            };
+            if received < ignored {
+                continue;
+            }
+            let piece = Message::Piece {
+                index,
+                begin,
+                block: vec![9u8; length as usize],
+            };
+            stream.write_all(&piece.to_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_piece_requests_dropped_block_again() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = PeerConfig {
+            block_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut peer = Peer::new(listener.local_addr().unwrap(), config);
+        tokio::spawn(serve_dropping_requests(listener, 1));
+
+        peer.connect().await.unwrap();
+        peer.wait_for_bitfield().await.unwrap();
+        let data = peer.download_piece(0, 1000).await.unwrap();
+        assert_eq!(data, vec![9u8; 1000]);
+    }
+
+    #[tokio::test]
+    async fn test_download_piece_gives_up_after_block_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = PeerConfig {
+            block_timeout: Duration::from_millis(100),
+            block_retries: 2,
+            ..Default::default()
+        };
+        let mut peer = Peer::new(listener.local_addr().unwrap(), config);
+        tokio::spawn(serve_dropping_requests(listener, usize::MAX));
+
+        peer.connect().await.unwrap();
+        peer.wait_for_bitfield().await.unwrap();
+        let err = peer.download_piece(0, 1000).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(PeerError::BlockTimeout {
+                index: 0,
+                begin: 0,
+                attempts: 3
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_download_piece_checks_block_lengths() {
+        let (mut peer, listener) = setup_mock_peer().await;
+
+        tokio::spawn(async move {
+            let mut stream = accept_unchoked(listener).await;
+
+            // Serve the first request in full, then one byte too many
+            for extra in 0..2 {
+                let mut request = [0u8; 17];
+                stream.read_exact(&mut request).await.unwrap();
+                let Message::Request {
+                    index,
+                    begin,
+                    length,
+                } = Message::from_bytes(&request[4..]).unwrap()
+                else {
+                    panic!("expected a request");
+                };
+                let piece = Message::Piece {
+                    index,
+                    begin,
+                    block: vec![7u8; length as usize + extra],
+                };
+                stream.write_all(&piece.to_bytes()).await.unwrap();
+            }
+        });
+
+        peer.connect().await.unwrap();
+        peer.wait_for_bitfield().await.unwrap();
+
+        // A short last piece comes back at exactly its own size
+        let data = peer.download_piece(3, 1000).await.unwrap();
+        assert_eq!(data, vec![7u8; 1000]);
+
+        let err = peer.download_piece(4, 1000).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Block at offset 0 has length 1001, expected 1000"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_pieces_pipelines_across_pieces() {
+        let (mut peer, listener) = setup_mock_peer().await;
+        let piece_length = 2 * BLOCK_SIZE as usize;
+
+        let mock = tokio::spawn(async move {
+            let mut stream = accept_unchoked(listener).await;
+
+            // Collect a full pipeline before answering anything, then reply newest
+            // first so blocks of different pieces arrive interleaved
+            let mut served = 0;
+            let mut first_batch = Vec::new();
+            while served < 6 {
+                let batch = if served == 0 { MAX_PENDING_REQUESTS } else { 1 };
+                let mut requests = Vec::new();
+                for _ in 0..batch {
+                    let mut request = [0u8; 17];
+                    stream.read_exact(&mut request).await.unwrap();
+                    let Message::Request {
+                        index,
+                        begin,
+                        length,
+                    } = Message::from_bytes(&request[4..]).unwrap()
+                    else {
+                        panic!("expected a request");
+                    };
+                    requests.push((index, begin, length));
+                }
+                if served == 0 {
+                    first_batch = requests.iter().map(|&(index, _, _)| index).collect();
+                }
+                for &(index, begin, length) in requests.iter().rev() {
+                    let piece = Message::Piece {
+                        index,
+                        begin,
+                        block: vec![index as u8 + 1; length as usize],
+                    };
+                    stream.write_all(&piece.to_bytes()).await.unwrap();
+                    served += 1;
+                }
+            }
+            first_batch
+        });
+
+        peer.connect().await.unwrap();
+        peer.wait_for_bitfield().await.unwrap();
+        let pieces = tokio::time::timeout(
+            Duration::from_secs(2),
+            peer.download_pieces(&[(0, piece_length), (1, piece_length), (2, piece_length)]),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        for (i, data) in pieces.iter().enumerate() {
+            assert_eq!(*data, vec![i as u8 + 1; piece_length]);
+        }
+        // Requests for all three pieces were outstanding before any block arrived
+        assert_eq!(mock.await.unwrap(), vec![0, 0, 1, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_download_piece_resumes_after_mid_download_choke() {
+        let (mut peer, listener) = setup_mock_peer().await;
+        let piece_length = 2 * BLOCK_SIZE as usize;
+
+        let mock = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            // Answer without the fast extension, so a choke drops pending requests
+            handshake[27] &= !0x04;
+            stream.write_all(&handshake).await.unwrap();
+            stream
+                .write_all(&Message::Bitfield(vec![0x80]).to_bytes())
+                .await
+                .unwrap();
+
+            let mut interested = [0u8; 5];
+            stream.read_exact(&mut interested).await.unwrap();
+            stream
+                .write_all(&Message::Unchoke.to_bytes())
+                .await
+                .unwrap();
+
+            async fn read_request(stream: &mut TcpStream) -> (u32, u32) {
+                let mut request = [0u8; 17];
+                stream.read_exact(&mut request).await.unwrap();
+                match Message::from_bytes(&request[4..]).unwrap() {
+                    Message::Request { begin, length, .. } => (begin, length),
+                    other => panic!("expected a request, got {:?}", other),
+                }
+            }
+            let first = read_request(&mut stream).await;
+            let _second = read_request(&mut stream).await;
+
+            // Serve one block, then choke and drop the other request
+            let block = Message::Piece {
+                index: 0,
+                begin: first.0,
+                block: vec![3u8; first.1 as usize],
+            };
+            stream.write_all(&block.to_bytes()).await.unwrap();
+            stream.write_all(&Message::Choke.to_bytes()).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            stream
+                .write_all(&Message::Unchoke.to_bytes())
+                .await
+                .unwrap();
+
+            let again = read_request(&mut stream).await;
+            let block = Message::Piece {
+                index: 0,
+                begin: again.0,
+                block: vec![3u8; again.1 as usize],
+            };
+            stream.write_all(&block.to_bytes()).await.unwrap();
+            again
+        });
+
+        peer.connect().await.unwrap();
+        peer.wait_for_bitfield().await.unwrap();
+        let data =
+            tokio::time::timeout(Duration::from_secs(2), peer.download_piece(0, piece_length))
+                .await
+                .expect("download should resume after the unchoke")
+                .unwrap();
+
+        assert_eq!(data, vec![3u8; piece_length]);
+        assert_eq!(mock.await.unwrap(), (BLOCK_SIZE, BLOCK_SIZE));
+    }
+
+    #[tokio::test]
+    async fn test_download_piece_to_hashes_out_of_order_blocks() {
+        let (mut peer, listener) = setup_mock_peer().await;
+        let piece_length = 2 * BLOCK_SIZE as usize;
+        let block_data = |begin: u32| vec![(begin / BLOCK_SIZE) as u8 + 5; BLOCK_SIZE as usize];
+
+        tokio::spawn(async move {
+            let mut stream = accept_unchoked(listener).await;
+
+            // Answer each piece's two requests last block first
+            loop {
+                let mut requests = Vec::new();
+                for _ in 0..2 {
+                    let mut request = [0u8; 17];
+                    if stream.read_exact(&mut request).await.is_err() {
+                        return;
+                    }
+                    if let Message::Request { index, begin, .. } =
+                        Message::from_bytes(&request[4..]).unwrap()
+                    {
+                        requests.push((index, begin));
+                    }
+                }
+                for &(index, begin) in requests.iter().rev() {
+                    let piece = Message::Piece {
+                        index,
+                        begin,
+                        block: block_data(begin),
+                    };
+                    stream.write_all(&piece.to_bytes()).await.unwrap();
+                }
+            }
+        });
+
+        peer.connect().await.unwrap();
+        peer.wait_for_bitfield().await.unwrap();
+        let expected: Vec<u8> = [block_data(0), block_data(BLOCK_SIZE)].concat();
+        let hash = Sha1::digest(&expected);
+
+        let store = MemoryStore::new(&TorrentInfo {
+            length: 3 * piece_length,
+            piece_length,
+            pieces: vec![0u8; 60].into(),
+            ..Default::default()
+        });
+        peer.download_piece_to(1, piece_length, &hash, &store)
+            .await
+            .unwrap();
+        let err = peer
+            .download_piece_to(2, piece_length, &[0u8; 20], &store)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("hash verification failed"));
+
+        assert_eq!(store.read_piece(1).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_piece_hasher_on_blocking_pool_matches_inline() {
+        let blocks = [(4, b"efgh".to_vec()), (0, b"abcd".to_vec())];
+        let mut inline = PieceHasher::default();
+        let mut offloaded = PieceHasher::default();
+        for (begin, block) in blocks {
+            inline.update(begin, block.clone());
+            offloaded = offloaded.update_blocking(begin, block).await.unwrap();
+        }
+
+        let expected: [u8; 20] = Sha1::digest(b"abcdefgh").into();
+        assert_eq!(inline.finalize(), expected);
+        assert_eq!(offloaded.finalize(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_piece_hasher_on_blocking_pool_leaves_runtime_free() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        // Test runtimes have a single thread, so a spawned task only runs while
+        // this one is parked
+        let spawn_ticker = || {
+            let ticked = Arc::new(AtomicBool::new(false));
+            let ticker = ticked.clone();
+            tokio::spawn(async move { ticker.store(true, Ordering::SeqCst) });
+            ticked
+        };
+        let block = vec![7u8; 8 * 1024 * 1024];
+
+        let ticked = spawn_ticker();
+        let mut inline = PieceHasher::default();
+        inline.update(0, block.clone());
+        assert!(!ticked.load(Ordering::SeqCst));
+
+        let ticked = spawn_ticker();
+        let offloaded = PieceHasher::default()
+            .update_blocking(0, block)
+            .await
+            .unwrap();
+        assert!(ticked.load(Ordering::SeqCst));
+        assert_eq!(offloaded.finalize(), inline.finalize());
+    }
+
+    #[tokio::test]
+    async fn test_connect_any_skips_dead_peers() {
+        // A closed port refuses immediately; a listener that never answers the
+        // handshake would stall a one-at-a-time connect
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let (_, silent) = setup_mock_peer().await;
+        let (_, live) = setup_mock_peer().await;
+        let live_addr = live.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = live.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            stream.write_all(&handshake).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let addrs = [closed_addr, silent.local_addr().unwrap(), live_addr];
+        let peer = tokio::time::timeout(
+            Duration::from_secs(2),
+            connect_any(&addrs, PeerConfig::default(), 2),
+        )
+        .await
+        .expect("live peer should win promptly")
+        .unwrap();
+        assert_eq!(peer.addr, live_addr);
+
+        let err = connect_any(&[closed_addr], PeerConfig::default(), 2)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Failed to connect to any peer"));
+    }
+
+    /// Accepts one connection and echoes the handshake back, returning the stream.
+    async fn accept_handshake(listener: TcpListener) -> TcpStream {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut handshake = [0u8; 68];
+        stream.read_exact(&mut handshake).await.unwrap();
+        stream.write_all(&handshake).await.unwrap();
+        stream
+    }
+
+    /// Accepts one connection like `accept_handshake`, advertises every piece with
+    /// `HaveAll` and unchokes once the peer declares interest.
+    async fn accept_unchoked(listener: TcpListener) -> TcpStream {
+        let mut stream = accept_handshake(listener).await;
+        stream
+            .write_all(&Message::HaveAll.to_bytes())
+            .await
+            .unwrap();
+        let mut interested = [0u8; 5];
+        stream.read_exact(&mut interested).await.unwrap();
+        stream
+            .write_all(&Message::Unchoke.to_bytes())
+            .await
+            .unwrap();
+        stream
+    }
+
+    #[tokio::test]
+    async fn test_connect_binds_configured_source_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // Find a free port for the source address
+        let bind_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = PeerConfig {
+            bind_addr: Some(bind_addr),
+            ..Default::default()
+        };
+        let mut peer = Peer::new(listener.local_addr().unwrap(), config);
+        let accepted = tokio::spawn(async move {
+            let stream = accept_handshake(listener).await;
+            stream.peer_addr().unwrap()
+        });
+
+        peer.connect().await.unwrap();
+        assert!(peer.peer_id.is_some());
+        assert_eq!(accepted.await.unwrap(), bind_addr);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_reads_peer_reserved_bits() {
+        let (mut peer, listener) = setup_mock_peer().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            // Advertise the extension protocol and DHT, but not the fast extension
+            handshake[20..28].copy_from_slice(&[0, 0, 0, 0, 0, 0x10, 0, 0x01]);
+            stream.write_all(&handshake).await.unwrap();
+        });
+
+        peer.connect().await.unwrap();
+        let reserved = peer.reserved();
+        assert!(reserved.supports_extension());
+        assert!(!reserved.supports_fast());
+        assert!(reserved.supports_dht());
+    }
+
+    #[test]
+    fn test_our_reserved_bits() {
+        let ours = ReservedBits::ours();
+        assert_eq!(ours.0, [0, 0, 0, 0, 0, 0x10, 0, 0x04]);
+        assert!(ours.supports_extension() && ours.supports_fast() && !ours.supports_dht());
+        assert_eq!(ReservedBits::default(), ReservedBits([0; 8]));
+    }
+
+    #[tokio::test]
+    async fn test_receive_message_reports_disconnect_mid_message() {
+        let (mut peer, listener) = setup_mock_peer().await;
+        tokio::spawn(async move {
+            let mut stream = accept_handshake(listener).await;
+            // Announce a 13-byte request but close after 3 bytes of it
+            stream.write_all(&[0, 0, 0, 13, 6, 0, 0]).await.unwrap();
+        });
+
+        peer.connect().await.unwrap();
+        let err = peer.receive_message().await.unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(PeerError::Disconnected)),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receive_message_times_out_on_silent_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = PeerConfig {
+            io_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut peer = Peer::new(listener.local_addr().unwrap(), config);
+        tokio::spawn(async move {
+            let _stream = accept_handshake(listener).await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        peer.connect().await.unwrap();
+        let err = tokio::time::timeout(Duration::from_secs(2), peer.receive_message())
+            .await
+            .expect("read should time out on its own")
+            .unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(PeerError::ReadTimeout(_))),
+            "{}",
+            err
+        );
+    }
 }
@@ -0,0 +1,372 @@
+//! Serving torrent data to other peers.
+//!
+//! A `Seeder` listens for inbound peer connections and, for each one:
+//! - Reads the handshake and picks the served torrent with a matching info hash;
+//!   connections asking for any other torrent are dropped without a reply
+//! - Answers the handshake with that torrent's info hash and our peer ID
+//! - Advertises the pieces it holds with a bitfield
+//! - Unchokes peers that declare interest
+//! - Answers block requests from the in-memory torrent data
+//! - Serves the info dictionary to peers that ask for it over `ut_metadata` (BEP 9),
+//!   so magnet link downloads can start from a seeder
+//!
+//! Only pieces whose data verifies against the torrent's piece hashes are advertised
+//! or served.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+
+use crate::bencode::bvalue::BValue;
+use tracing::{info, info_span, Instrument};
+
+use super::message::Message;
+use super::metadata::{
+    ExtensionHandshake, MetadataMessage, EXTENSION_HANDSHAKE_ID, METADATA_PIECE_SIZE,
+    UT_METADATA_ID,
+};
+use super::metainfo::TorrentInfo;
+use super::peer::{read_inbound_handshake, Peer, PeerConfig};
+use super::tracker::TrackerConfig;
+
+/// Largest block length a remote peer may request (128 KiB)
+pub const MAX_REQUEST_LENGTH: u32 = 128 * 1024;
+
+/// Serves the pieces of one or more torrents to inbound peers.
+pub struct Seeder {
+    listener: TcpListener,
+    /// Peer ID announced to trackers, taken from the first torrent's config
+    peer_id: [u8; 20],
+    /// Served torrents, keyed by info hash
+    torrents: HashMap<[u8; 20], Arc<SeededTorrent>>,
+}
+
+/// The torrent content and metadata shared by all connections of a seeder.
+struct SeededTorrent {
+    info: TorrentInfo,
+    data: Vec<u8>,
+    /// Bitfield of the pieces in `data` that verified against their hashes
+    bitfield: Vec<u8>,
+    /// The bencoded info dictionary, served to peers fetching metadata
+    metadata: Vec<u8>,
+    /// Configuration used for every inbound connection (info hash, peer ID, limits)
+    peer_config: PeerConfig,
+    /// Bytes of piece data served to peers
+    uploaded: Arc<AtomicU64>,
+}
+
+impl Seeder {
+    /// Binds a listener that will serve `data`, the content described by `info`.
+    ///
+    /// # Arguments
+    /// * `addr` - Address to listen on; use port 0 to let the OS choose
+    /// * `info` - Info dictionary of the torrent being served
+    /// * `data` - The torrent content, concatenated in file order
+    /// * `peer_config` - Connection settings; `info_hash` must be the torrent's info hash
+    pub async fn bind(
+        addr: SocketAddr,
+        info: TorrentInfo,
+        data: Vec<u8>,
+        peer_config: PeerConfig,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Self::from_listener(listener, info, data, peer_config)
+    }
+
+    /// Wraps a bound listener.
+    fn from_listener(
+        listener: TcpListener,
+        info: TorrentInfo,
+        data: Vec<u8>,
+        peer_config: PeerConfig,
+    ) -> Result<Self> {
+        let verified = info.verify_data(&data);
+        Self::from_verified(listener, info, data, &verified, peer_config)
+    }
+
+    /// Wraps a bound listener, serving the pieces marked in `verified`.
+    fn from_verified(
+        listener: TcpListener,
+        info: TorrentInfo,
+        data: Vec<u8>,
+        verified: &[bool],
+        peer_config: PeerConfig,
+    ) -> Result<Self> {
+        let mut seeder = Self {
+            listener,
+            peer_id: peer_config.peer_id,
+            torrents: HashMap::new(),
+        };
+        seeder.insert_torrent(info, data, verified, peer_config)?;
+        Ok(seeder)
+    }
+
+    /// Also serves `data`, the content described by `info`, on the same listener.
+    ///
+    /// Inbound peers are matched to a torrent by the info hash in their handshake, so
+    /// `peer_config.info_hash` must be this torrent's info hash.
+    pub fn add_torrent(
+        &mut self,
+        info: TorrentInfo,
+        data: Vec<u8>,
+        peer_config: PeerConfig,
+    ) -> Result<()> {
+        let verified = info.verify_data(&data);
+        self.insert_torrent(info, data, &verified, peer_config)
+    }
+
+    /// Serves `data`, advertising the pieces marked in `verified` without hashing
+    /// them again.
+    fn insert_torrent(
+        &mut self,
+        info: TorrentInfo,
+        data: Vec<u8>,
+        verified: &[bool],
+        peer_config: PeerConfig,
+    ) -> Result<()> {
+        if self.torrents.contains_key(&peer_config.info_hash) {
+            return Err(anyhow::anyhow!(
+                "Torrent {} is already being seeded",
+                hex::encode(peer_config.info_hash)
+            ));
+        }
+        if verified.len() != info.total_pieces() {
+            return Err(anyhow::anyhow!(
+                "Got {} verified flags for {} pieces",
+                verified.len(),
+                info.total_pieces()
+            ));
+        }
+        let bitfield = bitfield_from(verified);
+        let metadata = BValue::from(&info).to_bytes()?;
+        info!("Seeding {} on {}", info.name, self.listener.local_addr()?);
+
+        self.torrents.insert(
+            peer_config.info_hash,
+            Arc::new(SeededTorrent {
+                info,
+                data,
+                bitfield,
+                metadata,
+                peer_config,
+                uploaded: Arc::default(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Counts the piece bytes served for the torrent with `info_hash` into
+    /// `uploaded`, e.g. to report them to its tracker.
+    pub fn count_uploads(&mut self, info_hash: &[u8; 20], uploaded: Arc<AtomicU64>) -> Result<()> {
+        // The torrents are only shared once `run` starts, so this is the only reference
+        let torrent = self
+            .torrents
+            .get_mut(info_hash)
+            .and_then(Arc::get_mut)
+            .ok_or_else(|| {
+                anyhow::anyhow!("Torrent {} isn't being seeded", hex::encode(info_hash))
+            })?;
+        torrent.uploaded = uploaded;
+        Ok(())
+    }
+
+    /// Listens on `peer_config.listen_ip` and `peer_config.port`, serving `data`.
+    ///
+    /// An IPv6 listen address is bound dual-stack, so IPv4 peers can connect too.
+    /// With port 0 the OS picks a free port; `tracker_config` reports the real one.
+    pub async fn listen(info: TorrentInfo, data: Vec<u8>, peer_config: PeerConfig) -> Result<Self> {
+        let listener = Self::listen_socket(&peer_config)?;
+        Self::from_listener(listener, info, data, peer_config)
+    }
+
+    /// Listens like `listen`, but serves only the pieces marked in `verified`, which
+    /// the caller has already checked against their hashes, instead of hashing
+    /// `data` again.
+    pub async fn listen_verified(
+        info: TorrentInfo,
+        data: Vec<u8>,
+        verified: &[bool],
+        peer_config: PeerConfig,
+    ) -> Result<Self> {
+        let listener = Self::listen_socket(&peer_config)?;
+        Self::from_verified(listener, info, data, verified, peer_config)
+    }
+
+    /// Binds the dual-stack listener for `listen` and `listen_verified`.
+    fn listen_socket(peer_config: &PeerConfig) -> Result<TcpListener> {
+        let addr = SocketAddr::new(peer_config.listen_ip, peer_config.port);
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(false)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        Ok(TcpListener::from_std(socket.into())?)
+    }
+
+    /// Returns the address the seeder is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Tracker settings that announce the port this seeder is actually listening on.
+    pub fn tracker_config(&self) -> Result<TrackerConfig> {
+        Ok(TrackerConfig {
+            peer_id: self.peer_id,
+            port: self.local_addr()?.port(),
+            ..Default::default()
+        })
+    }
+
+    /// Accepts connections forever, serving each one on its own task.
+    pub async fn run(self) -> Result<()> {
+        let torrents = Arc::new(self.torrents);
+        loop {
+            let (stream, addr) = self.listener.accept().await?;
+            let torrents = torrents.clone();
+            tokio::spawn(
+                async move {
+                    if let Err(e) = Self::serve(stream, &torrents).await {
+                        info!("Connection with {} ended: {}", addr, e);
+                    }
+                }
+                .instrument(info_span!("upload", peer = %addr)),
+            );
+        }
+    }
+
+    /// Handles one inbound peer until it disconnects or misbehaves.
+    ///
+    /// A handshake for a torrent we don't serve ends the connection before we send
+    /// anything, so the remote side only sees it close.
+    async fn serve(
+        mut stream: tokio::net::TcpStream,
+        torrents: &HashMap<[u8; 20], Arc<SeededTorrent>>,
+    ) -> Result<()> {
+        let handshake = read_inbound_handshake(&mut stream).await?;
+        let info_hash = &handshake[28..48];
+        let torrent = torrents.get(info_hash).ok_or_else(|| {
+            anyhow::anyhow!("Handshake for unknown info hash {}", hex::encode(info_hash))
+        })?;
+        let mut peer = Peer::from_stream(stream, torrent.peer_config.clone())?;
+        peer.accept_handshake(&handshake).await?;
+        peer.send_message(Message::Bitfield(torrent.bitfield.clone()))
+            .await?;
+
+        // Id the remote peer wants ut_metadata messages sent with, once it has told us
+        let mut remote_metadata_id = None;
+        loop {
+            match peer.receive_message().await? {
+                Message::Interested => peer.send_message(Message::Unchoke).await?,
+                Message::Request {
+                    index,
+                    begin,
+                    length,
+                } => {
+                    let block = torrent.block(index, begin, length)?.to_vec();
+                    // Counted before sending, so it shows once the peer has the block
+                    torrent.uploaded.fetch_add(length as u64, Ordering::Relaxed);
+                    peer.send_message(Message::Piece {
+                        index,
+                        begin,
+                        block,
+                    })
+                    .await?;
+                }
+                Message::Extended {
+                    id: EXTENSION_HANDSHAKE_ID,
+                    payload,
+                } => {
+                    remote_metadata_id = ExtensionHandshake::from_bytes(&payload)?.ut_metadata;
+                    let handshake = ExtensionHandshake {
+                        ut_metadata: Some(UT_METADATA_ID),
+                        metadata_size: Some(torrent.metadata.len()),
+                    };
+                    peer.send_message(Message::Extended {
+                        id: EXTENSION_HANDSHAKE_ID,
+                        payload: handshake.to_bytes()?,
+                    })
+                    .await?;
+                }
+                Message::Extended {
+                    id: UT_METADATA_ID,
+                    payload,
+                } => {
+                    let MetadataMessage::Request { piece } = MetadataMessage::from_bytes(&payload)?
+                    else {
+                        continue;
+                    };
+                    let id = remote_metadata_id
+                        .ok_or_else(|| anyhow::anyhow!("ut_metadata request before handshake"))?;
+                    peer.send_message(Message::Extended {
+                        id,
+                        payload: torrent.metadata_piece(piece).to_bytes()?,
+                    })
+                    .await?;
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl SeededTorrent {
+    /// Answers a request for one piece of the metadata.
+    fn metadata_piece(&self, piece: usize) -> MetadataMessage {
+        let start = piece.saturating_mul(METADATA_PIECE_SIZE);
+        if start >= self.metadata.len() {
+            return MetadataMessage::Reject { piece };
+        }
+        let end = std::cmp::min(start + METADATA_PIECE_SIZE, self.metadata.len());
+        MetadataMessage::Data {
+            piece,
+            total_size: self.metadata.len(),
+            data: self.metadata[start..end].to_vec(),
+        }
+    }
+
+    /// Returns the requested block, rejecting requests for pieces we don't have or
+    /// ranges that fall outside the piece.
+    fn block(&self, index: u32, begin: u32, length: u32) -> Result<&[u8]> {
+        let piece_index = index as usize;
+        let has_piece = self
+            .bitfield
+            .get(piece_index / 8)
+            .is_some_and(|byte| byte & (1 << (7 - piece_index % 8)) != 0);
+        if !has_piece {
+            return Err(anyhow::anyhow!(
+                "Requested piece {} is not available",
+                index
+            ));
+        }
+
+        let piece_size = self.info.piece_size(piece_index).unwrap_or(0);
+        if length > MAX_REQUEST_LENGTH || begin as usize + length as usize > piece_size {
+            return Err(anyhow::anyhow!(
+                "Invalid request for piece {}: begin {} length {}",
+                index,
+                begin,
+                length
+            ));
+        }
+
+        let start = piece_index * self.info.piece_length + begin as usize;
+        Ok(&self.data[start..start + length as usize])
+    }
+}
+
+/// Packs per-piece availability into a bitfield, most significant bit first.
+fn bitfield_from(pieces: &[bool]) -> Vec<u8> {
+    let mut bitfield = vec![0u8; pieces.len().div_ceil(8)];
+    for (piece_index, _) in pieces.iter().enumerate().filter(|(_, &have)| have) {
+        bitfield[piece_index / 8] |= 1 << (7 - piece_index % 8);
+    }
+    bitfield
+}
@@ -0,0 +1,324 @@
+//! Where downloaded piece data is kept.
+//!
+//! `DownloadManager` writes each block through a `PieceStore` as it arrives, so the
+//! download itself doesn't depend on where the pieces end up:
+//! - `MemoryStore` keeps pieces in memory, for `DownloadManager::download`
+//! - `FileStore` writes pieces at their offsets in a single file of the torrent's
+//!   length, for `DownloadManager::download_to`
+//!
+//! Other backends (object storage, an encrypted volume, ...) can be plugged in with
+//! `DownloadManager::with_store`. Stores only hold bytes; piece hashes are checked
+//! by the download manager before a piece is reported as complete.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use super::metainfo::TorrentInfo;
+
+/// Storage for the pieces of one torrent.
+///
+/// Methods take `&self` because workers for several peers write concurrently, each
+/// to the pieces it has claimed.
+pub trait PieceStore: Send + Sync {
+    /// Writes `data` at byte `offset` within piece `index`.
+    ///
+    /// Pieces arrive a block at a time, in any order; writing a range again
+    /// replaces it.
+    fn write_piece<'a>(
+        &'a self,
+        index: usize,
+        offset: usize,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Reads back the full data of piece `index`.
+    fn read_piece(&self, index: usize) -> BoxFuture<'_, Result<Vec<u8>>>;
+
+    /// Returns whether every byte of piece `index` has been written.
+    fn has_piece(&self, index: usize) -> bool;
+
+    /// Tells the store that piece `index` has been handed on and doesn't need to
+    /// be kept. Does nothing by default.
+    fn release_piece(&self, _index: usize) {}
+}
+
+/// Tracks which byte ranges of each piece have been written.
+struct Coverage {
+    piece_sizes: Vec<usize>,
+    /// Written ranges of each piece as `offset -> end`, merged when they touch
+    written: std::sync::Mutex<Vec<BTreeMap<usize, usize>>>,
+}
+
+impl Coverage {
+    fn new(info: &TorrentInfo) -> Self {
+        let piece_sizes: Vec<usize> = (0..info.total_pieces())
+            .filter_map(|i| info.piece_size(i))
+            .collect();
+        let written = std::sync::Mutex::new(vec![BTreeMap::new(); piece_sizes.len()]);
+        Self {
+            piece_sizes,
+            written,
+        }
+    }
+
+    /// Returns the size of piece `index`, checking that `offset..offset + len` lies
+    /// within it.
+    fn check(&self, index: usize, offset: usize, len: usize) -> Result<usize> {
+        let size = self.size(index)?;
+        if offset + len > size {
+            return Err(anyhow::anyhow!(
+                "Write of {} bytes at offset {} runs past the end of piece {} ({} bytes)",
+                len,
+                offset,
+                index,
+                size
+            ));
+        }
+        Ok(size)
+    }
+
+    fn size(&self, index: usize) -> Result<usize> {
+        self.piece_sizes.get(index).copied().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Piece {} is out of range; the torrent has {} pieces",
+                index,
+                self.piece_sizes.len()
+            )
+        })
+    }
+
+    fn record(&self, index: usize, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let mut written = self.written.lock().unwrap();
+        let ranges = &mut written[index];
+        let (mut start, mut end) = (offset, offset + len);
+        // Absorb every range that overlaps or touches the new one
+        let touching: Vec<(usize, usize)> = ranges
+            .range(..=end)
+            .filter(|&(_, &range_end)| range_end >= start)
+            .map(|(&s, &e)| (s, e))
+            .collect();
+        for (range_start, range_end) in touching {
+            ranges.remove(&range_start);
+            start = start.min(range_start);
+            end = end.max(range_end);
+        }
+        ranges.insert(start, end);
+    }
+
+    fn is_complete(&self, index: usize) -> bool {
+        let Some(&size) = self.piece_sizes.get(index) else {
+            return false;
+        };
+        let written = self.written.lock().unwrap();
+        let mut ranges = written[index].iter();
+        match ranges.next() {
+            Some((&0, &end)) => end >= size,
+            // An empty piece is complete without any writes
+            None => size == 0,
+            _ => false,
+        }
+    }
+
+    fn clear(&self, index: usize) {
+        if let Some(ranges) = self.written.lock().unwrap().get_mut(index) {
+            ranges.clear();
+        }
+    }
+}
+
+/// Keeps every piece in memory.
+///
+/// A piece's buffer is allocated on its first write and freed by `release_piece`.
+pub struct MemoryStore {
+    pieces: std::sync::Mutex<Vec<Option<Vec<u8>>>>,
+    coverage: Coverage,
+}
+
+impl MemoryStore {
+    /// Creates an empty store for the pieces of `info`.
+    pub fn new(info: &TorrentInfo) -> Self {
+        Self {
+            pieces: std::sync::Mutex::new(vec![None; info.total_pieces()]),
+            coverage: Coverage::new(info),
+        }
+    }
+}
+
+impl PieceStore for MemoryStore {
+    fn write_piece<'a>(
+        &'a self,
+        index: usize,
+        offset: usize,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let size = self.coverage.check(index, offset, data.len())?;
+            let mut pieces = self.pieces.lock().unwrap();
+            let piece = pieces[index].get_or_insert_with(|| vec![0; size]);
+            piece[offset..offset + data.len()].copy_from_slice(data);
+            self.coverage.record(index, offset, data.len());
+            Ok(())
+        })
+    }
+
+    fn read_piece(&self, index: usize) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(async move {
+            self.coverage.size(index)?;
+            if !self.coverage.is_complete(index) {
+                return Err(anyhow::anyhow!("Piece {} has not been stored", index));
+            }
+            let pieces = self.pieces.lock().unwrap();
+            Ok(pieces[index].clone().unwrap_or_default())
+        })
+    }
+
+    fn has_piece(&self, index: usize) -> bool {
+        self.coverage.is_complete(index)
+    }
+
+    fn release_piece(&self, index: usize) {
+        if let Some(piece) = self.pieces.lock().unwrap().get_mut(index) {
+            *piece = None;
+        }
+        self.coverage.clear(index);
+    }
+}
+
+/// Writes pieces at their offsets in a single file of the torrent's length.
+///
+/// Only pieces written through the store count for `has_piece`; data already in the
+/// file when it was opened is not trusted without a hash check.
+pub struct FileStore {
+    path: PathBuf,
+    file: Mutex<tokio::fs::File>,
+    piece_length: usize,
+    coverage: Coverage,
+}
+
+impl FileStore {
+    /// Opens the file at `path` for the pieces of `info`, creating it if needed and
+    /// extending it to the torrent's length. Existing contents are kept.
+    pub async fn create(path: &Path, info: &TorrentInfo) -> Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .await?;
+        if file.metadata().await?.len() < info.length as u64 {
+            file.set_len(info.length as u64).await?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            piece_length: info.piece_length,
+            coverage: Coverage::new(info),
+        })
+    }
+
+    /// Returns the path of the file pieces are written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl PieceStore for FileStore {
+    fn write_piece<'a>(
+        &'a self,
+        index: usize,
+        offset: usize,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.coverage.check(index, offset, data.len())?;
+            let mut file = self.file.lock().await;
+            let position = (index * self.piece_length + offset) as u64;
+            file.seek(std::io::SeekFrom::Start(position)).await?;
+            file.write_all(data).await?;
+            // Tokio hands writes to a background thread; make sure the data has
+            // landed before the write is reported as done
+            file.flush().await?;
+            drop(file);
+            self.coverage.record(index, offset, data.len());
+            Ok(())
+        })
+    }
+
+    fn read_piece(&self, index: usize) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let size = self.coverage.size(index)?;
+            let mut data = vec![0; size];
+            let mut file = self.file.lock().await;
+            file.seek(std::io::SeekFrom::Start((index * self.piece_length) as u64))
+                .await?;
+            file.read_exact(&mut data).await?;
+            Ok(data)
+        })
+    }
+
+    fn has_piece(&self, index: usize) -> bool {
+        self.coverage.is_complete(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> TorrentInfo {
+        TorrentInfo {
+            length: 10,
+            piece_length: 4,
+            pieces: vec![0u8; 60].into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_tracks_blocks_written_out_of_order() {
+        let store = MemoryStore::new(&info());
+        store.write_piece(1, 2, b"gh").await.unwrap();
+        assert!(!store.has_piece(1));
+        assert!(store.read_piece(1).await.is_err());
+
+        store.write_piece(1, 0, b"ef").await.unwrap();
+        assert!(store.has_piece(1));
+        assert_eq!(store.read_piece(1).await.unwrap(), b"efgh");
+        assert!(!store.has_piece(0));
+
+        // The last piece is shorter than the rest
+        store.write_piece(2, 0, b"ij").await.unwrap();
+        assert!(store.has_piece(2));
+        let err = store.write_piece(2, 1, b"jk").await.unwrap_err();
+        assert!(err.to_string().contains("past the end of piece 2"));
+        assert!(store.write_piece(3, 0, b"x").await.is_err());
+
+        store.release_piece(1);
+        assert!(!store.has_piece(1));
+    }
+
+    #[tokio::test]
+    async fn test_file_store_writes_pieces_at_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.bin");
+        let store = FileStore::create(&path, &info()).await.unwrap();
+
+        store.write_piece(2, 0, b"ij").await.unwrap();
+        store.write_piece(0, 2, b"cd").await.unwrap();
+        store.write_piece(0, 0, b"ab").await.unwrap();
+        assert!(store.has_piece(0));
+        assert!(!store.has_piece(1));
+        assert_eq!(store.read_piece(2).await.unwrap(), b"ij");
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcd\0\0\0\0ij");
+    }
+}
@@ -26,11 +26,20 @@
 //! - Responds to piece requests
 //! - Simulates protocol messages
 //!
+//! End-to-end tests instead start this crate's own `Seeder` in-process with
+//! `spawn_seeder` and download from it over loopback, exercising both sides of the
+//! protocol together.
+//!
 //! Each test focuses on a specific aspect of the protocol to ensure
 //! proper implementation of the BitTorrent specification.
 
 use super::*;
+use md5::{Digest, Md5};
+use metainfo::{TorrentInfo, TorrentMetainfo};
 use peer::PeerConfig;
+use std::sync::Arc;
+use std::time::Duration;
+use storage::{MemoryStore, PieceStore};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tracing::debug;
@@ -71,12 +80,234 @@ impl MockPeer {
     }
 }
 
+/// Mock seeder that serves pieces of a fixed buffer to any number of connections.
+struct MockSeeder {
+    listener: TcpListener,
+    data: Arc<Vec<u8>>,
+    piece_length: usize,
+    bitfield: Vec<u8>,
+    /// Piece index of every block request received, in arrival order
+    requests: Arc<std::sync::Mutex<Vec<u32>>>,
+    /// Piece index of every `Have` received, in arrival order
+    haves: Arc<std::sync::Mutex<Vec<u32>>>,
+    /// Number of connections accepted
+    connections: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl MockSeeder {
+    /// Creates a seeder advertising every piece of `data`.
+    async fn new(data: Vec<u8>, piece_length: usize) -> Self {
+        Self::bind("127.0.0.1:0".parse().unwrap(), data, piece_length).await
+    }
+
+    /// Creates a seeder listening on `addr` and advertising every piece of `data`.
+    async fn bind(addr: std::net::SocketAddr, data: Vec<u8>, piece_length: usize) -> Self {
+        let total_pieces = data.len().div_ceil(piece_length);
+        let seeder = Self {
+            listener: TcpListener::bind(addr).await.unwrap(),
+            data: Arc::new(data),
+            piece_length,
+            bitfield: Vec::new(),
+            requests: Arc::default(),
+            haves: Arc::default(),
+            connections: Arc::default(),
+        };
+        seeder.with_pieces(&(0..total_pieces).collect::<Vec<_>>())
+    }
+
+    /// Restricts the advertised bitfield to the given pieces.
+    fn with_pieces(mut self, pieces: &[usize]) -> Self {
+        let total_pieces = self.data.len().div_ceil(self.piece_length);
+        self.bitfield = vec![0u8; total_pieces.div_ceil(8)];
+        for &piece in pieces {
+            self.bitfield[piece / 8] |= 1 << (7 - piece % 8);
+        }
+        self
+    }
+
+    /// Logs requests to `requests`, so several seeders can share one log.
+    fn with_request_log(mut self, requests: Arc<std::sync::Mutex<Vec<u32>>>) -> Self {
+        self.requests = requests;
+        self
+    }
+
+    /// Returns the socket address the seeder is listening on.
+    fn addr(&self) -> std::net::SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    /// Returns a handle to the log of requested piece indices.
+    fn requests(&self) -> Arc<std::sync::Mutex<Vec<u32>>> {
+        self.requests.clone()
+    }
+
+    /// Returns a handle to the log of pieces announced to the seeder with `Have`.
+    fn haves(&self) -> Arc<std::sync::Mutex<Vec<u32>>> {
+        self.haves.clone()
+    }
+
+    /// Returns a handle to the number of connections accepted so far.
+    fn connections(&self) -> Arc<std::sync::atomic::AtomicUsize> {
+        self.connections.clone()
+    }
+
+    /// Serves connections in the background until the test ends.
+    fn spawn(self) {
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = self.listener.accept().await.unwrap();
+                self.connections
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let data = self.data.clone();
+                let bitfield = self.bitfield.clone();
+                let piece_length = self.piece_length;
+                let requests = self.requests.clone();
+                let haves = self.haves.clone();
+                tokio::spawn(async move {
+                    let _ =
+                        Self::serve(stream, data, piece_length, bitfield, requests, haves).await;
+                });
+            }
+        });
+    }
+
+    /// Handles one connection: handshake, bitfield, unchoke on interest, then requests.
+    async fn serve(
+        mut stream: TcpStream,
+        data: Arc<Vec<u8>>,
+        piece_length: usize,
+        bitfield: Vec<u8>,
+        requests: Arc<std::sync::Mutex<Vec<u32>>>,
+        haves: Arc<std::sync::Mutex<Vec<u32>>>,
+    ) -> std::io::Result<()> {
+        let mut handshake = [0u8; 68];
+        stream.read_exact(&mut handshake).await?;
+        stream.write_all(&handshake).await?;
+        stream
+            .write_all(&message::Message::Bitfield(bitfield).to_bytes())
+            .await?;
+
+        loop {
+            let mut len = [0u8; 4];
+            stream.read_exact(&mut len).await?;
+            let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut body).await?;
+
+            match message::Message::from_bytes(&body) {
+                Ok(message::Message::Interested) => {
+                    stream
+                        .write_all(&message::Message::Unchoke.to_bytes())
+                        .await?;
+                }
+                Ok(message::Message::Request {
+                    index,
+                    begin,
+                    length,
+                }) => {
+                    requests.lock().unwrap().push(index);
+                    let start = index as usize * piece_length + begin as usize;
+                    let block = data[start..start + length as usize].to_vec();
+                    let piece = message::Message::Piece {
+                        index,
+                        begin,
+                        block,
+                    };
+                    stream.write_all(&piece.to_bytes()).await?;
+                }
+                Ok(message::Message::Have(index)) => haves.lock().unwrap().push(index),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Builds a single-file torrent describing `data` split into `piece_length` pieces.
+fn make_torrent(data: &[u8], piece_length: usize) -> TorrentMetainfo {
+    TorrentMetainfo::new(
+        Some("http://127.0.0.1/announce".into()),
+        TorrentInfo::from_data("test.bin", piece_length, data),
+    )
+}
+
+/// Starts a real in-process seeder for `data` and returns its torrent and address.
+async fn spawn_seeder(data: &[u8], piece_length: usize) -> (TorrentMetainfo, std::net::SocketAddr) {
+    let torrent = make_torrent(data, piece_length);
+    let peer_config = PeerConfig {
+        info_hash: torrent.info_hash().unwrap(),
+        ..Default::default()
+    };
+    let seeder = seeder::Seeder::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        torrent.info.clone().unwrap(),
+        data.to_vec(),
+        peer_config,
+    )
+    .await
+    .unwrap();
+    let addr = seeder.local_addr().unwrap();
+    tokio::spawn(seeder.run());
+    (torrent, addr)
+}
+
+/// Starts an HTTP tracker that answers every announce with `peers` in compact form
+/// and returns its announce URL.
+async fn spawn_tracker(peers: Vec<std::net::SocketAddr>) -> String {
+    spawn_logging_tracker(peers).await.0
+}
+
+/// Starts a tracker like `spawn_tracker` that also logs the request line of every
+/// announce, and returns its announce URL and the log.
+async fn spawn_logging_tracker(
+    peers: Vec<std::net::SocketAddr>,
+) -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/announce", listener.local_addr().unwrap());
+
+    let mut compact = Vec::new();
+    for peer in &peers {
+        if let std::net::SocketAddr::V4(v4) = peer {
+            compact.extend_from_slice(&v4.ip().octets());
+            compact.extend_from_slice(&v4.port().to_be_bytes());
+        }
+    }
+    let mut body = format!("d8:intervali60e5:peers{}:", compact.len()).into_bytes();
+    body.extend_from_slice(&compact);
+    body.push(b'e');
+
+    let announces = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = announces.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let read = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..read]);
+            if let Some(line) = request.lines().next() {
+                log.lock().unwrap().push(line.to_string());
+            }
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&body);
+            let _ = stream.write_all(&response).await;
+        }
+    });
+    (url, announces)
+}
+
+/// Generates `len` bytes of deterministic, non-repeating test data.
+fn test_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
 /// Tests serialization and deserialization of all message types.
 #[test]
 fn test_message_serialization() {
     debug!("Starting message serialization test");
     let messages = vec![
-        (message::Message::KeepAlive, vec![]),
+        (message::Message::KeepAlive, vec![0, 0, 0, 0]),
         (message::Message::Choke, vec![0, 0, 0, 1, 0]),
         (message::Message::Unchoke, vec![0, 0, 0, 1, 1]),
         (message::Message::Interested, vec![0, 0, 0, 1, 2]),
@@ -94,17 +325,48 @@ fn test_message_serialization() {
             },
             vec![0, 0, 0, 13, 6, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 64, 0],
         ),
+        (
+            message::Message::Cancel {
+                index: 1,
+                begin: 2,
+                length: 16384,
+            },
+            vec![0, 0, 0, 13, 8, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 64, 0],
+        ),
+        (
+            message::Message::SuggestPiece(7),
+            vec![0, 0, 0, 5, 0x0D, 0, 0, 0, 7],
+        ),
+        (message::Message::HaveAll, vec![0, 0, 0, 1, 0x0E]),
+        (message::Message::HaveNone, vec![0, 0, 0, 1, 0x0F]),
+        (
+            message::Message::RejectRequest {
+                index: 1,
+                begin: 2,
+                length: 16384,
+            },
+            vec![0, 0, 0, 13, 0x10, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 64, 0],
+        ),
+        (
+            message::Message::AllowedFast(3),
+            vec![0, 0, 0, 5, 0x11, 0, 0, 0, 3],
+        ),
+        (
+            message::Message::Extended {
+                id: 1,
+                payload: b"de".to_vec(),
+            },
+            vec![0, 0, 0, 4, 20, 1, b'd', b'e'],
+        ),
     ];
 
     for (message, expected_bytes) in messages {
         debug!("Testing message: {:?}", message);
         assert_eq!(message.to_bytes(), expected_bytes);
-        if !expected_bytes.is_empty() {
-            assert_eq!(
-                message::Message::from_bytes(&expected_bytes[4..]).unwrap(),
-                message
-            );
-        }
+        assert_eq!(
+            message::Message::from_bytes(&expected_bytes[4..]).unwrap(),
+            message
+        );
     }
     debug!("Message serialization test completed");
 }
@@ -207,6 +469,23 @@ async fn test_message_error_handling() {
     peer.connect().await.unwrap();
     debug!("Testing piece download with malformed message");
     assert!(peer.download_piece(0, 16384).await.is_err());
+
+    debug!("Testing truncated message payloads");
+    let truncated: Vec<&[u8]> = vec![
+        &[4, 0, 0],                      // Have with 2 of 4 index bytes
+        &[6, 0, 0, 0, 1, 0, 0, 0, 2, 0], // Request missing most of length
+        &[7, 0, 0, 0, 1],                // Piece missing begin
+        &[8, 0, 0, 0, 1],                // Cancel missing begin and length
+    ];
+    for bytes in truncated {
+        let err = message::Message::from_bytes(bytes).unwrap_err();
+        assert!(
+            err.to_string().contains("Truncated"),
+            "unexpected error for {:?}: {}",
+            bytes,
+            err
+        );
+    }
 }
 
 /// Tests connection timeout handling for unreachable peers.
@@ -302,8 +581,9 @@ async fn test_download_complete_file() {
                     stream.read_exact(&mut request).await.unwrap();
                     debug!("Received piece request");
 
+                    let index = u32::from_be_bytes(request[0..4].try_into().unwrap());
                     let response = message::Message::Piece {
-                        index: 0,
+                        index,
                         begin: 0,
                         block: piece_data.clone(),
                     }
@@ -330,3 +610,1723 @@ async fn test_download_complete_file() {
     }
     debug!("Multi-piece download completed");
 }
+
+/// Tests that abandoning a pipelined download cancels every outstanding request.
+#[tokio::test]
+async fn test_cancel_pending_after_aborted_download() {
+    debug!("Starting aborted download cancel test");
+    let mock_peer = MockPeer::new().await;
+    let peer_addr = mock_peer.addr();
+    let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+
+    mock_peer
+        .handle_connection(|mut stream| async move {
+            let mut handshake = [0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            stream.write_all(&handshake).await.unwrap();
+
+            // Read interested and unchoke the client
+            let mut interested = [0u8; 5];
+            stream.read_exact(&mut interested).await.unwrap();
+            assert_eq!(interested[4], 2);
+            stream
+                .write_all(&message::Message::Unchoke.to_bytes())
+                .await
+                .unwrap();
+
+            // Never answer requests; count requests and cancels until the client goes quiet
+            let mut requests = 0;
+            let mut cancels = 0;
+            loop {
+                let mut header = [0u8; 5];
+                let read = tokio::time::timeout(
+                    std::time::Duration::from_millis(500),
+                    stream.read_exact(&mut header),
+                )
+                .await;
+                if !matches!(read, Ok(Ok(_))) {
+                    break;
+                }
+                let mut payload = [0u8; 12];
+                stream.read_exact(&mut payload).await.unwrap();
+                match header[4] {
+                    6 => requests += 1,
+                    8 => cancels += 1,
+                    id => panic!("Unexpected message id {}", id),
+                }
+            }
+            cancel_tx.send((requests, cancels)).unwrap();
+        })
+        .await;
+
+    let mut peer = peer::Peer::new(peer_addr, PeerConfig::default());
+    peer.connect().await.unwrap();
+
+    // Four blocks are requested at once, none of which ever arrive
+    let result = tokio::time::timeout(
+        std::time::Duration::from_millis(100),
+        peer.download_piece(0, 4 * 16384),
+    )
+    .await;
+    assert!(result.is_err(), "Download should have timed out");
+    assert_eq!(peer.pending_requests(), 4);
+
+    peer.cancel_pending().await.unwrap();
+    assert_eq!(peer.pending_requests(), 0);
+
+    let (requests, cancels) = cancel_rx.await.unwrap();
+    assert_eq!(requests, 4);
+    assert_eq!(cancels, 4);
+}
+
+/// Tests that the download manager fetches and reassembles every piece from several peers.
+#[tokio::test]
+async fn test_download_manager_downloads_all_pieces() {
+    let data = test_data(100_000);
+    let torrent = make_torrent(&data, 32 * 1024);
+    let mut peers = Vec::new();
+    for _ in 0..2 {
+        let seeder = MockSeeder::new(data.clone(), 32 * 1024).await;
+        peers.push(seeder.addr());
+        seeder.spawn();
+    }
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    );
+
+    let downloaded = manager.download().await.unwrap();
+    assert_eq!(downloaded, data);
+}
+
+/// Tests that a piece stream yields every piece's verified data, in order when
+/// downloading sequentially from one peer.
+#[tokio::test]
+async fn test_download_manager_piece_stream_yields_all_pieces() {
+    use futures::StreamExt;
+
+    let data = test_data(3 * 16384 - 100);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let addr = seeder.addr();
+    seeder.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        vec![addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            priority: download::PiecePriority::Sequential,
+            ..Default::default()
+        },
+    );
+
+    let pieces: Vec<(usize, Vec<u8>)> =
+        tokio::time::timeout(Duration::from_secs(10), manager.piece_stream().collect())
+            .await
+            .unwrap();
+    let indices: Vec<usize> = pieces.iter().map(|(i, _)| *i).collect();
+    assert_eq!(indices, vec![0, 1, 2]);
+    let reassembled: Vec<u8> = pieces.into_iter().flat_map(|(_, piece)| piece).collect();
+    assert_eq!(reassembled, data);
+}
+
+/// Tests that sequential priority requests pieces in ascending index order.
+#[tokio::test]
+async fn test_download_manager_sequential_priority_requests_in_order() {
+    let data = test_data(8 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let addr = seeder.addr();
+    let requests = seeder.requests();
+    seeder.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        vec![addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            priority: download::PiecePriority::Sequential,
+            max_pending: 1,
+            ..Default::default()
+        },
+    );
+
+    let downloaded = manager.download().await.unwrap();
+    assert_eq!(downloaded, data);
+    assert_eq!(*requests.lock().unwrap(), (0..8).collect::<Vec<u32>>());
+}
+
+/// Tests that `max_pending` stops the first worker from claiming every piece.
+#[tokio::test]
+async fn test_download_manager_spreads_pieces_across_peers() {
+    let data = test_data(32 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let first = MockSeeder::new(data.clone(), 16384).await;
+    let second = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![first.addr(), second.addr()];
+    first.spawn();
+    second.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers.clone(),
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            // With a batch of all 32, whichever worker claims first would take them all
+            max_pending: 4,
+            shuffle_peers: false,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(manager.download().await.unwrap(), data);
+    let summary = manager.summary().unwrap();
+    for peer in &peers {
+        assert!(
+            summary.per_peer[peer].successful_pieces > 0,
+            "peer {} served no pieces: {:?}",
+            peer,
+            summary.per_peer
+        );
+    }
+}
+
+/// Tests that a worker backs off and reconnects to a peer that refused its first
+/// connections, rather than spending every retry before the peer is listening.
+#[tokio::test]
+async fn test_download_manager_reconnects_with_backoff() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    // Reserve a port, then leave it closed until the worker has been refused
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let seeder_data = data.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        MockSeeder::bind(addr, seeder_data, 16384).await.spawn();
+    });
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        vec![addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            // 100ms, 200ms, 400ms: the last retry lands after the seeder is up
+            peer_retries: 3,
+            reconnect_base_delay: Duration::from_millis(100),
+            reconnect_max_delay: Duration::from_secs(1),
+            ..Default::default()
+        },
+    );
+    assert_eq!(manager.download().await.unwrap(), data);
+
+    let state = &manager.summary().unwrap().per_peer[&addr];
+    assert!(state.reconnects >= 1, "{:?}", state);
+    assert!(state.backoff >= Duration::from_millis(100));
+    assert!(state.backoff <= Duration::from_secs(1));
+}
+
+/// Tests that a partial seed advertises only the verified pieces in its store and
+/// serves them to a connecting peer.
+#[tokio::test]
+async fn test_download_manager_seed_partial_advertises_verified_pieces() {
+    let data = test_data(4 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let info = torrent.info.clone().unwrap();
+    let store = MemoryStore::new(&info);
+    for piece_index in [0, 2] {
+        let piece = &data[piece_index * 16384..(piece_index + 1) * 16384];
+        store.write_piece(piece_index, 0, piece).await.unwrap();
+    }
+    // Fully written but corrupt, so it must not be advertised
+    store.write_piece(3, 0, &[0xff; 16384]).await.unwrap();
+
+    let peer_config = PeerConfig {
+        info_hash: torrent.info_hash().unwrap(),
+        listen_ip: "127.0.0.1".parse().unwrap(),
+        port: 0,
+        ..Default::default()
+    };
+    let manager = download::DownloadManager::new(
+        info,
+        Vec::new(),
+        peer_config.clone(),
+        download::DownloadConfig::default(),
+    )
+    .with_store(Box::new(store));
+    let seeder = manager.seed_partial().await.unwrap();
+    let addr = seeder.local_addr().unwrap();
+    tokio::spawn(seeder.run());
+
+    let mut peer = peer::Peer::new(addr, peer_config);
+    peer.connect().await.unwrap();
+    peer.wait_for_bitfield().await.unwrap();
+    let advertised: Vec<_> = (0..4).filter(|&i| peer.has_piece(i)).collect();
+    assert_eq!(advertised, vec![0, 2]);
+    assert_eq!(
+        peer.download_piece(2, 16384).await.unwrap(),
+        &data[2 * 16384..3 * 16384]
+    );
+    assert_eq!(manager.stats().uploaded, 16384);
+}
+
+/// Tests that downloads with the same `rng_seed` connect to peers in the same order,
+/// and so request pieces in the same order.
+#[tokio::test]
+async fn test_download_manager_rng_seed_reproduces_request_order() {
+    let data = test_data(6 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut peers = Vec::new();
+    for pieces in [[0, 1], [2, 3], [4, 5]] {
+        let seeder = MockSeeder::new(data.clone(), 16384)
+            .await
+            .with_pieces(&pieces)
+            .with_request_log(requests.clone());
+        peers.push(seeder.addr());
+        seeder.spawn();
+    }
+
+    let mut orders = Vec::new();
+    for _ in 0..2 {
+        let manager = download::DownloadManager::new(
+            torrent.info.clone().unwrap(),
+            peers.clone(),
+            PeerConfig {
+                info_hash: torrent.info_hash().unwrap(),
+                ..Default::default()
+            },
+            download::DownloadConfig {
+                // One peer at a time, taken in shuffled order
+                max_peers: 1,
+                priority: download::PiecePriority::Sequential,
+                rng_seed: Some(7),
+                ..Default::default()
+            },
+        );
+        assert_eq!(manager.download().await.unwrap(), data);
+        orders.push(std::mem::take(&mut *requests.lock().unwrap()));
+    }
+    assert_eq!(orders[0].len(), 6);
+    assert_eq!(orders[0], orders[1]);
+}
+
+/// Tests that connected peers are sent a `Have` once a piece completes.
+#[tokio::test]
+async fn test_download_manager_sends_have_to_connected_peers() {
+    let data = test_data(4 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    // Each peer has two pieces, so both stay connected past their first piece
+    let first = MockSeeder::new(data.clone(), 16384)
+        .await
+        .with_pieces(&[0, 1]);
+    let second = MockSeeder::new(data.clone(), 16384)
+        .await
+        .with_pieces(&[2, 3]);
+    let peers = vec![first.addr(), second.addr()];
+    let (first_haves, second_haves) = (first.haves(), second.haves());
+    first.spawn();
+    second.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            max_pending: 1,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(manager.download().await.unwrap(), data);
+    // Each worker announces every piece completed so far before claiming its next one
+    assert!(first_haves.lock().unwrap().contains(&0));
+    assert!(second_haves.lock().unwrap().contains(&2));
+}
+
+/// Tests that a progress event is reported for every verified piece.
+#[tokio::test]
+async fn test_download_manager_reports_progress() {
+    let data = test_data(50_000);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let (progress, mut events) = tokio::sync::mpsc::channel(16);
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    )
+    .with_progress(progress);
+    manager.download().await.unwrap();
+    drop(manager);
+
+    let mut received = Vec::new();
+    while let Some(event) = events.recv().await {
+        received.push(event);
+    }
+    assert_eq!(received.len(), 4);
+    let last = received.last().unwrap();
+    assert_eq!((last.completed_pieces, last.total_pieces), (4, 4));
+    assert_eq!(last.downloaded_bytes, data.len() as u64);
+    assert_eq!(last.total_bytes, data.len() as u64);
+    assert!(last.bytes_per_sec > 0);
+    assert_eq!(last.eta, Some(Duration::ZERO));
+}
+
+/// Tests that every completion is counted exactly once for a torrent with
+/// thousands of small pieces.
+#[tokio::test]
+async fn test_download_manager_counts_many_piece_completions() {
+    let data = test_data(4000 * 16);
+    let torrent = make_torrent(&data, 16);
+    let seeder = MockSeeder::new(data.clone(), 16).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let (progress, mut events) = tokio::sync::mpsc::channel(4000);
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    )
+    .with_progress(progress);
+    assert_eq!(manager.download().await.unwrap(), data);
+    drop(manager);
+
+    let mut completed = Vec::new();
+    while let Some(event) = events.recv().await {
+        completed.push(event.completed_pieces);
+    }
+    assert_eq!(completed, (1..=4000).collect::<Vec<_>>());
+}
+
+/// Tests that `download_to` streams every verified piece into the output file.
+#[tokio::test]
+async fn test_download_manager_download_to_writes_file() {
+    let data = test_data(100_000);
+    let torrent = make_torrent(&data, 32 * 1024);
+    let mut peers = Vec::new();
+    for _ in 0..2 {
+        let seeder = MockSeeder::new(data.clone(), 32 * 1024).await;
+        peers.push(seeder.addr());
+        seeder.spawn();
+    }
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.bin");
+    manager.download_to(&path).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert_eq!(manager.stats().downloaded, data.len() as u64);
+}
+
+/// Tests that `download_to_store` writes every piece into a custom store.
+#[tokio::test]
+async fn test_download_manager_downloads_into_memory_store() {
+    let data = test_data(100_000);
+    let torrent = make_torrent(&data, 32 * 1024);
+    let seeder = MockSeeder::new(data.clone(), 32 * 1024).await;
+    let addr = seeder.addr();
+    seeder.spawn();
+
+    let info = torrent.info.clone().unwrap();
+    let manager = download::DownloadManager::new(
+        info.clone(),
+        vec![addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    );
+    assert!(manager.download_to_store().await.is_err());
+
+    let manager = manager.with_store(Box::new(MemoryStore::new(&info)));
+    manager.download_to_store().await.unwrap();
+    let store = manager.store().unwrap();
+    let mut downloaded = Vec::new();
+    for piece_index in 0..info.total_pieces() {
+        assert!(store.has_piece(piece_index));
+        downloaded.extend(store.read_piece(piece_index).await.unwrap());
+    }
+    assert_eq!(downloaded, data);
+}
+
+/// Tests that cancelling through a `DownloadHandle` ends the download promptly.
+#[tokio::test]
+async fn test_download_handle_cancel_then_wait_returns() {
+    let data = test_data(4 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    // Accepts connections but never completes a handshake
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            connections.push(stream);
+        }
+    });
+
+    let handle = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        vec![addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    )
+    .start();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    handle.cancel();
+
+    let err = tokio::time::timeout(Duration::from_secs(2), handle.wait())
+        .await
+        .expect("cancelled download should return promptly")
+        .unwrap_err();
+    assert!(err.downcast_ref::<download::DownloadCancelled>().is_some());
+}
+
+/// Tests that a paused download requests nothing until it is resumed.
+#[tokio::test]
+async fn test_download_handle_pause_blocks_new_requests() {
+    let data = test_data(4 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let addr = seeder.addr();
+    let requests = seeder.requests();
+    seeder.spawn();
+
+    let handle = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        vec![addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    )
+    .start();
+    handle.pause();
+    assert!(handle.is_paused());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(requests.lock().unwrap().is_empty());
+    assert!(handle.progress().borrow().is_none());
+
+    handle.resume();
+    let mut progress = handle.progress();
+    assert_eq!(handle.wait().await.unwrap(), data);
+    assert!(!requests.lock().unwrap().is_empty());
+    let latest = progress.borrow_and_update().clone().unwrap();
+    assert_eq!(latest.completed_pieces, 4);
+}
+
+/// Tests that the download summary credits each piece to the peer that served it.
+#[tokio::test]
+async fn test_download_summary_attributes_pieces_to_peers() {
+    let data = test_data(4 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let first = MockSeeder::new(data.clone(), 16384)
+        .await
+        .with_pieces(&[0, 1, 2]);
+    let second = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[3]);
+    let (first_addr, second_addr) = (first.addr(), second.addr());
+    first.spawn();
+    second.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        vec![first_addr, second_addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    );
+    assert!(manager.summary().is_none());
+    assert_eq!(manager.download().await.unwrap(), data);
+
+    let summary = manager.summary().unwrap();
+    assert_eq!(summary.total_pieces, 4);
+    assert_eq!(summary.per_peer.len(), 2);
+    assert_eq!(summary.per_peer[&first_addr].successful_pieces, 3);
+    assert_eq!(summary.per_peer[&second_addr].successful_pieces, 1);
+    assert!(summary.elapsed > Duration::ZERO);
+}
+
+/// Tests that only the selected pieces are downloaded, even when the others are unavailable.
+#[tokio::test]
+async fn test_download_manager_downloads_selected_pieces() {
+    let data = test_data(4 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384)
+        .await
+        .with_pieces(&[1, 3]);
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    );
+
+    let pieces = tokio::time::timeout(Duration::from_secs(5), manager.download_pieces(&[3, 1]))
+        .await
+        .expect("download should finish once the selected pieces are verified")
+        .unwrap();
+    assert_eq!(pieces.keys().copied().collect::<Vec<_>>(), vec![1, 3]);
+    assert_eq!(pieces[&1], data[16384..2 * 16384]);
+    assert_eq!(pieces[&3], data[3 * 16384..]);
+    assert_eq!(manager.stats().downloaded, 2 * 16384);
+
+    assert!(manager.download_pieces(&[4]).await.is_err());
+}
+
+/// Tests that the manager counts the verified bytes it downloads.
+#[tokio::test]
+async fn test_download_manager_counts_downloaded_bytes() {
+    let data = test_data(2 * 16384 + 1000);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    );
+    assert_eq!(manager.stats(), download::TransferStats::default());
+
+    manager.download().await.unwrap();
+
+    let stats = manager.stats();
+    assert_eq!(stats.downloaded, (16384 + 16384 + 1000) as u64);
+    assert_eq!(stats.uploaded, 0);
+}
+
+/// Tests that cancelling a download stops it and hands back the verified pieces.
+#[tokio::test]
+async fn test_download_manager_cancel_returns_verified_pieces() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[0]);
+    // A peer that accepts the connection but never completes the handshake keeps
+    // the download from finishing on its own
+    let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peers = vec![seeder.addr(), stalled.local_addr().unwrap()];
+    seeder.spawn();
+
+    let cancel = tokio_util::sync::CancellationToken::new();
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    )
+    .with_cancellation(cancel.clone());
+
+    let canceller = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        cancel.cancel();
+    });
+    let err = tokio::time::timeout(Duration::from_secs(5), manager.download())
+        .await
+        .expect("cancelled download should return promptly")
+        .unwrap_err();
+    canceller.await.unwrap();
+
+    let cancelled = err.downcast::<download::DownloadCancelled>().unwrap();
+    assert_eq!(cancelled.pieces[0].as_deref(), Some(&data[..16384]));
+    assert!(cancelled.pieces[1].is_none());
+}
+
+/// Tests that the manager asks the tracker for fresh peers once its own peers run out.
+#[tokio::test]
+async fn test_download_manager_reannounces_when_peers_exhausted() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let partial = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[0]);
+    let complete = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![partial.addr()];
+    let tracker_url = spawn_tracker(vec![complete.addr()]).await;
+    partial.spawn();
+    complete.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    )
+    .with_tracker(tracker::Tracker::new(tracker_url, Default::default()));
+
+    let downloaded = tokio::time::timeout(Duration::from_secs(10), manager.download())
+        .await
+        .expect("re-announced peer should complete the download")
+        .unwrap();
+    assert_eq!(downloaded, data);
+}
+
+/// Tests that peers reported more than once, by the caller and by the tracker, are
+/// only connected to once.
+#[tokio::test]
+async fn test_download_manager_connects_to_each_peer_once() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let partial = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[0]);
+    let complete = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![partial.addr(), partial.addr()];
+    let tracker_url = spawn_tracker(vec![partial.addr(), complete.addr()]).await;
+    let partial_connections = partial.connections();
+    let complete_connections = complete.connections();
+    partial.spawn();
+    complete.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    )
+    .with_tracker(tracker::Tracker::new(tracker_url, Default::default()));
+
+    let downloaded = tokio::time::timeout(Duration::from_secs(10), manager.download())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(downloaded, data);
+    let count = |c: &std::sync::atomic::AtomicUsize| c.load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(count(&partial_connections), 1);
+    assert_eq!(count(&complete_connections), 1);
+}
+
+/// Tests that re-announcing stops with an error when new peers bring no progress.
+#[tokio::test]
+async fn test_download_manager_reannounce_gives_up_without_progress() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let partial = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[0]);
+    let peers = vec![partial.addr()];
+    let tracker_url = spawn_tracker(peers.clone()).await;
+    partial.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            reannounce_rounds: 2,
+            ..Default::default()
+        },
+    )
+    .with_tracker(tracker::Tracker::new(tracker_url, Default::default()));
+
+    let err = tokio::time::timeout(Duration::from_secs(10), manager.download())
+        .await
+        .expect("exhausted swarm should not hang")
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("no progress after 2 re-announce rounds"),
+        "{}",
+        err
+    );
+}
+
+/// Tests that a download whose peers all stop responding is restarted with fresh
+/// peers from the tracker once no piece has completed for `stall_timeout`.
+#[tokio::test]
+async fn test_download_manager_recovers_from_stalled_swarm() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    // Unchokes and takes requests, but never sends any data
+    let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let silent_addr = silent.local_addr().unwrap();
+    let silent_connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let connections = silent_connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = silent.accept().await.unwrap();
+            connections.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut handshake = [0u8; 68];
+                stream.read_exact(&mut handshake).await?;
+                stream.write_all(&handshake).await?;
+                stream
+                    .write_all(&message::Message::Bitfield(vec![0b1100_0000]).to_bytes())
+                    .await?;
+                stream
+                    .write_all(&message::Message::Unchoke.to_bytes())
+                    .await?;
+                let mut buf = [0u8; 1024];
+                while stream.read(&mut buf).await? > 0 {}
+                Ok::<_, std::io::Error>(())
+            });
+        }
+    });
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let tracker_url = spawn_tracker(vec![seeder.addr()]).await;
+    let seeder_connections = seeder.connections();
+    seeder.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        vec![silent_addr],
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            stall_timeout: Duration::from_millis(500),
+            ..Default::default()
+        },
+    )
+    .with_tracker(tracker::Tracker::new(tracker_url, Default::default()));
+
+    let downloaded = tokio::time::timeout(Duration::from_secs(10), manager.download())
+        .await
+        .expect("stalled download should be restarted")
+        .unwrap();
+    assert_eq!(downloaded, data);
+    let count = |c: &std::sync::atomic::AtomicUsize| c.load(std::sync::atomic::Ordering::SeqCst);
+    // The tracker was asked for peers, and the silent peer, which made no progress,
+    // wasn't reconnected
+    assert!(count(&seeder_connections) >= 1);
+    assert_eq!(count(&silent_connections), 1);
+}
+
+/// Tests that no more than `max_peers` peers are connected at the same time.
+#[tokio::test]
+async fn test_download_manager_caps_concurrent_peers() {
+    let data = test_data(16384);
+    let torrent = make_torrent(&data, 16384);
+
+    // Peers that accept connections but never answer the handshake, so every
+    // connection that is opened stays open until the download is cancelled
+    let accepted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let mut peers = Vec::new();
+    for _ in 0..8 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        peers.push(listener.local_addr().unwrap());
+        let accepted = accepted.clone();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                held.push(stream);
+            }
+        });
+    }
+
+    let cancel = tokio_util::sync::CancellationToken::new();
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            max_peers: 2,
+            ..Default::default()
+        },
+    )
+    .with_cancellation(cancel.clone());
+
+    let canceller = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        cancel.cancel();
+    });
+    assert!(manager.download().await.is_err());
+    canceller.await.unwrap();
+
+    assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 2);
+}
+
+/// Tests that waiting peers take over free slots once connected peers finish.
+#[tokio::test]
+async fn test_download_manager_rotates_peers_through_slots() {
+    let data = test_data(4 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let mut peers = Vec::new();
+    for piece in 0..4 {
+        let seeder = MockSeeder::new(data.clone(), 16384)
+            .await
+            .with_pieces(&[piece]);
+        peers.push(seeder.addr());
+        seeder.spawn();
+    }
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig {
+            max_peers: 1,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(manager.download().await.unwrap(), data);
+}
+
+/// Tests that a download fails cleanly when no peer has one of the pieces.
+#[tokio::test]
+async fn test_download_manager_fails_when_piece_unavailable() {
+    let data = test_data(3 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384)
+        .await
+        .with_pieces(&[0, 2]);
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let manager = download::DownloadManager::new(
+        torrent.info.clone().unwrap(),
+        peers,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+        download::DownloadConfig::default(),
+    );
+
+    let err = manager.download().await.unwrap_err();
+    assert!(
+        err.to_string().contains("piece 1"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+/// Tests the Downloader API used by the CLI against explicitly provided peers.
+#[tokio::test]
+async fn test_downloader_download_all_and_piece() {
+    let data = test_data(50_000);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let piece = downloader.download_piece(1).await.unwrap();
+    assert_eq!(piece, data[16384..32768]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+    downloader
+        .download_all(output.to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(output).unwrap(), data);
+}
+
+/// Tests that a torrent with a one-entry file list is saved like a single-file torrent.
+#[tokio::test]
+async fn test_downloader_single_entry_files_writes_output() {
+    let data = test_data(20_000);
+    let mut torrent = make_torrent(&data, 16384);
+    if let Some(info) = torrent.info.as_mut() {
+        info.files = Some(vec![metainfo::FileEntry {
+            length: data.len(),
+            path: vec!["test.bin".into()],
+            md5sum: None,
+        }]);
+    }
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+    downloader
+        .download_all(output.to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(output).unwrap(), data);
+}
+
+/// Tests that an existing output is only replaced when overwriting is forced.
+#[tokio::test]
+async fn test_downloader_overwrites_existing_output_only_when_forced() {
+    let data = test_data(20_000);
+    let torrent = make_torrent(&data, 16384);
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+    std::fs::write(&output, b"previous download").unwrap();
+
+    let info = torrent.info.clone().unwrap();
+    assert!(download::output_destination(&output, &info, false).is_err());
+    assert_eq!(std::fs::read(&output).unwrap(), b"previous download");
+
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+    let destination = download::output_destination(&output, &info, true).unwrap();
+    download::Downloader::with_peers(torrent, peers)
+        .unwrap()
+        .download_all(destination.to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), data);
+}
+
+/// Tests that a zero-length file between two others is created empty and doesn't
+/// shift the data of the files after it.
+#[tokio::test]
+async fn test_downloader_multi_file_with_zero_length_file() {
+    let data = test_data(30_000);
+    let mut torrent = make_torrent(&data, 16384);
+    if let Some(info) = torrent.info.as_mut() {
+        info.name = "album".into();
+        info.files = Some(vec![
+            metainfo::FileEntry {
+                length: 10_000,
+                path: vec!["one.bin".into()],
+                md5sum: None,
+            },
+            metainfo::FileEntry {
+                length: 0,
+                path: vec!["empty.txt".into()],
+                md5sum: None,
+            },
+            metainfo::FileEntry {
+                length: 20_000,
+                path: vec!["two.bin".into()],
+                md5sum: None,
+            },
+        ]);
+    }
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    downloader
+        .download_all(dir.path().to_str().unwrap())
+        .await
+        .unwrap();
+    let base = dir.path().join("album");
+    assert_eq!(std::fs::read(base.join("one.bin")).unwrap(), data[..10_000]);
+    assert_eq!(std::fs::read(base.join("empty.txt")).unwrap(), b"");
+    assert_eq!(std::fs::read(base.join("two.bin")).unwrap(), data[10_000..]);
+}
+
+/// Tests that a single file of a multi-file torrent is downloaded from just the
+/// pieces it overlaps.
+#[tokio::test]
+async fn test_downloader_download_file_fetches_only_its_pieces() {
+    let data = test_data(4 * 16384);
+    let mut torrent = make_torrent(&data, 16384);
+    if let Some(info) = torrent.info.as_mut() {
+        info.name = "season".into();
+        let entry = |length, name: &str| metainfo::FileEntry {
+            length,
+            path: vec!["episodes".into(), name.into()],
+            md5sum: None,
+        };
+        info.files = Some(vec![
+            entry(20_000, "one.mkv"),
+            entry(20_000, "two.mkv"),
+            entry(25_536, "three.mkv"),
+        ]);
+    }
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let requests = seeder.requests();
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = downloader
+        .download_file("episodes/two.mkv", dir.path().to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(path, dir.path().join("season/episodes/two.mkv"));
+    assert_eq!(std::fs::read(&path).unwrap(), data[20_000..40_000]);
+    assert!(!dir.path().join("season/episodes/one.mkv").exists());
+    // Bytes 20000..40000 lie in pieces 1 and 2
+    let mut requested = requests.lock().unwrap().clone();
+    requested.dedup();
+    assert_eq!(requested, vec![1, 2]);
+
+    // Files can be picked by index too
+    let path = downloader
+        .download_file("0", dir.path().to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), data[..20_000]);
+    assert!(downloader
+        .download_file("episodes/four.mkv", dir.path().to_str().unwrap())
+        .await
+        .is_err());
+}
+
+/// Tests that selecting pieces of a multi-file torrent fails instead of writing the
+/// pieces over the output directory.
+#[tokio::test]
+async fn test_downloader_download_selected_rejects_multi_file() {
+    let data = test_data(2 * 16384);
+    let mut torrent = make_torrent(&data, 16384);
+    if let Some(info) = torrent.info.as_mut() {
+        let entry = |length, name: &str| metainfo::FileEntry {
+            length,
+            path: vec![name.into()],
+            md5sum: None,
+        };
+        info.files = Some(vec![entry(16384, "one.bin"), entry(16384, "two.bin")]);
+    }
+    let peers = vec!["127.0.0.1:1".parse().unwrap()];
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let err = downloader
+        .download_selected(dir.path().to_str().unwrap(), &[0])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("multi-file"), "{}", err);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+/// Tests that downloaded files are checked against the `md5sum` of their entries.
+#[tokio::test]
+async fn test_downloader_verifies_file_md5sums() {
+    let data = test_data(30_000);
+    let mut torrent = make_torrent(&data, 16384);
+    if let Some(info) = torrent.info.as_mut() {
+        info.name = "album".into();
+        info.files = Some(vec![
+            metainfo::FileEntry {
+                length: 10_000,
+                path: vec!["one.bin".into()],
+                md5sum: Some(hex::encode(Md5::digest(&data[..10_000]))),
+            },
+            metainfo::FileEntry {
+                length: 20_000,
+                path: vec!["two.bin".into()],
+                md5sum: Some(hex::encode(Md5::digest(&data[10_000..])).to_uppercase()),
+            },
+        ]);
+    }
+    let info = torrent.info.clone().unwrap();
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    downloader
+        .download_all(dir.path().to_str().unwrap())
+        .await
+        .unwrap();
+    download::verify_md5(dir.path(), &info).await.unwrap();
+
+    let two = dir.path().join("album").join("two.bin");
+    std::fs::write(&two, &data[..20_000]).unwrap();
+    let err = download::verify_md5(dir.path(), &info).await.unwrap_err();
+    assert!(
+        err.to_string().contains("MD5 mismatch in 1 file(s)"),
+        "{}",
+        err
+    );
+    assert!(err.to_string().contains("two.bin"), "{}", err);
+}
+
+/// Tests that repairing a file re-downloads only its corrupt piece, in place.
+#[tokio::test]
+async fn test_downloader_repair_restores_corrupt_piece() {
+    let data = test_data(3 * 16384 + 500);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    let requests = seeder.requests();
+    seeder.spawn();
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+    let mut corrupt = data.clone();
+    corrupt[16384 + 10] ^= 0xFF;
+    std::fs::write(&output, &corrupt).unwrap();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+    let repaired = downloader.repair(output.to_str().unwrap()).await.unwrap();
+
+    assert_eq!(repaired, vec![1]);
+    assert_eq!(std::fs::read(&output).unwrap(), data);
+    assert!(requests.lock().unwrap().iter().all(|&index| index == 1));
+
+    // A valid file is left alone
+    assert!(downloader
+        .repair(output.to_str().unwrap())
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+/// Tests that a single piece is fetched from whichever peer actually has it.
+#[tokio::test]
+async fn test_download_piece_from_any_skips_peers_without_piece() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let without = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[0]);
+    let with = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![without.addr(), with.addr()];
+    without.spawn();
+    with.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let piece = downloader.download_piece_from_any(1).await.unwrap();
+    assert_eq!(piece, data[16384..]);
+}
+
+/// Tests that a peer which accepts the connection but never answers the handshake
+/// doesn't hold up a single-piece download from a live peer behind it.
+#[tokio::test]
+async fn test_download_piece_from_any_does_not_wait_for_dead_peer() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dead_addr = dead.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = dead.accept().await {
+            held.push(stream);
+        }
+    });
+    let live = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![dead_addr, live.addr()];
+    live.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let started = std::time::Instant::now();
+    let piece = downloader.download_piece_from_any(1).await.unwrap();
+    assert_eq!(piece, data[16384..]);
+    // The dead peer's handshake only times out after 10 seconds
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+/// Tests that a single-piece download honours the downloader's `piece_timeout`.
+#[tokio::test]
+async fn test_download_piece_from_any_uses_configured_piece_timeout() {
+    let data = test_data(16384);
+    let torrent = make_torrent(&data, 16384);
+    let mute = MockPeer::new().await;
+    let peers = vec![mute.addr()];
+    // Unchokes but never answers a request
+    mute.handle_connection(|mut stream| async move {
+        let mut handshake = [0u8; 68];
+        stream.read_exact(&mut handshake).await.unwrap();
+        stream.write_all(&handshake).await.unwrap();
+        stream
+            .write_all(&message::Message::Bitfield(vec![0x80]).to_bytes())
+            .await
+            .unwrap();
+        stream
+            .write_all(&message::Message::Unchoke.to_bytes())
+            .await
+            .unwrap();
+        let mut sink = Vec::new();
+        let _ = stream.read_to_end(&mut sink).await;
+    })
+    .await;
+
+    let downloader = download::Downloader::with_peers(torrent, peers)
+        .unwrap()
+        .with_config(download::DownloadConfig {
+            piece_timeout: Duration::from_millis(300),
+            ..Default::default()
+        });
+
+    let started = std::time::Instant::now();
+    let err = downloader.download_piece_from_any(0).await.unwrap_err();
+    assert!(
+        err.to_string().contains("after trying all peers"),
+        "{}",
+        err
+    );
+    // The default piece timeout is 30 seconds, and a block times out after 15
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+/// Tests that a download missing a piece fails instead of writing a file with a hole.
+#[tokio::test]
+async fn test_downloader_download_all_missing_piece_writes_nothing() {
+    let data = test_data(3 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let seeder = MockSeeder::new(data.clone(), 16384)
+        .await
+        .with_pieces(&[0, 2]);
+    let peers = vec![seeder.addr()];
+    seeder.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+
+    let err = downloader
+        .download_all(output.to_str().unwrap())
+        .await
+        .unwrap_err();
+
+    assert!(
+        err.to_string().contains("piece 1"),
+        "unexpected error: {}",
+        err
+    );
+    assert!(!output.exists());
+    assert!(!dir.path().join("out.bin.part").exists());
+}
+
+/// Tests that running a cancelled download again keeps the pieces saved in the
+/// `.part` file and only requests the missing ones, and that the tracker is told
+/// how much the cancelled session transferred.
+#[tokio::test]
+async fn test_downloader_download_all_resumes_after_cancel() {
+    let data = test_data(3 * 16384);
+    let (announce, announces) = spawn_logging_tracker(Vec::new()).await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+
+    let partial = MockSeeder::new(data.clone(), 16384)
+        .await
+        .with_pieces(&[0, 2]);
+    // A peer that never completes the handshake keeps the download from finishing
+    let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peers = vec![partial.addr(), stalled.local_addr().unwrap()];
+    partial.spawn();
+    let torrent = TorrentMetainfo::new(
+        Some(announce),
+        TorrentInfo::from_data("test.bin", 16384, &data),
+    );
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+    let cancel = downloader.cancellation_token();
+    let canceller = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        cancel.cancel();
+    });
+    let err = downloader
+        .download_all(output.to_str().unwrap())
+        .await
+        .unwrap_err();
+    canceller.await.unwrap();
+    assert!(
+        err.to_string().contains("2 of 3 pieces verified"),
+        "unexpected error: {}",
+        err
+    );
+    assert!(dir.path().join("out.bin.part").exists());
+    let stopped = announces.lock().unwrap().pop().unwrap();
+    assert!(
+        stopped.contains("event=stopped"),
+        "unexpected announce: {}",
+        stopped
+    );
+    assert!(
+        stopped.contains("downloaded=32768"),
+        "unexpected announce: {}",
+        stopped
+    );
+    assert!(
+        stopped.contains("uploaded=0"),
+        "unexpected announce: {}",
+        stopped
+    );
+    assert!(
+        stopped.contains("left=16384"),
+        "unexpected announce: {}",
+        stopped
+    );
+
+    let seeder = MockSeeder::new(data.clone(), 16384).await;
+    let peers = vec![seeder.addr()];
+    let requests = seeder.requests();
+    seeder.spawn();
+    let downloader = download::Downloader::with_peers(make_torrent(&data, 16384), peers).unwrap();
+    downloader
+        .download_all(output.to_str().unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&output).unwrap(), data);
+    let requests = requests.lock().unwrap();
+    assert!(!requests.is_empty());
+    assert!(requests.iter().all(|&index| index == 1));
+}
+
+/// Tests a full download from this crate's own seeder over loopback.
+#[tokio::test]
+async fn test_end_to_end_download_from_seeder() {
+    let data = test_data(200_000);
+    let (torrent, seeder_addr) = spawn_seeder(&data, 32 * 1024).await;
+
+    let downloader = download::Downloader::with_peers(torrent, vec![seeder_addr]).unwrap();
+
+    let last_piece = downloader.download_piece(6).await.unwrap();
+    assert_eq!(last_piece, data[6 * 32 * 1024..]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+    downloader
+        .download_all(output.to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(output).unwrap(), data);
+}
+
+/// Tests that a piece spanning many blocks arrives intact when several block
+/// messages are read through the connection's buffer at once.
+#[tokio::test]
+async fn test_peer_downloads_large_piece_through_read_buffer() {
+    let piece_length = 1024 * 1024;
+    let data = test_data(piece_length + 50_000);
+    let (torrent, seeder_addr) = spawn_seeder(&data, piece_length).await;
+    let mut peer = peer::Peer::new(
+        seeder_addr,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+    );
+    peer.connect().await.unwrap();
+    peer.wait_for_bitfield().await.unwrap();
+
+    let started = std::time::Instant::now();
+    let pieces = peer
+        .download_pieces(&[(0, piece_length), (1, 50_000)])
+        .await
+        .unwrap();
+    debug!("Downloaded {} bytes in {:?}", data.len(), started.elapsed());
+    assert_eq!(pieces[0], data[..piece_length]);
+    assert_eq!(pieces[1], data[piece_length..]);
+}
+
+/// Tests that the seeder refuses requests outside the bounds of a piece.
+#[tokio::test]
+async fn test_seeder_rejects_out_of_range_request() {
+    let data = test_data(20_000);
+    let (torrent, seeder_addr) = spawn_seeder(&data, 16384).await;
+    let mut peer = peer::Peer::new(
+        seeder_addr,
+        PeerConfig {
+            info_hash: torrent.info_hash().unwrap(),
+            ..Default::default()
+        },
+    );
+    peer.connect().await.unwrap();
+    peer.wait_for_bitfield().await.unwrap();
+    peer.wait_for_unchoke().await.unwrap();
+
+    // The last piece is only 3616 bytes long
+    peer.send_message(message::Message::Request {
+        index: 1,
+        begin: 0,
+        length: 16384,
+    })
+    .await
+    .unwrap();
+    assert!(peer.receive_message().await.is_err());
+}
+
+/// Tests that a piece no peer advertises fails fast with a descriptive error.
+#[tokio::test]
+async fn test_download_piece_fails_fast_when_no_peer_has_piece() {
+    let data = test_data(3 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let first = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[0]);
+    let second = MockSeeder::new(data.clone(), 16384).await.with_pieces(&[2]);
+    let peers = vec![first.addr(), second.addr()];
+    first.spawn();
+    second.spawn();
+
+    let downloader = download::Downloader::with_peers(torrent, peers).unwrap();
+
+    let started = std::time::Instant::now();
+    let err = downloader.download_piece(1).await.unwrap_err();
+
+    assert!(err.is::<download::PieceUnavailable>());
+    assert_eq!(err.to_string(), "no connected peer has piece 1");
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+/// Tests a magnet download end to end: metadata and pieces both come from this
+/// crate's own seeder.
+#[tokio::test]
+async fn test_magnet_download_from_seeder() {
+    // Enough pieces that the metadata spans more than one 16 KiB metadata piece
+    let data = test_data(1000 * 1024);
+    let (torrent, seeder_addr) = spawn_seeder(&data, 1024).await;
+    let magnet = magnet_link::MagnetLink {
+        info_hash: torrent.info_hash().unwrap(),
+        name: None,
+        tracker: None,
+        web_seeds: Vec::new(),
+        peer_hints: Vec::new(),
+    };
+
+    let downloader = download::Downloader::from_magnet_with_peers(&magnet, vec![seeder_addr])
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.bin");
+    downloader
+        .download_all(output.to_str().unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(output).unwrap(), data);
+}
+
+/// Tests that metadata fetched for a magnet link can be saved as a torrent file
+/// that parses back to the same info hash and tracker.
+#[tokio::test]
+async fn test_magnet_save_writes_matching_torrent() {
+    let data = test_data(64 * 1024);
+    let (torrent, seeder_addr) = spawn_seeder(&data, 16384).await;
+    let magnet = magnet_link::MagnetLink::new(torrent.info_hash().unwrap())
+        .with_tracker("http://tracker.example/announce")
+        .with_web_seed("http://seed.example/test.bin");
+
+    let downloader = download::Downloader::from_magnet_with_peers(&magnet, vec![seeder_addr])
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("saved.torrent");
+    downloader.save_torrent(&path).await.unwrap();
+
+    let saved = TorrentMetainfo::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(saved.info_hash().unwrap(), magnet.info_hash);
+    assert_eq!(
+        saved.announce.as_deref(),
+        Some("http://tracker.example/announce")
+    );
+    assert_eq!(saved.url_list, vec!["http://seed.example/test.bin"]);
+    let info = saved.info.unwrap();
+    assert_eq!(info.pieces, torrent.info.unwrap().pieces);
+    assert_eq!(info.length, data.len());
+}
+
+/// Tests that metadata not matching the magnet link's info hash is refused.
+#[tokio::test]
+async fn test_magnet_metadata_must_match_info_hash() {
+    let data = test_data(4 * 16384);
+    let (torrent, seeder_addr) = spawn_seeder(&data, 16384).await;
+    let info_hash = torrent.info_hash().unwrap();
+
+    let peer_config = PeerConfig {
+        info_hash,
+        ..Default::default()
+    };
+    let mut peer = peer::Peer::new(seeder_addr, peer_config);
+    peer.connect().await.unwrap();
+    let mut wrong_hash = info_hash;
+    wrong_hash[0] ^= 0xFF;
+
+    let err = metadata::fetch_metadata(&mut peer, wrong_hash)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("info hash"), "{}", err);
+}
+
+/// Tests that the seeder accepts handshakes from IPv6 peers.
+#[tokio::test]
+async fn test_seeder_handshake_over_ipv6() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let peer_config = PeerConfig {
+        info_hash: torrent.info_hash().unwrap(),
+        ..Default::default()
+    };
+    let seeder = seeder::Seeder::bind(
+        "[::1]:0".parse().unwrap(),
+        torrent.info.clone().unwrap(),
+        data,
+        peer_config.clone(),
+    )
+    .await
+    .unwrap();
+    let addr = seeder.local_addr().unwrap();
+    assert!(addr.is_ipv6());
+    tokio::spawn(seeder.run());
+
+    let mut peer = peer::Peer::new(addr, peer_config.clone());
+    peer.connect().await.unwrap();
+    assert_eq!(peer.peer_id, Some(peer_config.peer_id));
+}
+
+/// Tests that a dual-stack listener serves both address families and announces
+/// the port the OS picked.
+#[tokio::test]
+async fn test_seeder_listens_dual_stack_and_reports_bound_port() {
+    let data = test_data(2 * 16384);
+    let torrent = make_torrent(&data, 16384);
+    let peer_config = PeerConfig {
+        info_hash: torrent.info_hash().unwrap(),
+        port: 0,
+        ..Default::default()
+    };
+    let seeder = seeder::Seeder::listen(torrent.info.clone().unwrap(), data, peer_config.clone())
+        .await
+        .unwrap();
+    let port = seeder.local_addr().unwrap().port();
+    assert_ne!(port, 0);
+    assert_eq!(seeder.tracker_config().unwrap().port, port);
+    tokio::spawn(seeder.run());
+
+    for ip in ["127.0.0.1", "::1"] {
+        let addr = std::net::SocketAddr::new(ip.parse().unwrap(), port);
+        let mut peer = peer::Peer::new(addr, peer_config.clone());
+        peer.connect().await.unwrap();
+    }
+}
+
+/// Tests that a handshake for a torrent the seeder doesn't serve is dropped
+/// without a reply.
+#[tokio::test]
+async fn test_seeder_drops_handshake_for_unknown_info_hash() {
+    let data = test_data(2 * 16384);
+    let (torrent, seeder_addr) = spawn_seeder(&data, 16384).await;
+    let mut unknown = torrent.info_hash().unwrap();
+    unknown[0] ^= 0xFF;
+
+    let mut stream = TcpStream::connect(seeder_addr).await.unwrap();
+    let mut handshake = vec![19];
+    handshake.extend_from_slice(crate::PROTOCOL.as_bytes());
+    handshake.extend_from_slice(&[0; 8]);
+    handshake.extend_from_slice(&unknown);
+    handshake.extend_from_slice(&[1; 20]);
+    stream.write_all(&handshake).await.unwrap();
+
+    let mut buf = [0u8; 68];
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)), "{:?}", read);
+}
+
+/// Tests that one listener serves several torrents, dispatching each inbound
+/// connection by the info hash in its handshake.
+#[tokio::test]
+async fn test_seeder_dispatches_by_info_hash() {
+    let first_data = test_data(2 * 16384);
+    let second_data: Vec<u8> = test_data(3 * 16384).into_iter().rev().collect();
+    let first = make_torrent(&first_data, 16384);
+    let second = make_torrent(&second_data, 16384);
+    let config_for = |torrent: &TorrentMetainfo| PeerConfig {
+        info_hash: torrent.info_hash().unwrap(),
+        ..Default::default()
+    };
+
+    let mut seeder = seeder::Seeder::bind(
+        "127.0.0.1:0".parse().unwrap(),
+        first.info.clone().unwrap(),
+        first_data.clone(),
+        config_for(&first),
+    )
+    .await
+    .unwrap();
+    seeder
+        .add_torrent(
+            second.info.clone().unwrap(),
+            second_data.clone(),
+            config_for(&second),
+        )
+        .unwrap();
+    assert!(seeder
+        .add_torrent(
+            second.info.clone().unwrap(),
+            second_data.clone(),
+            config_for(&second),
+        )
+        .is_err());
+    let addr = seeder.local_addr().unwrap();
+    tokio::spawn(seeder.run());
+
+    for (torrent, data) in [(first, first_data), (second, second_data)] {
+        let downloader = download::Downloader::with_peers(torrent, vec![addr]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.bin");
+        downloader
+            .download_all(output.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(output).unwrap(), data);
+    }
+}
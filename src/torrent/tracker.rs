@@ -2,14 +2,42 @@
 //!
 //! Handles communication with BitTorrent trackers to discover peers
 //! and obtain information about the swarm.
+//!
+//! `get_peers` performs a one-off announce, and `announce` does the same but returns
+//! the whole `TrackerResponse` including swarm counts. `Tracker` keeps the state needed for
+//! repeated announces to the same tracker: the `tracker id` it asks clients to echo
+//! back and the `min interval` it allows between announces.
+//!
+//! A `Tracker` that fails an announce, or answers with a `failure reason`, is backed
+//! off: it isn't contacted again until its `retry in` (BEP 31) or an exponentially
+//! growing delay has passed. `announce_all` relies on this to skip failed trackers
+//! when aggregating peers from an announce-list.
+//!
+//! Peers are asked for in compact form. Some older trackers refuse that, so when a
+//! compact announce is rejected with a client error, a failure mentioning compact,
+//! or a response that can't be parsed, it is sent again with `compact=0` and the
+//! dictionary-form peer list is read instead.
 
 use anyhow::Result;
+use once_cell::sync::Lazy;
 use serde::Serialize;
-use std::net::Ipv4Addr;
-use tracing::info;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, instrument, warn};
 
 use super::peer::PeerId;
-use crate::{bencode::Bencode, utils::serialize_peer_id, PEER_ID};
+use crate::{
+    bencode::{bvalue::BValue, Bencode},
+    utils::{percent_encode, serialize_peer_id},
+    PEER_ID,
+};
+
+/// `User-Agent` sent to trackers unless configured otherwise
+pub const DEFAULT_USER_AGENT: &str = concat!("codecrafters-bt/", env!("CARGO_PKG_VERSION"));
+
+/// HTTP client shared by all announces so connections to a tracker are pooled.
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
 
 /// Configuration options for tracker requests.
 #[derive(Debug)]
@@ -18,8 +46,51 @@ pub struct TrackerConfig {
     pub peer_id: PeerId,
     /// The port we're listening on for incoming connections
     pub port: u16,
-    /// Whether to request compact peer lists
+    /// Whether to request compact peer lists; turned off for a `Tracker` once it
+    /// has only answered an announce with `compact=0`
     pub compact: bool,
+    /// Maximum time to wait for the tracker to respond to an announce
+    pub request_timeout: Duration,
+    /// Number of peers to ask the tracker for
+    pub numwant: Option<u32>,
+    /// Client-chosen identifier that lets the tracker recognise us across IP changes
+    pub key: Option<String>,
+    /// Total number of announce attempts before giving up on transient errors
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled after every further failure, up to
+    /// `max_failure_backoff`
+    pub retry_base_delay: Duration,
+    /// Lifecycle event to report with the announce, if any
+    pub event: Option<AnnounceEvent>,
+    /// `User-Agent` header sent with announces; some trackers filter on it
+    pub user_agent: String,
+    /// External address, IPv4 or IPv6, the tracker should hand out to other peers
+    /// instead of the one the announce arrives from (the `ip` parameter)
+    pub announce_ip: Option<IpAddr>,
+    /// How long a `Tracker` is skipped after a failed announce when the tracker
+    /// didn't say (`retry in`); doubled after every further failure in a row
+    pub failure_backoff: Duration,
+    /// Upper bound on the doubled `failure_backoff`
+    pub max_failure_backoff: Duration,
+    /// Bytes sent to other peers this session, reported as `uploaded`
+    pub uploaded: u64,
+    /// Bytes of verified piece data received this session, reported as `downloaded`
+    pub downloaded: u64,
+}
+
+/// Lifecycle events reported to the tracker with an announce.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnounceEvent {
+    /// The download has just begun
+    Started,
+    /// The download finished
+    Completed,
+    /// The client is shutting down and leaving the swarm
+    Stopped,
+    /// The client stopped downloading but keeps seeding the pieces it has, as a
+    /// partial seed (BEP 21)
+    Paused,
 }
 
 impl Default for TrackerConfig {
@@ -28,6 +99,18 @@ impl Default for TrackerConfig {
             peer_id: *PEER_ID,
             port: 6881,
             compact: true,
+            request_timeout: Duration::from_secs(15),
+            numwant: Some(50),
+            key: None,
+            max_attempts: 3,
+            retry_base_delay: Duration::from_millis(500),
+            event: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            announce_ip: None,
+            failure_backoff: Duration::from_secs(60),
+            max_failure_backoff: Duration::from_secs(30 * 60),
+            uploaded: 0,
+            downloaded: 0,
         }
     }
 }
@@ -41,9 +124,71 @@ struct TrackerRequest<'a> {
     downloaded: u64,
     left: u64,
     compact: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    numwant: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<AnnounceEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trackerid: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<IpAddr>,
+}
+
+/// The fields of a successful announce response.
+///
+/// A response with a `failure reason` isn't successful and is returned as a
+/// `TrackerFailure` error instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackerResponse {
+    /// Peers the tracker handed out
+    pub peers: Vec<Peer>,
+    /// How long the tracker wants clients to wait before announcing again
+    pub interval: Option<Duration>,
+    /// Number of seeders in the swarm, if the tracker reported it
+    pub complete: Option<u64>,
+    /// Number of leechers in the swarm, if the tracker reported it
+    pub incomplete: Option<u64>,
+    /// Shortest time the tracker allows between announces
+    pub min_interval: Option<Duration>,
+    /// Identifier the tracker wants echoed back on later announces
+    pub tracker_id: Option<String>,
+    /// Non-fatal `warning message` sent along with the peers
+    pub warning: Option<String>,
+}
+
+/// A `failure reason` returned by the tracker instead of a peer list.
+#[derive(Debug, thiserror::Error)]
+#[error("Tracker returned failure: {reason}")]
+pub struct TrackerFailure {
+    /// Human-readable reason the tracker gave
+    pub reason: String,
+    /// When the tracker said it may be asked again, if it did
+    pub retry_in: Option<RetryIn>,
+}
+
+/// The `retry in` key of a failure response (BEP 31).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryIn {
+    /// The announce may be retried after this long
+    After(Duration),
+    /// The tracker will never accept the announce; don't retry it
+    Never,
+}
+
+/// Returned by `Tracker::announce` while the tracker is backed off after a failure.
+#[derive(Debug, thiserror::Error)]
+#[error("Tracker {url} is backed off after {failures} failed announces")]
+pub struct TrackerBackedOff {
+    /// Announce URL of the tracker
+    pub url: String,
+    /// Number of announces in a row that failed
+    pub failures: u32,
 }
 
 /// Represents a peer in the swarm.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Peer {
     /// IPv4 address of the peer
     pub ip: Ipv4Addr,
@@ -57,9 +202,132 @@ impl std::fmt::Display for Peer {
     }
 }
 
-/// URL encodes a byte slice for use in tracker requests.
-fn urlencode(bytes: &[u8]) -> String {
-    bytes.iter().map(|&b| format!("%{:02x}", b)).collect()
+/// Builds the full announce URL, including the query string, for a tracker request.
+///
+/// Parameters already in `announce_url`, such as a private tracker's passkey, are
+/// kept and the request's parameters appended after them.
+fn build_announce_url(
+    announce_url: &str,
+    info_hash: [u8; 20],
+    file_length: Option<u64>,
+    config: &TrackerConfig,
+    tracker_id: Option<&str>,
+) -> Result<String> {
+    let request = TrackerRequest {
+        peer_id: &serialize_peer_id(&config.peer_id),
+        port: config.port,
+        uploaded: config.uploaded,
+        downloaded: config.downloaded,
+        left: file_length.unwrap_or(16848),
+        compact: config.compact as u8,
+        numwant: config.numwant,
+        key: config.key.as_deref(),
+        event: config.event,
+        trackerid: tracker_id,
+        ip: config.announce_ip,
+    };
+
+    let url_params = serde_urlencoded::to_string(&request)?;
+    // A fragment is never sent to the server, so the query has to go before it
+    let (base, fragment) = match announce_url.split_once('#') {
+        Some((base, fragment)) => (base, format!("#{}", fragment)),
+        None => (announce_url, String::new()),
+    };
+    let separator = match base.find('?') {
+        None => "?",
+        Some(_) if base.ends_with('?') || base.ends_with('&') => "",
+        Some(_) => "&",
+    };
+    Ok(format!(
+        "{}{}{}&info_hash={}{}",
+        base,
+        separator,
+        url_params,
+        percent_encode(&info_hash),
+        fragment
+    ))
+}
+
+/// Sends a single announce, treating non-2xx statuses as errors.
+///
+/// The client asks for a gzip-compressed response, which keeps large peer lists
+/// small, and decompresses it transparently.
+async fn announce_once(url: &str, config: &TrackerConfig) -> reqwest::Result<Vec<u8>> {
+    let response = HTTP_CLIENT
+        .get(url)
+        .header(reqwest::header::USER_AGENT, &config.user_agent)
+        .timeout(config.request_timeout)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Whether a failed announce is worth retrying: connection failures, timeouts
+/// and server errors are usually transient, anything else is not.
+fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_connect()
+        || err.is_timeout()
+        || err.status().is_some_and(|status| status.is_server_error())
+}
+
+/// Sends an announce, retrying transient failures with exponential backoff.
+async fn announce_with_retry(url: &str, config: &TrackerConfig) -> Result<Vec<u8>> {
+    let mut attempt = 1;
+    loop {
+        match announce_once(url, config).await {
+            Ok(bytes) => return Ok(bytes),
+            Err(e) if attempt < config.max_attempts && is_transient(&e) => {
+                let delay = retry_delay(config, attempt);
+                warn!(
+                    "Announce attempt {}/{} failed: {}; retrying in {:?}",
+                    attempt, config.max_attempts, e, delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Wait before retrying after `attempt` failed announces: `retry_base_delay` doubled
+/// for each failure after the first, capped at `max_failure_backoff`.
+fn retry_delay(config: &TrackerConfig, attempt: u32) -> Duration {
+    config
+        .retry_base_delay
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(config.max_failure_backoff)
+}
+
+/// Reports a lifecycle event (such as `stopped`) to the tracker.
+///
+/// Unlike `get_peers`, the reply does not need to contain a peer list; only a
+/// `failure reason` is treated as an error.
+#[instrument(name = "announce", skip_all, fields(tracker = announce_url, ?event))]
+pub async fn announce_event(
+    announce_url: &str,
+    info_hash: [u8; 20],
+    left: Option<u64>,
+    event: AnnounceEvent,
+    config: Option<TrackerConfig>,
+) -> Result<()> {
+    let config = TrackerConfig {
+        event: Some(event),
+        ..config.unwrap_or_default()
+    };
+    let url = build_announce_url(announce_url, info_hash, left, &config, None)?;
+    info!("Announcing {:?} to {}", event, announce_url);
+
+    let response_bytes = announce_with_retry(&url, &config).await?;
+    let bvalue = Bencode::decode_bytes(&response_bytes)?;
+    if let Some(reason) = bvalue.get_dict()?.get(b"failure reason".as_slice()) {
+        return Err(anyhow::anyhow!(
+            "Tracker returned failure: {}",
+            String::from_utf8_lossy(reason.get_bytes()?)
+        ));
+    }
+    Ok(())
 }
 
 /// Contacts a tracker to get a list of peers for a torrent.
@@ -74,53 +342,1128 @@ fn urlencode(bytes: &[u8]) -> String {
 /// # Returns
 ///
 /// Returns a vector of peers on success, or an error if the tracker request fails.
+/// Transient failures (connection errors, timeouts, 5xx) are retried according to
+/// `config`; a tracker `failure reason` is returned immediately.
 pub async fn get_peers(
     announce_url: &str,
     info_hash: [u8; 20],
     file_length: Option<u64>,
     config: Option<TrackerConfig>,
 ) -> Result<Vec<Peer>> {
-    let config = config.unwrap_or_default();
+    Ok(announce(announce_url, info_hash, file_length, config)
+        .await?
+        .peers)
+}
+
+/// Announces to a tracker and returns its full response, including the swarm
+/// counts alongside the peer list.
+///
+/// Retries and errors behave as in `get_peers`.
+#[instrument(name = "announce", skip_all, fields(tracker = announce_url))]
+pub async fn announce(
+    announce_url: &str,
+    info_hash: [u8; 20],
+    file_length: Option<u64>,
+    config: Option<TrackerConfig>,
+) -> Result<TrackerResponse> {
+    let mut config = config.unwrap_or_default();
 
     info!("Getting peers for tracker URL: {}", announce_url);
-    let client = reqwest::Client::new();
+    announce_with_fallback(announce_url, info_hash, file_length, &mut config, None).await
+}
 
-    let request = TrackerRequest {
-        peer_id: &serialize_peer_id(&config.peer_id),
-        port: config.port,
-        uploaded: 0,
-        downloaded: 0,
-        left: file_length.unwrap_or(16848),
-        compact: config.compact as u8,
+/// Sends an announce and parses the response, asking once more with `compact=0` if
+/// the tracker rejects the compact request (see `rejects_compact`).
+///
+/// `config.compact` is left off when only the non-compact announce succeeded. If
+/// that fails too, the error from the compact announce is returned.
+async fn announce_with_fallback(
+    announce_url: &str,
+    info_hash: [u8; 20],
+    left: Option<u64>,
+    config: &mut TrackerConfig,
+    tracker_id: Option<&str>,
+) -> Result<TrackerResponse> {
+    let url = build_announce_url(announce_url, info_hash, left, config, tracker_id)?;
+    info!("Tracker URL: {}", url);
+    let result = match announce_with_retry(&url, config).await {
+        Ok(bytes) => parse_announce_response(&bytes),
+        Err(e) => Err(e),
+    };
+    let err = match result {
+        Err(e) if config.compact && rejects_compact(&e) => e,
+        result => return result,
     };
 
-    let url_params = serde_urlencoded::to_string(&request)?;
-    let url = format!(
-        "{}?{}&info_hash={}",
-        announce_url,
-        url_params,
-        urlencode(&info_hash)
-    );
-
-    info!("Tracker URL: {}", url);
+    warn!("Compact announce failed ({}), retrying with compact=0", err);
+    config.compact = false;
+    let url = build_announce_url(announce_url, info_hash, left, config, tracker_id)?;
+    // Sent once: the tracker has just answered, so this isn't a transient failure
+    let fallback = match announce_once(&url, config).await {
+        Ok(bytes) => parse_announce_response(&bytes),
+        Err(e) => Err(e.into()),
+    };
+    match fallback {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("Announce with compact=0 failed too: {}", e);
+            config.compact = true;
+            Err(err)
+        }
+    }
+}
 
-    let response = client.get(url).send().await?;
-    let response_bytes = response.bytes().await?;
+/// Whether a failed compact announce may work with `compact=0`: the tracker
+/// answered with a 4xx status, a `failure reason` mentioning compact, or a
+/// response that couldn't be parsed. Connection failures and server errors are
+/// left to the usual retries.
+fn rejects_compact(err: &anyhow::Error) -> bool {
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.status().is_some_and(|status| status.is_client_error());
+    }
+    if let Some(failure) = err.downcast_ref::<TrackerFailure>() {
+        return failure.reason.to_ascii_lowercase().contains("compact");
+    }
+    true
+}
 
-    let bvalue = Bencode::decode_bytes(&response_bytes)?;
+/// Decodes an announce response, failing if the tracker reported a `failure reason`.
+fn parse_announce_response(bytes: &[u8]) -> Result<TrackerResponse> {
+    let bvalue = Bencode::decode_bytes(bytes)?;
     info!("Response: {}", bvalue);
-    let peers = bvalue
-        .get_dict()?
-        .get("peers")
+    let dict = bvalue.get_dict()?;
+    if let Some(reason) = dict.get(b"failure reason".as_slice()) {
+        let retry_in = match dict.get(b"retry in".as_slice()) {
+            Some(BValue::Integer(minutes)) if *minutes >= 0 => Some(RetryIn::After(
+                Duration::from_secs((*minutes as u64).saturating_mul(60)),
+            )),
+            Some(BValue::String(never)) if never == b"never" => Some(RetryIn::Never),
+            _ => None,
+        };
+        return Err(TrackerFailure {
+            reason: String::from_utf8_lossy(reason.get_bytes()?).into_owned(),
+            retry_in,
+        }
+        .into());
+    }
+    let warning = match dict.get(b"warning message".as_slice()) {
+        Some(warning) => Some(String::from_utf8_lossy(warning.get_bytes()?).into_owned()),
+        None => None,
+    };
+    if let Some(warning) = &warning {
+        warn!("Tracker warning: {}", warning);
+    }
+    let peers = dict
+        .get(b"peers".as_slice())
         .ok_or(anyhow::anyhow!("Peers not found"))?;
-    let peers_bytes = peers.get_bytes()?;
+    let peers = match peers {
+        BValue::List(entries) => parse_dictionary_peers(entries)?,
+        peers => parse_compact_peers(peers.get_bytes()?)?,
+    };
+    // IPv6 peers aren't used yet, but a malformed list still means a corrupt response
+    if let Some(peers6) = dict.get(b"peers6".as_slice()) {
+        check_compact_len("peers6", peers6.get_bytes()?, 18)?;
+    }
 
+    let seconds = |key: &str| match dict.get(key.as_bytes()) {
+        Some(BValue::Integer(secs)) if *secs >= 0 => Some(Duration::from_secs(*secs as u64)),
+        _ => None,
+    };
+    let tracker_id = match dict.get(b"tracker id".as_slice()) {
+        Some(BValue::String(id)) => Some(String::from_utf8_lossy(id).into_owned()),
+        _ => None,
+    };
+    let count = |key: &str| match dict.get(key.as_bytes()) {
+        Some(BValue::Integer(n)) if *n >= 0 => Some(*n as u64),
+        _ => None,
+    };
+
+    Ok(TrackerResponse {
+        peers,
+        interval: seconds("interval"),
+        complete: count("complete"),
+        incomplete: count("incomplete"),
+        min_interval: seconds("min interval"),
+        tracker_id,
+        warning,
+    })
+}
+
+/// Reads a compact peer list: 4 bytes of IPv4 address and 2 of port per peer.
+fn parse_compact_peers(bytes: &[u8]) -> Result<Vec<Peer>> {
+    check_compact_len("peers", bytes, 6)?;
+    Ok(bytes
+        .chunks_exact(6)
+        .map(|chunk| Peer {
+            ip: Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]),
+            port: u16::from_be_bytes([chunk[4], chunk[5]]),
+        })
+        .collect())
+}
+
+/// Reads the dictionary form of a peer list sent for `compact=0`, one dictionary
+/// with `ip` and `port` per peer.
+///
+/// Peers whose `ip` isn't an IPv4 address, such as IPv6 addresses or hostnames,
+/// are skipped.
+fn parse_dictionary_peers(entries: &[BValue]) -> Result<Vec<Peer>> {
     let mut peers = Vec::new();
-    for chunk in peers_bytes.chunks_exact(6) {
-        let ip = Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]);
-        let port = u16::from_be_bytes([chunk[4], chunk[5]]);
-        peers.push(Peer { ip, port });
+    for entry in entries {
+        let entry = entry.get_dict()?;
+        let ip = entry
+            .get(b"ip".as_slice())
+            .ok_or_else(|| anyhow::anyhow!("Peer entry without an ip"))?
+            .get_bytes()?;
+        let port = match entry.get(b"port".as_slice()) {
+            Some(BValue::Integer(port)) => u16::try_from(*port)
+                .map_err(|_| anyhow::anyhow!("Peer port {} is out of range", port))?,
+            _ => return Err(anyhow::anyhow!("Peer entry without a port")),
+        };
+        match String::from_utf8_lossy(ip).parse() {
+            Ok(ip) => peers.push(Peer { ip, port }),
+            Err(_) => info!("Skipping peer {}", String::from_utf8_lossy(ip)),
+        }
     }
-
     Ok(peers)
 }
+
+/// Ensures a compact peer list holds a whole number of `entry_len`-byte entries.
+// `is_multiple_of` needs a newer toolchain than the pinned rust-1.77
+#[allow(clippy::manual_is_multiple_of)]
+fn check_compact_len(field: &str, bytes: &[u8], entry_len: usize) -> Result<()> {
+    if bytes.len() % entry_len != 0 {
+        return Err(anyhow::anyhow!(
+            "Malformed {} field: {} bytes is not a multiple of {}",
+            field,
+            bytes.len(),
+            entry_len
+        ));
+    }
+    Ok(())
+}
+
+/// Announce state for repeated announces to a single tracker.
+#[derive(Debug)]
+pub struct Tracker {
+    announce_url: String,
+    config: TrackerConfig,
+    /// Identifier the tracker asked us to send back with later announces
+    tracker_id: Option<String>,
+    /// How often the tracker wants to be announced to
+    interval: Option<Duration>,
+    /// Shortest time the tracker allows between announces
+    min_interval: Option<Duration>,
+    /// When we last announced
+    last_announce: Option<Instant>,
+    /// Announces in a row that failed
+    failures: u32,
+    /// When the tracker may be contacted again after a failure; `None` with
+    /// `failures > 0` means it asked never to be retried
+    retry_at: Option<Instant>,
+}
+
+impl Tracker {
+    /// Creates a tracker session for `announce_url`.
+    pub fn new(announce_url: impl Into<String>, config: TrackerConfig) -> Self {
+        Self {
+            announce_url: announce_url.into(),
+            config,
+            tracker_id: None,
+            interval: None,
+            min_interval: None,
+            last_announce: None,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    /// The tracker's announce URL.
+    pub fn announce_url(&self) -> &str {
+        &self.announce_url
+    }
+
+    /// Sets the session's byte counts sent with the following announces.
+    pub fn set_transferred(&mut self, uploaded: u64, downloaded: u64) {
+        self.config.uploaded = uploaded;
+        self.config.downloaded = downloaded;
+    }
+
+    /// Whether the tracker is skipped because of a recent failed announce.
+    pub fn is_backed_off(&self) -> bool {
+        match self.retry_at {
+            Some(retry_at) => Instant::now() < retry_at,
+            None => self.failures > 0,
+        }
+    }
+
+    /// Records a failed announce and works out how long to leave the tracker alone.
+    fn back_off(&mut self, err: &anyhow::Error) {
+        self.failures += 1;
+        let retry_in = err
+            .downcast_ref::<TrackerFailure>()
+            .and_then(|failure| failure.retry_in);
+        let delay = match retry_in {
+            Some(RetryIn::After(delay)) => delay,
+            Some(RetryIn::Never) => {
+                warn!("Tracker asked never to be retried");
+                self.retry_at = None;
+                return;
+            }
+            None => self
+                .config
+                .failure_backoff
+                .saturating_mul(2u32.saturating_pow(self.failures - 1))
+                .min(self.config.max_failure_backoff),
+        };
+        warn!("Backing off from tracker for {:?}", delay);
+        // A delay too far out to represent is as good as never retrying.
+        self.retry_at = Instant::now().checked_add(delay);
+    }
+
+    /// The tracker id from the most recent response that carried one.
+    pub fn tracker_id(&self) -> Option<&str> {
+        self.tracker_id.as_deref()
+    }
+
+    /// The `interval` the tracker last asked for, after which it expects the next
+    /// regular announce.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// The `min interval` the tracker last asked for.
+    pub fn min_interval(&self) -> Option<Duration> {
+        self.min_interval
+    }
+
+    /// Announces to the tracker and returns the peers it lists.
+    ///
+    /// Echoes back any `tracker id` from earlier responses and, if the tracker set a
+    /// `min interval`, waits until that long has passed since the previous announce.
+    ///
+    /// While the tracker is backed off after a failure, returns `TrackerBackedOff`
+    /// without contacting it.
+    #[instrument(name = "announce", skip_all, fields(tracker = %self.announce_url))]
+    pub async fn announce(&mut self, info_hash: [u8; 20], left: Option<u64>) -> Result<Vec<Peer>> {
+        if self.is_backed_off() {
+            return Err(TrackerBackedOff {
+                url: self.announce_url.clone(),
+                failures: self.failures,
+            }
+            .into());
+        }
+        if let (Some(last), Some(min_interval)) = (self.last_announce, self.min_interval) {
+            let allowed = last + min_interval;
+            if Instant::now() < allowed {
+                info!(
+                    "Waiting {:?} to respect the tracker's min interval",
+                    allowed - Instant::now()
+                );
+                tokio::time::sleep_until(allowed).await;
+            }
+        }
+
+        let response = announce_with_fallback(
+            &self.announce_url,
+            info_hash,
+            left,
+            &mut self.config,
+            self.tracker_id.as_deref(),
+        )
+        .await;
+        self.last_announce = Some(Instant::now());
+
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                self.back_off(&e);
+                return Err(e);
+            }
+        };
+        self.failures = 0;
+        self.retry_at = None;
+        if response.tracker_id.is_some() {
+            self.tracker_id = response.tracker_id;
+        }
+        if response.interval.is_some() {
+            self.interval = response.interval;
+        }
+        if response.min_interval.is_some() {
+            self.min_interval = response.min_interval;
+        }
+        Ok(response.peers)
+    }
+}
+
+/// Announces to every tracker of an announce-list that isn't backed off and merges
+/// the peers they return, dropping duplicates.
+///
+/// Trackers that fail are backed off, so the next round skips them until they may
+/// be retried. Fails only if no tracker answered.
+pub async fn announce_all(
+    trackers: &mut [Tracker],
+    info_hash: [u8; 20],
+    left: Option<u64>,
+) -> Result<Vec<Peer>> {
+    let mut peers: Vec<Peer> = Vec::new();
+    let mut answered = false;
+    let mut last_error = None;
+    for tracker in trackers.iter_mut() {
+        if tracker.is_backed_off() {
+            info!("Skipping backed-off tracker {}", tracker.announce_url());
+            continue;
+        }
+        match tracker.announce(info_hash, left).await {
+            Ok(returned) => {
+                answered = true;
+                for peer in returned {
+                    if !peers.contains(&peer) {
+                        peers.push(peer);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Announce to {} failed: {}", tracker.announce_url(), e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if answered {
+        return Ok(peers);
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Every tracker is backed off")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Spawns a one-shot HTTP server that answers a single request with `body`
+    /// after waiting `delay`, returning the announce URL to use.
+    async fn mock_tracker(body: Vec<u8>, delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await.unwrap();
+            tokio::time::sleep(delay).await;
+
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&body);
+            let _ = stream.write_all(&response).await;
+        });
+
+        format!("http://{}/announce", addr)
+    }
+
+    /// Spawns an HTTP server that answers the first `failures` requests with a 503
+    /// and every later one with `body`. Returns the announce URL and a request counter.
+    async fn flaky_tracker(failures: usize, body: Vec<u8>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+
+                let (status, body) = if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    ("503 Service Unavailable", Vec::new())
+                } else {
+                    ("200 OK", body.clone())
+                };
+                let mut response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .into_bytes();
+                response.extend_from_slice(&body);
+                let _ = stream.write_all(&response).await;
+            }
+        });
+
+        (format!("http://{}/announce", addr), hits)
+    }
+
+    /// Spawns an HTTP server that answers successive requests with `bodies` in order,
+    /// recording each request. Returns the announce URL and the raw requests.
+    async fn recording_tracker(
+        bodies: Vec<Vec<u8>>,
+    ) -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        tokio::spawn(async move {
+            for body in bodies {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                recorded.lock().unwrap().push(request.into_owned());
+
+                let mut response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .into_bytes();
+                response.extend_from_slice(&body);
+                let _ = stream.write_all(&response).await;
+            }
+        });
+
+        (format!("http://{}/announce", addr), requests)
+    }
+
+    fn fast_retry_config() -> TrackerConfig {
+        TrackerConfig {
+            retry_base_delay: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_announce_url_includes_numwant_by_default() {
+        let url = build_announce_url(
+            "http://tracker.example/announce",
+            [0u8; 20],
+            Some(100),
+            &TrackerConfig::default(),
+            None,
+        )
+        .unwrap();
+
+        assert!(url.contains("numwant=50"), "unexpected url: {}", url);
+        assert!(!url.contains("key="), "unexpected url: {}", url);
+    }
+
+    #[test]
+    fn test_announce_url_starts_query_without_existing_one() {
+        let url = build_announce_url(
+            "http://tracker.example/announce",
+            [0u8; 20],
+            Some(100),
+            &TrackerConfig::default(),
+            None,
+        )
+        .unwrap();
+
+        assert!(
+            url.starts_with("http://tracker.example/announce?peer_id="),
+            "unexpected url: {}",
+            url
+        );
+        assert_eq!(url.matches('?').count(), 1, "unexpected url: {}", url);
+    }
+
+    #[test]
+    fn test_announce_url_keeps_existing_query() {
+        let url = |announce_url: &str| {
+            build_announce_url(
+                announce_url,
+                [0u8; 20],
+                Some(100),
+                &TrackerConfig::default(),
+                None,
+            )
+            .unwrap()
+        };
+
+        let passkey = url("http://tracker.example/announce.php?passkey=abc123");
+        assert!(
+            passkey.starts_with("http://tracker.example/announce.php?passkey=abc123&peer_id="),
+            "unexpected url: {}",
+            passkey
+        );
+        assert_eq!(
+            passkey.matches('?').count(),
+            1,
+            "unexpected url: {}",
+            passkey
+        );
+
+        // A trailing separator is reused rather than doubled
+        for announce_url in [
+            "http://tracker.example/announce?",
+            "http://tracker.example/announce?passkey=abc123&",
+        ] {
+            let url = url(announce_url);
+            assert!(
+                url.starts_with(&format!("{}peer_id=", announce_url)),
+                "unexpected url: {}",
+                url
+            );
+        }
+
+        let fragment = url("http://tracker.example/announce?passkey=abc123#top");
+        assert!(fragment.ends_with("#top"), "unexpected url: {}", fragment);
+        assert!(
+            fragment.contains("passkey=abc123&peer_id="),
+            "unexpected url: {}",
+            fragment
+        );
+    }
+
+    #[test]
+    fn test_announce_url_reports_transferred_bytes() {
+        let config = TrackerConfig {
+            uploaded: 1234,
+            downloaded: 5678,
+            ..Default::default()
+        };
+        let url = build_announce_url(
+            "http://tracker.example/announce",
+            [0u8; 20],
+            Some(100),
+            &config,
+            None,
+        )
+        .unwrap();
+
+        assert!(
+            url.contains("uploaded=1234&downloaded=5678"),
+            "unexpected url: {}",
+            url
+        );
+    }
+
+    #[test]
+    fn test_announce_url_includes_event_when_set() {
+        let config = TrackerConfig {
+            event: Some(AnnounceEvent::Stopped),
+            ..Default::default()
+        };
+        let url = build_announce_url(
+            "http://tracker.example/announce",
+            [0u8; 20],
+            Some(100),
+            &config,
+            None,
+        )
+        .unwrap();
+
+        assert!(url.contains("event=stopped"), "unexpected url: {}", url);
+        assert!(
+            url.contains("uploaded=0&downloaded=0"),
+            "unexpected url: {}",
+            url
+        );
+
+        let config = TrackerConfig {
+            event: Some(AnnounceEvent::Paused),
+            ..Default::default()
+        };
+        let url = build_announce_url(
+            "http://tracker.example/announce",
+            [0u8; 20],
+            Some(100),
+            &config,
+            None,
+        )
+        .unwrap();
+        assert!(url.contains("event=paused"), "unexpected url: {}", url);
+    }
+
+    #[test]
+    fn test_announce_url_includes_key_when_set() {
+        let config = TrackerConfig {
+            numwant: None,
+            key: Some("a1b2c3".into()),
+            ..Default::default()
+        };
+        let url = build_announce_url(
+            "http://tracker.example/announce",
+            [0u8; 20],
+            Some(100),
+            &config,
+            None,
+        )
+        .unwrap();
+
+        assert!(url.contains("key=a1b2c3"), "unexpected url: {}", url);
+        assert!(!url.contains("numwant="), "unexpected url: {}", url);
+    }
+
+    #[test]
+    fn test_announce_url_includes_ip_only_when_set() {
+        let url = |announce_ip: Option<&str>| {
+            let config = TrackerConfig {
+                announce_ip: announce_ip.map(|ip| ip.parse().unwrap()),
+                ..Default::default()
+            };
+            build_announce_url(
+                "http://tracker.example/announce",
+                [0u8; 20],
+                None,
+                &config,
+                None,
+            )
+            .unwrap()
+        };
+
+        assert!(!url(None).contains("ip="), "unexpected url: {}", url(None));
+        let v4 = url(Some("203.0.113.7"));
+        assert!(v4.contains("&ip=203.0.113.7"), "unexpected url: {}", v4);
+        let v6 = url(Some("2001:db8::1"));
+        assert!(
+            v6.contains("&ip=2001%3Adb8%3A%3A1"),
+            "unexpected url: {}",
+            v6
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_parses_compact_response() {
+        let mut body = b"d8:intervali1800e5:peers12:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1, 10, 0, 0, 2, 0x1a, 0xe2]);
+        body.push(b'e');
+        let url = mock_tracker(body, Duration::ZERO).await;
+
+        let peers = get_peers(&url, [0u8; 20], Some(100), None).await.unwrap();
+
+        let peers: Vec<String> = peers.iter().map(|p| p.to_string()).collect();
+        assert_eq!(peers, vec!["127.0.0.1:6881", "10.0.0.2:6882"]);
+    }
+
+    #[tokio::test]
+    async fn test_announce_parses_swarm_counts() {
+        let mut body = b"d8:completei12e10:incompletei3e8:intervali1800e5:peers6:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1]);
+        body.push(b'e');
+        let url = mock_tracker(body, Duration::ZERO).await;
+
+        let response = announce(&url, [0u8; 20], Some(100), None).await.unwrap();
+
+        assert_eq!(response.complete, Some(12));
+        assert_eq!(response.incomplete, Some(3));
+        assert_eq!(response.peers.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_full_announce_response() {
+        let mut body =
+            b"d8:completei5e10:incompletei2e8:intervali1800e12:min intervali60e5:peers6:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1]);
+        body.extend_from_slice(b"10:tracker id3:abc15:warning message9:slow downe");
+
+        let response = parse_announce_response(&body).unwrap();
+
+        assert_eq!(
+            response,
+            TrackerResponse {
+                peers: vec![Peer {
+                    ip: Ipv4Addr::LOCALHOST,
+                    port: 6881,
+                }],
+                interval: Some(Duration::from_secs(1800)),
+                complete: Some(5),
+                incomplete: Some(2),
+                min_interval: Some(Duration::from_secs(60)),
+                tracker_id: Some("abc".to_string()),
+                warning: Some("slow down".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_announce_response_failure_is_an_error() {
+        let body = b"d14:failure reason7:invalid8:retry ini5ee";
+
+        let err = parse_announce_response(body).unwrap_err();
+
+        let failure = err.downcast_ref::<TrackerFailure>().unwrap();
+        assert_eq!(failure.reason, "invalid");
+        assert_eq!(
+            failure.retry_in,
+            Some(RetryIn::After(Duration::from_secs(300)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announce_without_swarm_counts() {
+        let url = mock_tracker(b"d8:intervali1800e5:peers0:e".to_vec(), Duration::ZERO).await;
+
+        let response = announce(&url, [0u8; 20], Some(100), None).await.unwrap();
+
+        assert_eq!(response.complete, None);
+        assert_eq!(response.incomplete, None);
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_rejects_truncated_compact_peers() {
+        let mut body = b"d8:intervali1800e5:peers8:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1, 10, 0]);
+        body.push(b'e');
+        let url = mock_tracker(body, Duration::ZERO).await;
+
+        let err = get_peers(&url, [0u8; 20], Some(100), None)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("not a multiple of 6"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_rejects_truncated_compact_peers6() {
+        let mut body = b"d8:intervali1800e5:peers0:6:peers610:".to_vec();
+        body.extend_from_slice(&[0u8; 10]);
+        body.push(b'e');
+        let url = mock_tracker(body, Duration::ZERO).await;
+
+        let err = get_peers(&url, [0u8; 20], Some(100), None)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("not a multiple of 18"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_surfaces_failure_reason() {
+        let body = b"d14:failure reason22:torrent not registerede".to_vec();
+        let url = mock_tracker(body, Duration::ZERO).await;
+
+        let err = get_peers(&url, [0u8; 20], Some(100), None)
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string().contains("torrent not registered"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_with_warning_still_returns_peers() {
+        let mut body = b"d5:peers6:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1]);
+        body.extend_from_slice(b"15:warning message9:slow downe");
+        let url = mock_tracker(body, Duration::ZERO).await;
+
+        let peers = get_peers(&url, [0u8; 20], Some(100), None).await.unwrap();
+
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].to_string(), "127.0.0.1:6881");
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_times_out() {
+        let url = mock_tracker(b"d5:peers0:e".to_vec(), Duration::from_secs(5)).await;
+        let config = TrackerConfig {
+            request_timeout: Duration::from_millis(100),
+            max_attempts: 1,
+            ..Default::default()
+        };
+
+        let started = std::time::Instant::now();
+        let result = get_peers(&url, [0u8; 20], Some(100), Some(config)).await;
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_retries_server_errors() {
+        let mut body = b"d5:peers6:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1]);
+        body.push(b'e');
+        let (url, hits) = flaky_tracker(2, body).await;
+
+        let peers = get_peers(&url, [0u8; 20], Some(100), Some(fast_retry_config()))
+            .await
+            .unwrap();
+
+        assert_eq!(peers.len(), 1);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_gives_up_after_max_attempts() {
+        let (url, hits) = flaky_tracker(usize::MAX, Vec::new()).await;
+
+        let result = get_peers(&url, [0u8; 20], Some(100), Some(fast_retry_config())).await;
+
+        assert!(result.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_retries_many_attempts_without_overflow() {
+        let (url, hits) = flaky_tracker(usize::MAX, Vec::new()).await;
+        let config = TrackerConfig {
+            max_attempts: 40,
+            retry_base_delay: Duration::from_nanos(1),
+            max_failure_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        assert_eq!(retry_delay(&config, 39), Duration::from_millis(1));
+        let huge = TrackerConfig {
+            retry_base_delay: Duration::MAX,
+            max_failure_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        assert_eq!(retry_delay(&huge, u32::MAX), Duration::from_millis(1));
+
+        let result = get_peers(&url, [0u8; 20], Some(100), Some(config)).await;
+
+        assert!(result.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 40);
+    }
+
+    #[tokio::test]
+    async fn test_get_peers_does_not_retry_failure_reason() {
+        let body = b"d14:failure reason22:torrent not registerede".to_vec();
+        let (url, hits) = flaky_tracker(0, body).await;
+
+        let result = get_peers(&url, [0u8; 20], Some(100), Some(fast_retry_config())).await;
+
+        assert!(result.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tracker_echoes_tracker_id_and_respects_min_interval() {
+        let first = b"d12:min intervali1e5:peers0:10:tracker id3:abce".to_vec();
+        let second = b"d5:peers0:e".to_vec();
+        let (url, requests) = recording_tracker(vec![first, second]).await;
+        let mut tracker = Tracker::new(url, TrackerConfig::default());
+
+        tracker.announce([0u8; 20], Some(100)).await.unwrap();
+        assert_eq!(tracker.tracker_id(), Some("abc"));
+        assert_eq!(tracker.min_interval(), Some(Duration::from_secs(1)));
+
+        let started = std::time::Instant::now();
+        tracker.announce([0u8; 20], Some(100)).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        // The id is kept when a later response doesn't repeat it
+        assert_eq!(tracker.tracker_id(), Some("abc"));
+
+        let requests = requests.lock().unwrap();
+        assert!(!requests[0].contains("trackerid="), "{}", requests[0]);
+        assert!(requests[1].contains("trackerid=abc"), "{}", requests[1]);
+    }
+
+    #[tokio::test]
+    async fn test_announce_all_skips_failed_tracker_until_backoff_elapses() {
+        let (failing_url, failing_hits) =
+            flaky_tracker(0, b"d14:failure reason8:overloade".to_vec()).await;
+        let mut body = b"d5:peers6:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1]);
+        body.push(b'e');
+        let (working_url, working_hits) = flaky_tracker(0, body).await;
+        let config = || TrackerConfig {
+            failure_backoff: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut trackers = vec![
+            Tracker::new(failing_url, config()),
+            Tracker::new(working_url, config()),
+        ];
+
+        let peers = announce_all(&mut trackers, [0u8; 20], Some(100))
+            .await
+            .unwrap();
+        assert_eq!(peers.len(), 1);
+        assert!(trackers[0].is_backed_off());
+        assert!(!trackers[1].is_backed_off());
+
+        // The next round leaves the failed tracker alone
+        announce_all(&mut trackers, [0u8; 20], Some(100))
+            .await
+            .unwrap();
+        assert_eq!(failing_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(working_hits.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        announce_all(&mut trackers, [0u8; 20], Some(100))
+            .await
+            .unwrap();
+        assert_eq!(failing_hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tracker_honours_retry_in() {
+        let body = b"d14:failure reason4:busy8:retry ini5ee".to_vec();
+        let err = parse_announce_response(&body).unwrap_err();
+        let failure = err.downcast_ref::<TrackerFailure>().unwrap();
+        assert_eq!(failure.reason, "busy");
+        assert_eq!(
+            failure.retry_in,
+            Some(RetryIn::After(Duration::from_secs(300)))
+        );
+
+        let body = b"d14:failure reason6:banned8:retry in5:nevere".to_vec();
+        let (url, hits) = flaky_tracker(0, body).await;
+        let config = TrackerConfig {
+            failure_backoff: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut tracker = Tracker::new(url, config);
+        tracker.announce([0u8; 20], Some(100)).await.unwrap_err();
+        let err = tracker.announce([0u8; 20], Some(100)).await.unwrap_err();
+        assert!(err.downcast_ref::<TrackerBackedOff>().is_some(), "{}", err);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_huge_retry_in_does_not_overflow() {
+        let body = format!("d14:failure reason4:busy8:retry ini{}ee", i64::MAX).into_bytes();
+        let err = parse_announce_response(&body).unwrap_err();
+        let failure = err.downcast_ref::<TrackerFailure>().unwrap();
+        assert_eq!(
+            failure.retry_in,
+            Some(RetryIn::After(Duration::from_secs(u64::MAX)))
+        );
+
+        let (url, hits) = flaky_tracker(0, body).await;
+        let mut tracker = Tracker::new(url, TrackerConfig::default());
+        tracker.announce([0u8; 20], Some(100)).await.unwrap_err();
+        assert!(tracker.is_backed_off());
+        let err = tracker.announce([0u8; 20], Some(100)).await.unwrap_err();
+        assert!(err.downcast_ref::<TrackerBackedOff>().is_some(), "{}", err);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// Wraps `data` in a gzip stream holding one stored (uncompressed) deflate block.
+    fn gzip_stored(data: &[u8]) -> Vec<u8> {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 1];
+        gzip.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        gzip.extend_from_slice(&(!(data.len() as u16)).to_le_bytes());
+        gzip.extend_from_slice(data);
+        gzip.extend_from_slice(&(!crc).to_le_bytes());
+        gzip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        gzip
+    }
+
+    #[tokio::test]
+    async fn test_announce_asks_for_and_decodes_gzip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let body = gzip_stored(b"d5:peers6:\x7f\x00\x00\x01\x1a\xe1e");
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&body);
+            let _ = stream.write_all(&response).await;
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+
+        let peers = get_peers(&url, [0u8; 20], Some(100), None).await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].port, 6881);
+        let request = server.await.unwrap();
+        assert!(
+            request
+                .lines()
+                .any(|line| line.starts_with("accept-encoding: ") && line.contains("gzip")),
+            "{}",
+            request
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announce_sends_configured_user_agent() {
+        let (url, requests) = recording_tracker(vec![b"d5:peers0:e".to_vec(); 2]).await;
+
+        get_peers(&url, [0u8; 20], Some(100), None).await.unwrap();
+        let config = TrackerConfig {
+            user_agent: "test-agent/1.0".to_string(),
+            ..Default::default()
+        };
+        get_peers(&url, [0u8; 20], Some(100), Some(config))
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        let user_agent = |request: &str| {
+            request
+                .lines()
+                .find_map(|line| line.strip_prefix("user-agent: "))
+                .map(str::to_string)
+        };
+        assert_eq!(
+            user_agent(&requests[0]).as_deref(),
+            Some(DEFAULT_USER_AGENT)
+        );
+        assert_eq!(user_agent(&requests[1]).as_deref(), Some("test-agent/1.0"));
+    }
+
+    #[tokio::test]
+    async fn test_tracker_falls_back_to_non_compact_peer_list() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let (status, body): (_, &[u8]) = if request.contains("compact=1") {
+                    ("400 Bad Request", b"")
+                } else {
+                    (
+                        "200 OK",
+                        b"d5:peersld2:ip9:127.0.0.14:porti6881eed2:ip11:example.com4:porti6882eeee",
+                    )
+                };
+                recorded.lock().unwrap().push(request);
+                let mut response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .into_bytes();
+                response.extend_from_slice(body);
+                let _ = stream.write_all(&response).await;
+            }
+        });
+
+        let mut tracker = Tracker::new(&url, TrackerConfig::default());
+        let peers = tracker.announce([0u8; 20], Some(100)).await.unwrap();
+        // The hostname entry isn't an IPv4 peer and is skipped
+        let peers: Vec<String> = peers.iter().map(|p| p.to_string()).collect();
+        assert_eq!(peers, vec!["127.0.0.1:6881"]);
+
+        // Later announces go straight to compact=0
+        tracker.announce([0u8; 20], Some(100)).await.unwrap();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].contains("compact=1"));
+        assert!(requests[1].contains("compact=0"));
+        assert!(requests[2].contains("compact=0"));
+    }
+}
@@ -0,0 +1,324 @@
+//! HTTP web seeds (BEP 19).
+//!
+//! A torrent's `url-list` (or a magnet link's `ws` parameters) names HTTP servers
+//! hosting the torrent's files. Pieces can be fetched from them with `Range`
+//! requests when the swarm is too slow or empty:
+//! - Single-file torrents: the URL is the file itself, or a directory holding a
+//!   file named after the torrent when it ends in `/`
+//! - Multi-file torrents: each file lives at `<url>/<name>/<path...>`
+//!
+//! A piece that spans several files is fetched with one request per file and
+//! only accepted if the reassembled data matches the piece hash.
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::time::Duration;
+
+use super::metainfo::TorrentInfo;
+use crate::utils::percent_encode;
+
+/// HTTP client shared by all web seeds so connections to a server are pooled.
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+/// Maximum time allowed for a single range request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An HTTP server hosting the torrent's files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebSeed {
+    /// Base URL from the torrent's `url-list`
+    pub url: String,
+}
+
+impl WebSeed {
+    /// Creates a web seed for the given base URL.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+
+    /// Downloads piece `piece_index` of `info` and checks it against the piece hash.
+    pub async fn fetch_piece(&self, info: &TorrentInfo, piece_index: usize) -> Result<Vec<u8>> {
+        let piece_size = info
+            .piece_size(piece_index)
+            .ok_or_else(|| anyhow::anyhow!("Piece {} is out of range", piece_index))?;
+        let start = piece_index * info.piece_length;
+        let end = start + piece_size;
+
+        let mut data = Vec::with_capacity(piece_size);
+        for (url, range_start, range_end) in self.segments(info, start, end) {
+            let block = Self::fetch_range(&url, range_start, range_end).await?;
+            data.extend_from_slice(&block);
+        }
+
+        if !info.verify_piece(piece_index, &data) {
+            return Err(anyhow::anyhow!(
+                "Piece {} from web seed {} failed hash verification",
+                piece_index,
+                self.url
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Splits the torrent byte range `start..end` into `(url, start, end)` ranges
+    /// within the files that hold it.
+    fn segments(
+        &self,
+        info: &TorrentInfo,
+        start: usize,
+        end: usize,
+    ) -> Vec<(String, usize, usize)> {
+        let Some(files) = info.multi_file() else {
+            let url = if self.url.ends_with('/') {
+                format!("{}{}", self.url, percent_encode(info.name.as_bytes()))
+            } else {
+                self.url.clone()
+            };
+            return vec![(url, start, end)];
+        };
+
+        let base = self.url.trim_end_matches('/');
+        let mut segments = Vec::new();
+        let mut offset = 0;
+        for file in files {
+            let file_end = offset + file.length;
+            // An empty file holds none of the range, and can't be asked for zero bytes
+            if file.length > 0 && file_end > start && offset < end {
+                let path: Vec<_> = file
+                    .path
+                    .iter()
+                    .map(|p| percent_encode(p.as_bytes()))
+                    .collect();
+                let url = format!(
+                    "{}/{}/{}",
+                    base,
+                    percent_encode(info.name.as_bytes()),
+                    path.join("/")
+                );
+                segments.push((url, start.max(offset) - offset, end.min(file_end) - offset));
+            }
+            offset = file_end;
+        }
+        segments
+    }
+
+    /// Requests bytes `start..end` of the file at `url`.
+    async fn fetch_range(url: &str, start: usize, end: usize) -> Result<Vec<u8>> {
+        let response = HTTP_CLIENT
+            .get(url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", start, end - 1),
+            )
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        // A server that ignores the range sends the whole file with 200 OK
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(anyhow::anyhow!(
+                "Web seed {} does not support range requests (status {})",
+                url,
+                response.status()
+            ));
+        }
+        let bytes = response.bytes().await?;
+        if bytes.len() != end - start {
+            return Err(anyhow::anyhow!(
+                "Web seed {} returned {} bytes, expected {}",
+                url,
+                bytes.len(),
+                end - start
+            ));
+        }
+        Ok(bytes.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::metainfo::FileEntry;
+    use sha1::{Digest, Sha1};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Starts an HTTP server that answers `Range` requests for `data` and returns
+    /// its base URL.
+    async fn spawn_range_server(data: Vec<u8>) -> String {
+        let base = spawn_files_server(vec![("/file.bin", data)]).await;
+        format!("{}/file.bin", base)
+    }
+
+    /// Starts an HTTP server that answers `Range` requests for each `(path, data)`
+    /// in `files` and returns its base URL, without a trailing slash.
+    async fn spawn_files_server(files: Vec<(&'static str, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let Some((_, data)) = files.iter().find(|(p, _)| p.to_lowercase() == path) else {
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                        .await;
+                    continue;
+                };
+                let range = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|r| r.trim().split_once('-'))
+                    .map(|(a, b)| (a.parse::<usize>().unwrap(), b.parse::<usize>().unwrap()));
+                let Some((start, last)) = range else {
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                        .await;
+                    continue;
+                };
+                let body = &data[start..=last];
+                let mut response = format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                    body.len(),
+                    start,
+                    last,
+                    data.len()
+                )
+                .into_bytes();
+                response.extend_from_slice(body);
+                let _ = stream.write_all(&response).await;
+            }
+        });
+        url
+    }
+
+    fn make_info(data: &[u8], piece_length: usize) -> TorrentInfo {
+        TorrentInfo {
+            name: "file.bin".to_string(),
+            length: data.len(),
+            piece_length,
+            pieces: data
+                .chunks(piece_length)
+                .flat_map(|chunk| Sha1::digest(chunk).to_vec())
+                .collect::<Vec<_>>()
+                .into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_piece_uses_byte_range() {
+        let data: Vec<u8> = (0..40_000).map(|i| (i % 251) as u8).collect();
+        let info = make_info(&data, 16384);
+        let seed = WebSeed::new(spawn_range_server(data.clone()).await);
+
+        assert_eq!(
+            seed.fetch_piece(&info, 1).await.unwrap(),
+            &data[16384..32768]
+        );
+        // The last piece is shorter than the piece length
+        assert_eq!(seed.fetch_piece(&info, 2).await.unwrap(), &data[32768..]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_piece_rejects_corrupt_data() {
+        let data: Vec<u8> = (0..20_000).map(|i| (i % 251) as u8).collect();
+        let info = make_info(&data, 16384);
+        let mut corrupt = data.clone();
+        corrupt[0] ^= 0xff;
+        let seed = WebSeed::new(spawn_range_server(corrupt).await);
+
+        assert!(seed.fetch_piece(&info, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_manager_falls_back_to_web_seed() {
+        let data: Vec<u8> = (0..50_000).map(|i| (i % 251) as u8).collect();
+        let info = make_info(&data, 16384);
+        let url = spawn_range_server(data.clone()).await;
+
+        // No peers at all, so the peer throughput is zero and the seed serves everything
+        let manager = crate::torrent::download::DownloadManager::new(
+            info,
+            Vec::new(),
+            Default::default(),
+            Default::default(),
+        )
+        .with_web_seeds(&[url]);
+
+        assert_eq!(manager.download().await.unwrap(), data);
+        assert_eq!(manager.stats().downloaded, data.len() as u64);
+    }
+
+    #[test]
+    fn test_multi_file_segments_span_files() {
+        let info = TorrentInfo {
+            name: "my dir".to_string(),
+            length: 30,
+            piece_length: 16,
+            files: Some(vec![
+                FileEntry {
+                    length: 10,
+                    path: vec!["a.txt".to_string()],
+                    md5sum: None,
+                },
+                FileEntry {
+                    length: 20,
+                    path: vec!["sub".to_string(), "b.txt".to_string()],
+                    md5sum: None,
+                },
+            ]),
+            ..Default::default()
+        };
+        let seed = WebSeed::new("http://host/seed/");
+
+        assert_eq!(
+            seed.segments(&info, 0, 16),
+            vec![
+                ("http://host/seed/my%20dir/a.txt".to_string(), 0, 10),
+                ("http://host/seed/my%20dir/sub/b.txt".to_string(), 0, 6),
+            ]
+        );
+        assert_eq!(
+            seed.segments(&info, 16, 30),
+            vec![("http://host/seed/my%20dir/sub/b.txt".to_string(), 6, 20)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_piece_skips_empty_file_inside_piece() {
+        let data: Vec<u8> = (0..30).collect();
+        let entry = |length, name: &str| FileEntry {
+            length,
+            path: vec![name.to_string()],
+            md5sum: None,
+        };
+        let info = TorrentInfo {
+            name: "dir".to_string(),
+            files: Some(vec![
+                entry(10, "a.bin"),
+                entry(0, "empty.bin"),
+                entry(20, "b.bin"),
+            ]),
+            ..make_info(&data, 16)
+        };
+        let base = spawn_files_server(vec![
+            ("/dir/a.bin", data[..10].to_vec()),
+            ("/dir/b.bin", data[10..].to_vec()),
+        ])
+        .await;
+        let seed = WebSeed::new(format!("{}/", base));
+
+        assert_eq!(
+            seed.segments(&info, 0, 16),
+            vec![
+                (format!("{}/dir/a.bin", base), 0, 10),
+                (format!("{}/dir/b.bin", base), 0, 6),
+            ]
+        );
+        assert_eq!(seed.fetch_piece(&info, 0).await.unwrap(), &data[..16]);
+    }
+}
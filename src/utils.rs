@@ -1,3 +1,9 @@
+use std::io::Read;
+
+use anyhow::Result;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
 use rand::Rng;
 
 use crate::torrent::peer::PeerId;
@@ -22,3 +28,151 @@ pub fn serialize_peer_id(peer_id: &[u8]) -> String {
 pub fn peer_id_to_string(peer_id: &[u8]) -> String {
     peer_id.iter().map(|&b| format!("{:02x}", b)).collect()
 }
+
+/// Percent-encodes `bytes` for use in a URL, leaving only unreserved characters
+/// (RFC 3986: letters, digits, `-`, `_`, `.` and `~`) as they are, so the result is
+/// safe as a query parameter value or a single path segment.
+pub fn percent_encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Reads raw torrent bytes from `path`, or from stdin when `path` is `-`.
+pub fn read_torrent_source(path: &str) -> Result<Vec<u8>> {
+    read_torrent_from(path, std::io::stdin().lock())
+}
+
+fn read_torrent_from(path: &str, mut stdin: impl Read) -> Result<Vec<u8>> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        stdin.read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    Ok(std::fs::read(path)?)
+}
+
+/// Parses a comma-separated list of piece indices and inclusive ranges, such as
+/// `0,1,5-10`, into sorted, de-duplicated indices.
+///
+/// Every index must be below `total_pieces`; bounds are checked before a range is
+/// expanded, so a huge range fails instead of exhausting memory.
+pub fn parse_piece_ranges(spec: &str, total_pieces: usize) -> Result<Vec<usize>> {
+    let mut pieces = std::collections::BTreeSet::new();
+    for part in spec.split(',').map(str::trim) {
+        let parse = |s: &str| {
+            let index = s
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("Invalid piece index {:?}", s))?;
+            if index >= total_pieces {
+                return Err(anyhow::anyhow!(
+                    "Piece {} is out of range; the torrent has {} pieces",
+                    index,
+                    total_pieces
+                ));
+            }
+            Ok(index)
+        };
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(anyhow::anyhow!("Invalid piece range {:?}", part));
+                }
+                pieces.extend(start..=end);
+            }
+            None => {
+                pieces.insert(parse(part)?);
+            }
+        }
+    }
+    Ok(pieces.into_iter().collect())
+}
+
+/// Decodes standard base64 (RFC 4648, `+` and `/`), with or without `=` padding.
+/// ASCII whitespace anywhere in the input is ignored, so text wrapped by the
+/// `base64` tool or copied from logs decodes as is.
+pub fn decode_base64(input: &str) -> Result<Vec<u8>> {
+    const ENGINE: GeneralPurpose = GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    );
+    let input: String = input.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    Ok(ENGINE.decode(input)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::metainfo::TorrentMetainfo;
+
+    #[test]
+    fn test_read_torrent_from_stdin() {
+        let torrent = std::fs::read("sample.torrent").unwrap();
+
+        let bytes = read_torrent_from("-", std::io::Cursor::new(torrent.clone())).unwrap();
+
+        assert_eq!(bytes, torrent);
+        assert!(TorrentMetainfo::from_bytes(&bytes).unwrap().info.is_some());
+    }
+
+    #[test]
+    fn test_read_torrent_from_path_ignores_stdin() {
+        let bytes = read_torrent_from("sample.torrent", std::io::empty()).unwrap();
+        assert_eq!(bytes, std::fs::read("sample.torrent").unwrap());
+    }
+
+    #[test]
+    fn test_percent_encode_keeps_only_unreserved() {
+        assert_eq!(percent_encode(b"a-Z_0.~"), "a-Z_0.~");
+        assert_eq!(
+            percent_encode(b"http://t/a?b=c&d"),
+            "http%3A%2F%2Ft%2Fa%3Fb%3Dc%26d"
+        );
+        assert_eq!(percent_encode(&[0x00, 0x12, 0xff]), "%00%12%FF");
+    }
+
+    #[test]
+    fn test_parse_piece_ranges() {
+        assert_eq!(
+            parse_piece_ranges("0,1,5-7, 3", 10).unwrap(),
+            vec![0, 1, 3, 5, 6, 7]
+        );
+        assert_eq!(parse_piece_ranges("2,1-2", 10).unwrap(), vec![1, 2]);
+        for invalid in ["", "a", "1,", "5-2", "1-", "10", "8-10", "0-99999999999"] {
+            assert!(parse_piece_ranges(invalid, 10).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64("aTQyZQ==").unwrap(), b"i42e");
+        assert_eq!(decode_base64("aTQyZQ").unwrap(), b"i42e");
+        assert_eq!(
+            decode_base64(" ZDM6Zm9vMzpiYXJl\n").unwrap(),
+            b"d3:foo3:bare"
+        );
+        assert_eq!(decode_base64("+/8=").unwrap(), [0xfb, 0xff]);
+        assert!(decode_base64("aTQy*Q==").is_err());
+        assert!(decode_base64("aTQyZ").is_err());
+    }
+
+    #[test]
+    fn test_decode_base64_wrapped_lines() {
+        // `base64` wraps its output at 76 columns
+        let data: Vec<u8> = (0..100).collect();
+        let encoded = concat!(
+            "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8gISIjJCUmJygpKissLS4vMDEyMzQ1Njc4\n",
+            "OTo7PD0+P0BBQkNERUZHSElKS0xNTk9QUVJTVFVWV1hZWltcXV5fYGFiYw==\n",
+        );
+        assert_eq!(decode_base64(encoded).unwrap(), data);
+        assert_eq!(decode_base64("aTQy\r\n ZQ==").unwrap(), b"i42e");
+    }
+}